@@ -0,0 +1,217 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use ringbuf::traits::Consumer;
+use ringbuf::{HeapCons, HeapRb};
+
+use super::fft::Spectrum;
+
+/// Output and mic sample pairs from the output callback.
+/// Holds well over one analysis interval at the slowest frame rate.
+pub(super) type TapRing = Arc<HeapRb<[f32; 2]>>;
+const TAP_RING_SIZE: usize = 65536;
+
+/// Magnitude reported for empty bins; keeps the values finite for the frontend
+const FLOOR_DB: f32 = -120.0;
+
+/// Spectrum feed settings
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpectrumSettings {
+    pub enabled: bool,
+    /// Frames sent to the frontend per second (5 - 60)
+    pub rate_hz: f32,
+    /// FFT length in samples (rounded to a power of two)
+    pub fft_size: usize,
+}
+
+impl Default for SpectrumSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rate_hz: 20.0,
+            fft_size: 2048,
+        }
+    }
+}
+
+/// One spectrum of the mixed output and of the mic
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct SpectrumFrame {
+    pub sample_rate: u32,
+    /// Width of each bin; bin `k` is centred on `k * bin_hz`
+    pub bin_hz: f32,
+    /// Power per bin in dB relative to a full-scale sine, DC to Nyquist
+    pub output_db: Vec<f32>,
+    pub mic_db: Vec<f32>,
+}
+
+/// Analysis tap shared between the output callback and the analyzer thread
+#[derive(Clone)]
+pub(super) struct SpectrumControls {
+    /// Whether the output callback feeds the tap
+    pub enabled: Arc<AtomicBool>,
+    pub ring: TapRing,
+}
+
+impl SpectrumControls {
+    pub fn new() -> Self {
+        Self {
+            enabled: Arc::new(AtomicBool::new(false)),
+            ring: Arc::new(HeapRb::new(TAP_RING_SIZE)),
+        }
+    }
+}
+
+/// Rolling history of the tapped samples and the FFT run over it
+struct Analyzer {
+    consumer: HeapCons<[f32; 2]>,
+    spectrum: Spectrum,
+    /// Newest `fft_size` samples of each bus, oldest first once linearised
+    output: Vec<f32>,
+    mic: Vec<f32>,
+    position: usize,
+    frame: Vec<f32>,
+    power: Vec<f32>,
+}
+
+impl Analyzer {
+    fn new(consumer: HeapCons<[f32; 2]>, fft_size: usize) -> Self {
+        let mut analyzer = Self {
+            consumer,
+            spectrum: Spectrum::new(2),
+            output: Vec::new(),
+            mic: Vec::new(),
+            position: 0,
+            frame: Vec::new(),
+            power: Vec::new(),
+        };
+        analyzer.resize(fft_size);
+        analyzer
+    }
+
+    fn resize(&mut self, fft_size: usize) {
+        let size = fft_size.clamp(256, 16384).next_power_of_two();
+        if size == self.output.len() {
+            return;
+        }
+        self.spectrum = Spectrum::new(size);
+        self.output = vec![0.0; size];
+        self.mic = vec![0.0; size];
+        self.position = 0;
+        self.frame = vec![0.0; size];
+        self.power = vec![0.0; self.spectrum.bins()];
+    }
+
+    /// Discard whatever the tap collected while nobody was looking
+    fn clear(&mut self) {
+        self.consumer.clear();
+        self.output.fill(0.0);
+        self.mic.fill(0.0);
+    }
+
+    /// Take in the newly tapped samples and analyse the newest window
+    fn analyze(&mut self, sample_rate: u32) -> SpectrumFrame {
+        let size = self.output.len();
+        while let Some([output, mic]) = self.consumer.try_pop() {
+            self.output[self.position] = output;
+            self.mic[self.position] = mic;
+            self.position = (self.position + 1) % size;
+        }
+        SpectrumFrame {
+            sample_rate,
+            bin_hz: sample_rate as f32 / size as f32,
+            output_db: self.magnitudes(true),
+            mic_db: self.magnitudes(false),
+        }
+    }
+
+    fn magnitudes(&mut self, output: bool) -> Vec<f32> {
+        let history = if output { &self.output } else { &self.mic };
+        let (newer, older) = history.split_at(self.position);
+        self.frame[..older.len()].copy_from_slice(older);
+        self.frame[older.len()..].copy_from_slice(newer);
+        self.spectrum.power_spectrum(&self.frame, &mut self.power);
+        self.power
+            .iter()
+            .map(|&power| if power > 0.0 { (10.0 * power.log10()).max(FLOOR_DB) } else { FLOOR_DB })
+            .collect()
+    }
+}
+
+/// Spectrum analysis thread: runs off the real-time path and sends one
+/// frame per interval while enabled. Settings arrive on `settings_rx`;
+/// the thread exits once the engine handle drops its sender.
+pub(super) fn analyzer_thread(
+    controls: SpectrumControls,
+    sample_rate: Arc<AtomicU32>,
+    settings_rx: Receiver<SpectrumSettings>,
+    frame_tx: Sender<SpectrumFrame>,
+) {
+    let mut settings = SpectrumSettings::default();
+    let mut analyzer = Analyzer::new(HeapCons::new(Arc::clone(&controls.ring)), settings.fft_size);
+
+    loop {
+        let interval = if settings.enabled {
+            Duration::from_secs_f32(1.0 / settings.rate_hz.clamp(5.0, 60.0))
+        } else {
+            Duration::from_millis(250)
+        };
+        match settings_rx.recv_timeout(interval) {
+            Ok(new_settings) => {
+                if new_settings.enabled && !settings.enabled {
+                    analyzer.clear();
+                }
+                analyzer.resize(new_settings.fft_size);
+                controls.enabled.store(new_settings.enabled, Ordering::Relaxed);
+                settings = new_settings;
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if settings.enabled {
+                    // Drop the frame if the frontend side hasn't caught up
+                    let _ = frame_tx.try_send(analyzer.analyze(sample_rate.load(Ordering::Relaxed)));
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                controls.enabled.store(false, Ordering::Relaxed);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ringbuf::traits::Producer;
+    use ringbuf::HeapProd;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn test_frames_show_tapped_tones() {
+        let controls = SpectrumControls::new();
+        let mut tap = HeapProd::new(Arc::clone(&controls.ring));
+        let mut analyzer = Analyzer::new(HeapCons::new(Arc::clone(&controls.ring)), 2048);
+
+        // 750 Hz on the output and 93.75 Hz hum on the mic, both on bin centres
+        for i in 0..4096 {
+            let t = i as f32 / 48000.0;
+            let _ = tap.try_push([(2.0 * PI * 750.0 * t).sin() * 0.5, (2.0 * PI * 93.75 * t).sin() * 0.1]);
+        }
+        let frame = analyzer.analyze(48000);
+        assert_eq!(frame.output_db.len(), 1025);
+        assert!((frame.bin_hz - 23.4375).abs() < 1e-4);
+
+        let loudest = |bins: &[f32]| {
+            bins.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)).map(|(k, db)| (k, *db)).unwrap()
+        };
+        let (bin, db) = loudest(&frame.output_db);
+        assert_eq!(bin, 32);
+        assert!((db + 6.02).abs() < 0.1, "output tone {} dB", db);
+        let (bin, db) = loudest(&frame.mic_db);
+        assert_eq!(bin, 4);
+        assert!((db + 20.0).abs() < 0.1, "mic hum {} dB", db);
+        assert!(frame.output_db[200] < -80.0);
+    }
+}
@@ -0,0 +1,540 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Device, FromSample, Stream, StreamConfig};
+
+use super::health::{StreamKind, StreamMonitor};
+use super::latency::LoopbackDetector;
+use super::pipeline::{InputProcessor, LocalPipeline, MicCapture, MixPipeline, PlaybackPipeline};
+use super::tone::ToneDetector;
+use super::stream_config::{choose_config, ChosenConfig, StreamConfigs, StreamPreferences};
+
+#[cfg(target_os = "linux")]
+use super::{get_sink_input_ids, get_source_output_ids, pulseaudio_sink_channels, pulseaudio_source_channels, route_local_stream_to_default_speakers_with_baseline, route_sink_input_to_device_with_baseline, route_source_output_to_device_with_baseline};
+
+#[cfg(target_os = "macos")]
+use super::{check_microphone_permission, request_microphone_permission};
+
+/// Something that can run the engine's pipelines against audio devices.
+///
+/// The audio thread only talks to devices through this trait; the pipelines
+/// themselves are plain buffer processors. Streams run from when they are
+/// opened until dropped.
+pub(super) trait AudioBackend {
+    type Stream;
+
+    /// Set the buffer size, rate and format to aim for. Returns whether they
+    /// changed, in which case running streams should be reopened.
+    fn set_preferences(&mut self, preferences: StreamPreferences) -> bool;
+
+    /// Open the mic capture stream
+    fn open_input(&mut self, device_name: Option<&str>, capture: MicCapture, monitor: StreamMonitor) -> Result<Self::Stream, String>;
+
+    /// Open the main output stream (mic + optionally sidetone mixed) for VB-Cable/Zoom
+    fn open_output(&mut self, device_name: Option<&str>, pipeline: MixPipeline, monitor: StreamMonitor) -> Result<Self::Stream, String>;
+
+    /// Open the local sidetone monitor stream for headphones/speakers
+    fn open_local_output(&mut self, device_name: Option<&str>, pipeline: LocalPipeline, monitor: StreamMonitor) -> Result<Self::Stream, String>;
+
+    /// Open a capture stream on a loopback of the main output for latency tests
+    fn open_loopback(&mut self, device_name: &str, detector: LoopbackDetector, monitor: StreamMonitor) -> Result<Self::Stream, String>;
+
+    /// Open a capture stream for a tone detector: the conference audio (e.g. a
+    /// sink monitor) for the receive decoder, or a rig's audio for tone keying
+    fn open_tone_capture(&mut self, device_name: &str, detector: ToneDetector, monitor: StreamMonitor) -> Result<Self::Stream, String>;
+
+    /// Open a stream playing back the test recording
+    fn open_playback(&mut self, device_name: Option<&str>, pipeline: PlaybackPipeline, monitor: StreamMonitor) -> Result<Self::Stream, String>;
+}
+
+/// Real sound devices through cpal
+pub(super) struct CpalBackend {
+    /// Buffer size, rate and format to aim for when opening streams
+    preferences: StreamPreferences,
+    /// What each stream was actually opened with, shared with the engine handle
+    configs: Arc<parking_lot::Mutex<StreamConfigs>>,
+}
+
+impl CpalBackend {
+    pub fn new(configs: Arc<parking_lot::Mutex<StreamConfigs>>) -> Self {
+        Self {
+            preferences: StreamPreferences::default(),
+            configs,
+        }
+    }
+
+    /// Match the preferences against the device's supported input configs,
+    /// opening with `channels` if given
+    fn input_config(&self, device: &Device, channels: Option<u16>) -> Result<ChosenConfig, String> {
+        let default = device.default_input_config().map_err(|e| e.to_string())?;
+        let supported: Vec<_> = device
+            .supported_input_configs()
+            .map(|configs| configs.collect())
+            .unwrap_or_default();
+        Ok(choose_config(&supported, &default, &self.preferences, channels))
+    }
+
+    /// Match the preferences against the device's supported output configs,
+    /// opening with `channels` if given
+    fn output_config(&self, device: &Device, channels: Option<u16>) -> Result<ChosenConfig, String> {
+        let default = device.default_output_config().map_err(|e| e.to_string())?;
+        let supported: Vec<_> = device
+            .supported_output_configs()
+            .map(|configs| configs.collect())
+            .unwrap_or_default();
+        Ok(choose_config(&supported, &default, &self.preferences, channels))
+    }
+
+    fn report(&self, stream: StreamKind, chosen: &ChosenConfig) {
+        let negotiated = chosen.describe(stream);
+        eprintln!("[audio] {:?} stream negotiated: {:?}", stream, negotiated);
+        self.configs.lock().set(negotiated);
+    }
+
+    /// Open a capture stream feeding `processor`, reporting its config as `report`
+    fn open_capture<P: InputProcessor>(
+        &mut self,
+        device_name: Option<&str>,
+        mut processor: P,
+        report: Option<StreamKind>,
+        monitor: StreamMonitor,
+    ) -> Result<Stream, String> {
+        // On macOS, always request microphone permission via AVFoundation.
+        // CoreAudio alone doesn't always trigger the TCC permission dialog,
+        // especially with ad-hoc signed apps. We call requestAccess every time
+        // (not just when status != authorized) because it also initializes the
+        // AVFoundation mic subsystem, which may be needed for CoreAudio to work.
+        #[cfg(target_os = "macos")]
+        {
+            let status = unsafe { check_microphone_permission() };
+            eprintln!("[audio] macOS mic permission status: {} (0=notDetermined, 1=restricted, 2=denied, 3=authorized)", status);
+
+            eprintln!("[audio] Requesting microphone permission...");
+            let result = unsafe { request_microphone_permission() };
+            eprintln!("[audio] Microphone permission result: {} (1=granted, 0=denied, -1=timeout)", result);
+
+            if result != 1 {
+                return Err("Microphone permission denied. Please enable in System Settings > Privacy & Security > Microphone".to_string());
+            }
+        }
+
+        let host = cpal::default_host();
+
+        // On Linux, always use the "pipewire" ALSA device and route using pactl
+        // The device_name parameter is a PulseAudio source name, not an ALSA name
+        #[cfg(target_os = "linux")]
+        let (device, pulse_source) = {
+            eprintln!("[audio] Looking for 'pipewire' or 'default' ALSA input device...");
+            let devices: Vec<_> = host.input_devices()
+                .map_err(|e| e.to_string())?
+                .collect();
+
+            eprintln!("[audio] Available ALSA input devices:");
+            for d in &devices {
+                if let Ok(n) = d.name() {
+                    eprintln!("[audio]   - '{}'", n);
+                }
+            }
+
+            let dev = devices.iter()
+                .find(|d| d.name().map(|n| n == "pipewire" || n == "default").unwrap_or(false))
+                .cloned()
+                .or_else(|| host.default_input_device())
+                .ok_or_else(|| "No pipewire/default input device available".to_string())?;
+
+            eprintln!("[audio] Using ALSA input device: {:?}", dev.name());
+            if let Some(name) = device_name {
+                eprintln!("[audio] Will route to PulseAudio source: {}", name);
+            }
+
+            (dev, device_name.map(|s| s.to_string()))
+        };
+
+        #[cfg(not(target_os = "linux"))]
+        let device = if let Some(name) = device_name {
+            let devices: Vec<_> = host.input_devices()
+                .map_err(|e| e.to_string())?
+                .collect();
+
+            devices.iter()
+                .find(|d| d.name().map(|n| n == name).unwrap_or(false))
+                .cloned()
+                .or_else(|| host.default_input_device())
+                .ok_or_else(|| format!("Input device '{}' not found", name))?
+        } else {
+            host.default_input_device()
+                .ok_or_else(|| "No default input device".to_string())?
+        };
+
+        // Open with as many channels as the PulseAudio source has, so the
+        // channel selection picks the interface's own inputs rather than a
+        // remix of them; elsewhere the named device is opened directly
+        #[cfg(target_os = "linux")]
+        let device_channels = pulse_source.as_deref().and_then(pulseaudio_source_channels);
+        #[cfg(not(target_os = "linux"))]
+        let device_channels = None;
+
+        let chosen = self.input_config(&device, device_channels)?;
+        let config = &chosen.config;
+
+        eprintln!("[audio] Input device config: channels={}, sample_rate={}, format={:?}, buffer={:?}",
+            config.channels, config.sample_rate.0, chosen.sample_format, config.buffer_size);
+        eprintln!("[audio] Input device name: {:?}", device.name());
+
+        let channels = config.channels as usize;
+
+        // Let the output side resample from the mic rate
+        processor.prepare(config.sample_rate.0);
+
+        // Capture baseline source-output IDs before creating stream
+        #[cfg(target_os = "linux")]
+        let baseline_source_outputs = if pulse_source.is_some() {
+            get_source_output_ids()
+        } else {
+            Vec::new()
+        };
+
+        let stream = match chosen.sample_format {
+            cpal::SampleFormat::F32 => build_input_stream::<f32, P>(&device, config, processor, channels, monitor),
+            cpal::SampleFormat::I16 => build_input_stream::<i16, P>(&device, config, processor, channels, monitor),
+            cpal::SampleFormat::U16 => build_input_stream::<u16, P>(&device, config, processor, channels, monitor),
+            _ => return Err("Unsupported input sample format".to_string()),
+        }?;
+        if let Some(kind) = report {
+            self.report(kind, &chosen);
+        }
+
+        // On Linux, route the source-output to the user's selected PulseAudio source
+        #[cfg(target_os = "linux")]
+        if let Some(source_name) = pulse_source {
+            route_source_output_to_device_with_baseline(source_name, baseline_source_outputs);
+        }
+
+        play(stream)
+    }
+}
+
+impl AudioBackend for CpalBackend {
+    type Stream = Stream;
+
+    fn set_preferences(&mut self, preferences: StreamPreferences) -> bool {
+        if preferences == self.preferences {
+            return false;
+        }
+        self.preferences = preferences;
+        true
+    }
+
+    /// Create an audio input stream (microphone capture)
+    fn open_input(&mut self, device_name: Option<&str>, capture: MicCapture, monitor: StreamMonitor) -> Result<Stream, String> {
+        self.open_capture(device_name, capture, Some(StreamKind::Input), monitor)
+    }
+
+    /// Create an audio output stream (mic + optionally sidetone mixed) for VB-Cable/Zoom
+    fn open_output(&mut self, device_name: Option<&str>, mut pipeline: MixPipeline, monitor: StreamMonitor) -> Result<Stream, String> {
+        let host = cpal::default_host();
+
+        // On Linux, always use the "pipewire" ALSA device and route using pactl
+        // The device_name parameter is a PulseAudio sink name, not an ALSA name
+        #[cfg(target_os = "linux")]
+        let (device, pulse_sink) = {
+            eprintln!("[audio] Looking for 'pipewire' or 'default' ALSA output device...");
+            let devices: Vec<_> = host.output_devices()
+                .map_err(|e| e.to_string())?
+                .collect();
+
+            eprintln!("[audio] Available ALSA output devices:");
+            for d in &devices {
+                if let Ok(n) = d.name() {
+                    eprintln!("[audio]   - '{}'", n);
+                }
+            }
+
+            let dev = devices.iter()
+                .find(|d| d.name().map(|n| n == "pipewire" || n == "default").unwrap_or(false))
+                .cloned()
+                .or_else(|| host.default_output_device())
+                .ok_or_else(|| "No pipewire/default output device available".to_string())?;
+
+            eprintln!("[audio] Using ALSA output device: {:?}", dev.name());
+            if let Some(name) = device_name {
+                eprintln!("[audio] Will route to PulseAudio sink: {}", name);
+            }
+
+            (dev, device_name.map(|s| s.to_string()))
+        };
+
+        #[cfg(not(target_os = "linux"))]
+        let device = if let Some(name) = device_name {
+            let devices: Vec<_> = host.output_devices()
+                .map_err(|e| e.to_string())?
+                .collect();
+
+            devices.iter()
+                .find(|d| d.name().map(|n| n == name).unwrap_or(false))
+                .cloned()
+                .or_else(|| host.default_output_device())
+                .ok_or_else(|| format!("Output device '{}' not found", name))?
+        } else {
+            host.default_output_device()
+                .ok_or_else(|| "No default output device".to_string())?
+        };
+
+        // Match the PulseAudio sink's channels so the channel map lands on them
+        #[cfg(target_os = "linux")]
+        let device_channels = pulse_sink.as_deref().and_then(pulseaudio_sink_channels);
+        #[cfg(not(target_os = "linux"))]
+        let device_channels = None;
+
+        let chosen = self.output_config(&device, device_channels)?;
+        let config = &chosen.config;
+
+        let channels = config.channels as usize;
+        pipeline.prepare(config.sample_rate.0);
+
+        // Capture baseline sink-input IDs before creating stream
+        #[cfg(target_os = "linux")]
+        let baseline_sink_inputs = if pulse_sink.is_some() {
+            get_sink_input_ids()
+        } else {
+            Vec::new()
+        };
+
+        let stream = match chosen.sample_format {
+            cpal::SampleFormat::F32 => build_output_stream::<f32, _>(&device, config, move |data: &mut [f32], channels, now| pipeline.process(data, channels, now), channels, monitor),
+            cpal::SampleFormat::I16 => build_output_stream::<i16, _>(&device, config, move |data: &mut [i16], channels, now| pipeline.process(data, channels, now), channels, monitor),
+            cpal::SampleFormat::U16 => build_output_stream::<u16, _>(&device, config, move |data: &mut [u16], channels, now| pipeline.process(data, channels, now), channels, monitor),
+            _ => return Err("Unsupported output sample format".to_string()),
+        }?;
+        self.report(StreamKind::Output, &chosen);
+
+        // On Linux, route the sink-input to the user's selected PulseAudio sink
+        #[cfg(target_os = "linux")]
+        if let Some(sink_name) = pulse_sink {
+            route_sink_input_to_device_with_baseline(sink_name, baseline_sink_inputs);
+        }
+
+        play(stream)
+    }
+
+    /// Create a local output stream (sidetone only) for monitoring through headphones/speakers
+    fn open_local_output(&mut self, device_name: Option<&str>, mut pipeline: LocalPipeline, monitor: StreamMonitor) -> Result<Stream, String> {
+        let host = cpal::default_host();
+
+        // On Linux, always use the "pipewire" ALSA device and route using pactl
+        // The device_name parameter is a PulseAudio sink name, not an ALSA name
+        #[cfg(target_os = "linux")]
+        let (device, pulse_sink) = {
+            eprintln!("[audio] Looking for 'pipewire' or 'default' ALSA device for local output...");
+            let devices: Vec<_> = host.output_devices()
+                .map_err(|e| e.to_string())?
+                .collect();
+
+            let dev = devices.iter()
+                .find(|d| d.name().map(|n| n == "pipewire" || n == "default").unwrap_or(false))
+                .cloned()
+                .or_else(|| host.default_output_device())
+                .ok_or_else(|| "No pipewire/default output device for local monitoring".to_string())?;
+
+            eprintln!("[audio] Using ALSA local output device: {:?}", dev.name());
+            if let Some(name) = device_name {
+                eprintln!("[audio] Will route local sidetone to PulseAudio sink: {}", name);
+            }
+
+            (dev, device_name.map(|s| s.to_string()))
+        };
+
+        #[cfg(not(target_os = "linux"))]
+        let device = if let Some(name) = device_name {
+            let devices: Vec<_> = host.output_devices()
+                .map_err(|e| e.to_string())?
+                .collect();
+
+            devices.iter()
+                .find(|d| d.name().map(|n| n == name).unwrap_or(false))
+                .cloned()
+                .or_else(|| host.default_output_device())
+                .ok_or_else(|| format!("Local output device '{}' not found", name))?
+        } else {
+            host.default_output_device()
+                .ok_or_else(|| "No default output device for local monitoring".to_string())?
+        };
+
+        let chosen = self.output_config(&device, None)?;
+        let config = &chosen.config;
+
+        let channels = config.channels as usize;
+        pipeline.prepare(config.sample_rate.0);
+
+        // Capture baseline sink-input IDs before creating stream
+        #[cfg(target_os = "linux")]
+        let baseline_sink_inputs = get_sink_input_ids();
+
+        let stream = match chosen.sample_format {
+            cpal::SampleFormat::F32 => build_output_stream::<f32, _>(&device, config, move |data: &mut [f32], channels, now| pipeline.process(data, channels, now), channels, monitor),
+            cpal::SampleFormat::I16 => build_output_stream::<i16, _>(&device, config, move |data: &mut [i16], channels, now| pipeline.process(data, channels, now), channels, monitor),
+            cpal::SampleFormat::U16 => build_output_stream::<u16, _>(&device, config, move |data: &mut [u16], channels, now| pipeline.process(data, channels, now), channels, monitor),
+            _ => return Err("Unsupported output sample format".to_string()),
+        }?;
+        self.report(StreamKind::Local, &chosen);
+
+        // On Linux, route the local sidetone to the user's selected PulseAudio sink
+        // If no specific device selected, route to default speakers (away from VailZoomer)
+        #[cfg(target_os = "linux")]
+        if let Some(sink_name) = pulse_sink {
+            route_sink_input_to_device_with_baseline(sink_name, baseline_sink_inputs);
+        } else {
+            // No specific device - route to default speakers using baseline
+            route_local_stream_to_default_speakers_with_baseline(baseline_sink_inputs);
+        }
+
+        play(stream)
+    }
+
+    /// Capture the output's loopback (e.g. the VailZoomer sink monitor) without touching the mic
+    fn open_loopback(&mut self, device_name: &str, detector: LoopbackDetector, monitor: StreamMonitor) -> Result<Stream, String> {
+        self.open_capture(Some(device_name), detector, None, monitor)
+    }
+
+    /// Capture a tone source, without touching the mic
+    fn open_tone_capture(&mut self, device_name: &str, detector: ToneDetector, monitor: StreamMonitor) -> Result<Stream, String> {
+        self.open_capture(Some(device_name), detector, None, monitor)
+    }
+
+    /// Play the test recording on the device's default config, converted
+    /// from the rate it was recorded at
+    fn open_playback(&mut self, device_name: Option<&str>, mut pipeline: PlaybackPipeline, monitor: StreamMonitor) -> Result<Stream, String> {
+        let host = cpal::default_host();
+
+        // On Linux, always use the "pipewire" ALSA device and route using pactl
+        #[cfg(target_os = "linux")]
+        let (device, pulse_sink) = {
+            eprintln!("[audio] Looking for 'pipewire' or 'default' ALSA device for playback...");
+            let devices: Vec<_> = host.output_devices()
+                .map_err(|e| e.to_string())?
+                .collect();
+
+            let dev = devices.iter()
+                .find(|d| d.name().map(|n| n == "pipewire" || n == "default").unwrap_or(false))
+                .cloned()
+                .or_else(|| host.default_output_device())
+                .ok_or_else(|| "No pipewire/default output device for playback".to_string())?;
+
+            eprintln!("[audio] Using ALSA playback device: {:?}", dev.name());
+            if let Some(name) = device_name {
+                eprintln!("[audio] Will route playback to PulseAudio sink: {}", name);
+            }
+
+            (dev, device_name.map(|s| s.to_string()))
+        };
+
+        #[cfg(not(target_os = "linux"))]
+        let device = if let Some(name) = device_name {
+            let devices: Vec<_> = host.output_devices()
+                .map_err(|e| e.to_string())?
+                .collect();
+
+            devices.iter()
+                .find(|d| d.name().map(|n| n == name).unwrap_or(false))
+                .cloned()
+                .or_else(|| host.default_output_device())
+                .ok_or_else(|| format!("Playback device '{}' not found", name))?
+        } else {
+            host.default_output_device()
+                .ok_or_else(|| "No default output device for playback".to_string())?
+        };
+
+        let config = device
+            .default_output_config()
+            .map_err(|e| e.to_string())?;
+        let sample_format = config.sample_format();
+        let config: StreamConfig = config.into();
+        let channels = config.channels as usize;
+        pipeline.prepare(config.sample_rate.0);
+
+        // Capture baseline sink-input IDs before creating stream
+        #[cfg(target_os = "linux")]
+        let baseline_sink_inputs = get_sink_input_ids();
+
+        let stream = match sample_format {
+            cpal::SampleFormat::F32 => build_output_stream::<f32, _>(&device, &config, move |data: &mut [f32], channels, now| pipeline.process(data, channels, now), channels, monitor),
+            cpal::SampleFormat::I16 => build_output_stream::<i16, _>(&device, &config, move |data: &mut [i16], channels, now| pipeline.process(data, channels, now), channels, monitor),
+            cpal::SampleFormat::U16 => build_output_stream::<u16, _>(&device, &config, move |data: &mut [u16], channels, now| pipeline.process(data, channels, now), channels, monitor),
+            _ => return Err("Unsupported output sample format".to_string()),
+        }?;
+
+        // On Linux, route playback to the user's selected PulseAudio sink or default speakers
+        #[cfg(target_os = "linux")]
+        if let Some(sink_name) = pulse_sink {
+            route_sink_input_to_device_with_baseline(sink_name, baseline_sink_inputs);
+        } else {
+            route_local_stream_to_default_speakers_with_baseline(baseline_sink_inputs);
+        }
+
+        play(stream)
+    }
+}
+
+/// Start a stream the backend has just built
+fn play(stream: Stream) -> Result<Stream, String> {
+    stream.play().map_err(|e| e.to_string())?;
+    Ok(stream)
+}
+
+fn build_input_stream<T: cpal::SizedSample, P: InputProcessor>(
+    device: &Device,
+    config: &StreamConfig,
+    mut processor: P,
+    channels: usize,
+    monitor: StreamMonitor,
+) -> Result<Stream, String>
+where
+    f32: FromSample<T>,
+{
+    let error_monitor = monitor.clone();
+
+    device
+        .build_input_stream(
+            config,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                monitor.beat();
+                processor.process(data, channels, Instant::now());
+            },
+            move |err| {
+                eprintln!("Input stream error: {}", err);
+                error_monitor.report_error(err.to_string());
+            },
+            None,
+        )
+        .map_err(|e| e.to_string())
+}
+
+fn build_output_stream<T, F>(
+    device: &Device,
+    config: &StreamConfig,
+    mut process: F,
+    channels: usize,
+    monitor: StreamMonitor,
+) -> Result<Stream, String>
+where
+    T: cpal::SizedSample + FromSample<f32>,
+    F: FnMut(&mut [T], usize, Instant) + Send + 'static,
+{
+    let error_monitor = monitor.clone();
+
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                monitor.beat();
+                process(data, channels, Instant::now());
+            },
+            move |err| {
+                eprintln!("Output stream error: {}", err);
+                error_monitor.report_error(err.to_string());
+            },
+            None,
+        )
+        .map_err(|e| e.to_string())
+}
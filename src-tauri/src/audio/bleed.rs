@@ -0,0 +1,525 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// Length of the adaptive filter (~2.7 ms at 48 kHz). The reference is first
+/// delayed by the estimated bulk path delay (ring fill, device buffers and
+/// air), so the taps only need to cover the speaker and mic response and
+/// small errors in that estimate.
+const FILTER_TAPS: usize = 128;
+
+/// Taps kept ahead of the estimated delay, so the true delay stays inside
+/// the filter when the estimate is off either way
+const DELAY_LEAD: usize = FILTER_TAPS / 2;
+
+/// Longest speaker-to-mic path delay the canceller can line up with
+const MAX_PATH_DELAY_MS: f32 = 250.0;
+
+/// Length of the envelope blocks the path delay is estimated from, and so its resolution
+const ENVELOPE_BLOCK_MS: f32 = 1.0;
+
+/// Time constant of the envelope correlation behind the path delay estimate
+const DELAY_SMOOTHING_MS: f32 = 2000.0;
+
+/// Normalised correlation between the reference and mic envelopes needed
+/// before a path delay is trusted
+const MIN_DELAY_CORRELATION: f32 = 0.5;
+
+/// Change in the reference envelope from one block to the next, relative
+/// to the larger of the two, that counts as a key edge rather than ripple
+const EDGE_CHANGE: f32 = 0.25;
+
+/// NLMS step size; small enough to ride through the operator talking over the tone
+const STEP_SIZE: f32 = 0.05;
+
+/// Reference power below which the filter neither filters nor adapts
+const MIN_REFERENCE_POWER: f32 = 1e-6;
+
+/// How long the normalisation remembers the tone's level across key-ups
+const PEAK_HOLD_MS: f32 = 1000.0;
+
+/// Echo return loss enhancement counted as fully converged
+const CONVERGED_ERLE_DB: f32 = 30.0;
+
+/// Time constant of the power estimates behind the convergence estimate
+const ERLE_SMOOTHING_MS: f32 = 200.0;
+
+/// State of the sidetone-bleed canceller, for the UI
+#[derive(Clone, Copy, Debug, Default, serde::Serialize)]
+pub struct BleedStatus {
+    pub enabled: bool,
+    /// Enabled and the route plays the sidetone locally, so there is something to cancel
+    pub active: bool,
+    /// The local monitor runs at a different rate from the output, so its
+    /// tone can't serve as the reference and cancellation is off
+    pub rate_mismatch: bool,
+    /// How much the leaked tone has been reduced (echo return loss enhancement)
+    pub erle_db: f32,
+    /// `erle_db` as a fraction of full convergence (0.0 - 1.0)
+    pub convergence: f32,
+    /// Estimated delay from the local sidetone to the mic, once one has been found
+    pub delay_ms: Option<f32>,
+}
+
+/// Canceller switch shared with the output callback, plus its current status
+#[derive(Clone)]
+pub(super) struct BleedControls {
+    shared: Arc<BleedShared>,
+}
+
+struct BleedShared {
+    enabled: AtomicBool,
+    active: AtomicBool,
+    rate_mismatch: AtomicBool,
+    /// Rate the local monitor fills the reference ring at, 0 until it runs
+    reference_rate: AtomicU32,
+    erle_db: AtomicU32,
+    /// Path delay in ms, negative while unknown
+    delay_ms: AtomicU32,
+}
+
+impl BleedControls {
+    pub fn new() -> Self {
+        Self {
+            shared: Arc::new(BleedShared {
+                enabled: AtomicBool::new(false),
+                active: AtomicBool::new(false),
+                rate_mismatch: AtomicBool::new(false),
+                reference_rate: AtomicU32::new(0),
+                erle_db: AtomicU32::new(0),
+                delay_ms: AtomicU32::new((-1.0_f32).to_bits()),
+            }),
+        }
+    }
+
+    /// Called from the control side
+    pub fn set_enabled(&self, enabled: bool) {
+        self.shared.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Called by the local monitor once its device rate is known
+    pub fn set_reference_rate(&self, sample_rate: u32) {
+        self.shared.reference_rate.store(sample_rate, Ordering::Relaxed);
+    }
+
+    pub fn status(&self) -> BleedStatus {
+        let shared = &self.shared;
+        let erle_db = f32::from_bits(shared.erle_db.load(Ordering::Relaxed));
+        let delay_ms = f32::from_bits(shared.delay_ms.load(Ordering::Relaxed));
+        BleedStatus {
+            enabled: shared.enabled.load(Ordering::Relaxed),
+            active: shared.active.load(Ordering::Relaxed),
+            rate_mismatch: shared.rate_mismatch.load(Ordering::Relaxed),
+            erle_db,
+            convergence: (erle_db / CONVERGED_ERLE_DB).clamp(0.0, 1.0),
+            delay_ms: (delay_ms >= 0.0).then_some(delay_ms),
+        }
+    }
+}
+
+/// Finds the bulk delay from the local sidetone to the mic by correlating
+/// their envelopes: every key edge shows up in the mic one path delay after
+/// it was played. Works on 1 ms blocks so the search over the whole delay
+/// range stays cheap enough for the callback.
+struct PathDelayEstimator {
+    block_len: usize,
+    /// Samples and sums of squares of the block being collected
+    count: usize,
+    mic_energy: f32,
+    reference_energy: f32,
+    /// Reference envelope history, newest first from `position`, stored
+    /// twice like the filter history
+    history: Vec<f32>,
+    position: usize,
+    mic_mean: f32,
+    reference_mean: f32,
+    mic_variance: f32,
+    reference_variance: f32,
+    /// Smoothed covariance of the mic envelope with the reference envelope `lag` blocks earlier
+    covariance: Vec<f32>,
+    smoothing: f32,
+    /// Reference envelope of the previous block
+    reference_previous: f32,
+    /// Blocks since the reference last had a key edge
+    since_edge: usize,
+    /// Best delay found so far, in samples
+    delay: Option<usize>,
+}
+
+impl PathDelayEstimator {
+    fn new(sample_rate: f32) -> Self {
+        let block_len = ((ENVELOPE_BLOCK_MS / 1000.0 * sample_rate).round() as usize).max(1);
+        let lags = (MAX_PATH_DELAY_MS / ENVELOPE_BLOCK_MS) as usize + 1;
+        Self {
+            block_len,
+            count: 0,
+            mic_energy: 0.0,
+            reference_energy: 0.0,
+            history: vec![0.0; 2 * lags],
+            position: 0,
+            mic_mean: 0.0,
+            reference_mean: 0.0,
+            mic_variance: 0.0,
+            reference_variance: 0.0,
+            covariance: vec![0.0; lags],
+            smoothing: 1.0 - (-ENVELOPE_BLOCK_MS / DELAY_SMOOTHING_MS).exp(),
+            reference_previous: 0.0,
+            since_edge: usize::MAX,
+            delay: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.count = 0;
+        self.mic_energy = 0.0;
+        self.reference_energy = 0.0;
+        self.history.fill(0.0);
+        self.mic_mean = 0.0;
+        self.reference_mean = 0.0;
+        self.mic_variance = 0.0;
+        self.reference_variance = 0.0;
+        self.covariance.fill(0.0);
+        self.reference_previous = 0.0;
+        self.since_edge = usize::MAX;
+        self.delay = None;
+    }
+
+    fn push(&mut self, mic: f32, reference: f32) {
+        self.mic_energy += mic * mic;
+        self.reference_energy += reference * reference;
+        self.count += 1;
+        if self.count < self.block_len {
+            return;
+        }
+
+        let mic_envelope = (self.mic_energy / self.count as f32).sqrt();
+        let reference_envelope = (self.reference_energy / self.count as f32).sqrt();
+        self.count = 0;
+        self.mic_energy = 0.0;
+        self.reference_energy = 0.0;
+
+        let change = (reference_envelope - self.reference_previous).abs();
+        if change > EDGE_CHANGE * reference_envelope.max(self.reference_previous) {
+            self.since_edge = 0;
+        } else {
+            self.since_edge = self.since_edge.saturating_add(1);
+        }
+        self.reference_previous = reference_envelope;
+
+        let lags = self.covariance.len();
+        self.position = (self.position + lags - 1) % lags;
+        self.history[self.position] = reference_envelope;
+        self.history[self.position + lags] = reference_envelope;
+
+        let a = self.smoothing;
+        self.mic_mean += (mic_envelope - self.mic_mean) * a;
+        self.reference_mean += (reference_envelope - self.reference_mean) * a;
+        let mic_deviation = mic_envelope - self.mic_mean;
+        let reference_deviation = reference_envelope - self.reference_mean;
+        self.mic_variance += (mic_deviation * mic_deviation - self.mic_variance) * a;
+        self.reference_variance += (reference_deviation * reference_deviation - self.reference_variance) * a;
+
+        let window = &self.history[self.position..self.position + lags];
+        let mut best = (0, f32::MIN);
+        for (lag, (covariance, past)) in self.covariance.iter_mut().zip(window).enumerate() {
+            *covariance += (mic_deviation * (past - self.reference_mean) - *covariance) * a;
+            if *covariance > best.1 {
+                best = (lag, *covariance);
+            }
+        }
+
+        // Only move once the key edges clearly line up, and only while the
+        // last one can still be on its way to the mic; a held key or the
+        // operator talking says nothing about the delay
+        let scale = (self.mic_variance * self.reference_variance).sqrt();
+        let edge_in_flight = self.since_edge < lags;
+        if edge_in_flight && scale > MIN_REFERENCE_POWER && best.1 / scale > MIN_DELAY_CORRELATION {
+            // Place the peak between blocks from its neighbours
+            let (lag, peak) = best;
+            let offset = match (lag.checked_sub(1).map(|l| self.covariance[l]), self.covariance.get(lag + 1)) {
+                (Some(before), Some(&after)) => {
+                    let curvature = before - 2.0 * peak + after;
+                    if curvature < 0.0 { (0.5 * (before - after) / curvature).clamp(-0.5, 0.5) } else { 0.0 }
+                }
+                _ => 0.0,
+            };
+            self.delay = Some(((lag as f32 + offset) * self.block_len as f32).round() as usize);
+        }
+    }
+}
+
+/// NLMS adaptive filter that models the path from the local sidetone to the
+/// mic and subtracts the predicted leak from the mic samples.
+pub(super) struct BleedCanceller {
+    controls: BleedControls,
+    sample_rate: f32,
+    active: bool,
+    /// The reference comes from a local monitor running at another rate
+    rate_mismatch: bool,
+    estimator: PathDelayEstimator,
+    /// Reference samples waiting out the bulk path delay
+    delay_line: Vec<f32>,
+    delay_position: usize,
+    /// Delay applied to the reference before the filter, in samples
+    bulk_delay: usize,
+    weights: Vec<f32>,
+    /// Reference history, stored twice so the last `FILTER_TAPS` samples
+    /// are always one contiguous slice starting at `position` (newest first)
+    history: Vec<f32>,
+    position: usize,
+    /// Sum of squares of the reference history
+    reference_power: f32,
+    /// `reference_power` held at its recent peak, decaying slowly
+    reference_peak: f32,
+    peak_decay: f32,
+    /// Smoothed mic and residual power while the reference is playing
+    mic_power: f32,
+    residual_power: f32,
+    smoothing: f32,
+}
+
+impl BleedCanceller {
+    pub fn new(controls: BleedControls, sample_rate: f32) -> Self {
+        let mut canceller = Self {
+            controls,
+            sample_rate,
+            active: false,
+            rate_mismatch: false,
+            estimator: PathDelayEstimator::new(sample_rate),
+            delay_line: Vec::new(),
+            delay_position: 0,
+            bulk_delay: 0,
+            weights: vec![0.0; FILTER_TAPS],
+            history: vec![0.0; 2 * FILTER_TAPS],
+            position: 0,
+            reference_power: 0.0,
+            reference_peak: 0.0,
+            peak_decay: 0.0,
+            mic_power: 0.0,
+            residual_power: 0.0,
+            smoothing: 0.0,
+        };
+        canceller.set_sample_rate(sample_rate);
+        canceller
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.smoothing = 1.0 - (-1.0 / (ERLE_SMOOTHING_MS / 1000.0 * sample_rate)).exp();
+        self.peak_decay = (-1.0 / (PEAK_HOLD_MS / 1000.0 * sample_rate)).exp();
+        self.estimator = PathDelayEstimator::new(sample_rate);
+        self.delay_line = vec![0.0; (MAX_PATH_DELAY_MS / 1000.0 * sample_rate) as usize + 1];
+        self.delay_position = 0;
+        self.bulk_delay = 0;
+        self.reset_filter();
+    }
+
+    /// Forget the modelled path, keeping the delay estimate
+    fn reset_filter(&mut self) {
+        self.weights.fill(0.0);
+        self.history.fill(0.0);
+        self.position = 0;
+        self.reference_power = 0.0;
+        self.reference_peak = 0.0;
+        self.mic_power = 0.0;
+        self.residual_power = 0.0;
+    }
+
+    /// Called once per buffer; `local_sidetone` is whether the route plays
+    /// the tone on the local monitor. The reference ring is read one sample
+    /// per output sample, so a local monitor at another rate would drift
+    /// against the mic: cancellation stays off until the rates match.
+    pub fn begin_block(&mut self, local_sidetone: bool) {
+        let shared = &self.controls.shared;
+        let reference_rate = shared.reference_rate.load(Ordering::Relaxed);
+        self.rate_mismatch = reference_rate != 0 && reference_rate as f32 != self.sample_rate;
+        let active = local_sidetone && !self.rate_mismatch && shared.enabled.load(Ordering::Relaxed);
+        if active != self.active {
+            // Start over: the acoustic path may have changed while we were off
+            self.active = active;
+            self.estimator.reset();
+            self.delay_line.fill(0.0);
+            self.bulk_delay = 0;
+            self.reset_filter();
+        }
+    }
+
+    /// Publish the status for the UI; called once per buffer
+    pub fn end_block(&self) {
+        let erle_db = if self.active && self.residual_power > 0.0 {
+            (10.0 * (self.mic_power / self.residual_power).log10()).max(0.0)
+        } else {
+            0.0
+        };
+        let shared = &self.controls.shared;
+        shared.active.store(self.active, Ordering::Relaxed);
+        shared.rate_mismatch.store(self.rate_mismatch, Ordering::Relaxed);
+        shared.erle_db.store(erle_db.to_bits(), Ordering::Relaxed);
+        let delay_ms = match self.estimator.delay {
+            Some(delay) if self.active => delay as f32 * ENVELOPE_BLOCK_MS / self.estimator.block_len as f32,
+            _ => -1.0,
+        };
+        shared.delay_ms.store(delay_ms.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Remove the leaked `reference` (the local sidetone sample being played) from `mic`
+    pub fn process(&mut self, mic: f32, reference: f32) -> f32 {
+        if !self.active {
+            return mic;
+        }
+
+        // Line the reference up with the leak, starting over if the path moved
+        // further than the filter can follow
+        self.estimator.push(mic, reference);
+        let bulk_delay = self.estimator.delay.map_or(0, |delay| delay.saturating_sub(DELAY_LEAD));
+        if bulk_delay.abs_diff(self.bulk_delay) > DELAY_LEAD / 2 {
+            self.bulk_delay = bulk_delay;
+            self.reset_filter();
+        }
+        let length = self.delay_line.len();
+        self.delay_line[self.delay_position] = reference;
+        let reference = self.delay_line[(self.delay_position + length - self.bulk_delay) % length];
+        self.delay_position = (self.delay_position + 1) % length;
+
+        // Push the newest reference sample, dropping the oldest from the power sum
+        self.position = (self.position + FILTER_TAPS - 1) % FILTER_TAPS;
+        let oldest = self.history[self.position];
+        self.history[self.position] = reference;
+        self.history[self.position + FILTER_TAPS] = reference;
+        self.reference_power = (self.reference_power + reference * reference - oldest * oldest).max(0.0);
+        if self.position == 0 {
+            // Recompute now and then so rounding can't build up in the running sum
+            self.reference_power = self.history[..FILTER_TAPS].iter().map(|x| x * x).sum();
+        }
+        if self.reference_power < MIN_REFERENCE_POWER {
+            return mic;
+        }
+
+        let window = &self.history[self.position..self.position + FILTER_TAPS];
+        let estimate: f32 = self.weights.iter().zip(window).map(|(w, x)| w * x).sum();
+        let residual = mic - estimate;
+
+        // Normalise by the tone's recent level rather than what is in the
+        // window, so the first samples of a key-down don't throw the weights
+        self.reference_peak = self.reference_power.max(self.reference_peak * self.peak_decay);
+        let step = STEP_SIZE * residual / (self.reference_peak + MIN_REFERENCE_POWER);
+        for (w, x) in self.weights.iter_mut().zip(window) {
+            *w += step * x;
+        }
+
+        self.mic_power += (mic * mic - self.mic_power) * self.smoothing;
+        self.residual_power += (residual * residual - self.residual_power) * self.smoothing;
+        residual
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = 48000.0;
+
+    #[test]
+    fn test_cancels_delayed_tone_and_reports_convergence() {
+        let controls = BleedControls::new();
+        controls.set_enabled(true);
+        let mut canceller = BleedCanceller::new(controls.clone(), RATE);
+        canceller.begin_block(true);
+
+        // The mic hears the tone 3 ms late at 40%, plus a little voice
+        let tone = |i: usize| (2.0 * std::f32::consts::PI * 600.0 * i as f32 / RATE).sin() * 0.5;
+        let voice = |i: usize| (2.0 * std::f32::consts::PI * 170.0 * i as f32 / RATE).sin() * 0.01;
+        let delay = 144;
+        let mut residual_peak: f32 = 0.0;
+        for i in 0..RATE as usize {
+            let leaked = if i >= delay { 0.4 * tone(i - delay) } else { 0.0 };
+            let out = canceller.process(leaked + voice(i), tone(i));
+            if i > RATE as usize / 2 {
+                residual_peak = residual_peak.max((out - voice(i)).abs());
+            }
+        }
+        canceller.end_block();
+
+        assert!(residual_peak < 0.004, "residual {}", residual_peak);
+        let status = controls.status();
+        assert!(status.active);
+        assert!(status.erle_db > 20.0, "ERLE {}", status.erle_db);
+        assert!(status.convergence > 0.6);
+
+        // Route without a local tone: untouched and idle
+        canceller.begin_block(false);
+        assert_eq!(canceller.process(0.3, 0.5), 0.3);
+        canceller.end_block();
+        assert!(!controls.status().active);
+    }
+
+    #[test]
+    fn test_cancels_keyed_dits_across_path_delay() {
+        let controls = BleedControls::new();
+        controls.set_enabled(true);
+        let mut canceller = BleedCanceller::new(controls.clone(), RATE);
+        canceller.begin_block(true);
+
+        // 20 WPM dits (60 ms on, 60 ms off) with 5 ms ramps, heard 40 ms
+        // later at 40%, much longer than the filter itself
+        let dit = (0.060 * RATE) as usize;
+        let ramp = 0.005 * RATE;
+        let keyed = |i: usize| {
+            let phase = i % (2 * dit);
+            let envelope = if phase < dit {
+                (phase as f32 / ramp).min(1.0)
+            } else {
+                1.0 - ((phase - dit) as f32 / ramp).min(1.0)
+            };
+            (2.0 * std::f32::consts::PI * 600.0 * i as f32 / RATE).sin() * 0.5 * envelope
+        };
+        let voice = |i: usize| (2.0 * std::f32::consts::PI * 170.0 * i as f32 / RATE).sin() * 0.01;
+        let delay = (0.040 * RATE) as usize;
+        let total = 4 * RATE as usize;
+        let mut residual_peak: f32 = 0.0;
+        for i in 0..total {
+            let leaked = if i >= delay { 0.4 * keyed(i - delay) } else { 0.0 };
+            let out = canceller.process(leaked + voice(i), keyed(i));
+            // Every key-down and key-up of the last second, as the mic hears them
+            if i > total - RATE as usize {
+                residual_peak = residual_peak.max((out - voice(i)).abs());
+            }
+        }
+        canceller.end_block();
+
+        assert!(residual_peak < 0.01, "residual {}", residual_peak);
+        let delay_ms = controls.status().delay_ms.expect("no path delay found");
+        assert!((delay_ms - 40.0).abs() <= 1.0, "delay {}", delay_ms);
+    }
+
+    #[test]
+    fn test_held_key_keeps_path_delay() {
+        let controls = BleedControls::new();
+        controls.set_enabled(true);
+        let mut canceller = BleedCanceller::new(controls.clone(), RATE);
+        canceller.begin_block(true);
+
+        // One key-down with a 5 ms ramp, heard 10 ms later, then held for two seconds
+        let ramp = 0.005 * RATE;
+        let held = |i: usize| (2.0 * std::f32::consts::PI * 600.0 * i as f32 / RATE).sin() * 0.5 * (i as f32 / ramp).min(1.0);
+        let delay = (0.010 * RATE) as usize;
+        let mut delays = Vec::new();
+        let mut residual_peak: f32 = 0.0;
+        for i in 0..2 * RATE as usize {
+            let leaked = if i >= delay { 0.4 * held(i - delay) } else { 0.0 };
+            let out = canceller.process(leaked, held(i));
+            if i > RATE as usize {
+                residual_peak = residual_peak.max(out.abs());
+            }
+            if i % (RATE as usize / 10) == 0 {
+                canceller.end_block();
+                delays.extend(controls.status().delay_ms);
+            }
+        }
+
+        // The steady tone after the edge says nothing new, so the delay
+        // (and with it the filter) stays put
+        assert!(!delays.is_empty());
+        assert!(delays.iter().all(|delay| *delay == delays[0]), "delays {:?}", delays);
+        assert!(residual_peak < 0.002, "residual {}", residual_peak);
+    }
+}
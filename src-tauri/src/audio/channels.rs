@@ -0,0 +1,100 @@
+use cpal::FromSample;
+
+use super::handoff::Handoff;
+
+/// How the channels of an input device become the mono mic signal
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum InputChannels {
+    /// Mean of every channel
+    #[default]
+    Average,
+    /// One channel (0-based); silent if the device doesn't have it
+    Single(usize),
+    /// Every channel added together
+    Sum,
+    /// Gain per channel; channels without a gain are dropped
+    Gains(Vec<f32>),
+}
+
+impl InputChannels {
+    /// Downmix one interleaved frame
+    pub fn downmix<T: Copy>(&self, frame: &[T]) -> f32
+    where
+        f32: FromSample<T>,
+    {
+        let sample = |s: &T| <f32 as FromSample<T>>::from_sample_(*s);
+        match self {
+            InputChannels::Average => frame.iter().map(sample).sum::<f32>() / frame.len() as f32,
+            InputChannels::Single(channel) => frame.get(*channel).map(sample).unwrap_or(0.0),
+            InputChannels::Sum => frame.iter().map(sample).sum(),
+            InputChannels::Gains(gains) => frame.iter().zip(gains).map(|(s, gain)| sample(s) * gain).sum(),
+        }
+    }
+}
+
+/// What one channel of the output device carries
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputChannel {
+    /// Mic and sidetone mixed and limited
+    #[default]
+    Mix,
+    Sidetone,
+    Mic,
+    Silent,
+}
+
+impl OutputChannel {
+    /// Pick this channel's sample. The sidetone and mic bypass the limiter,
+    /// so they are clipped to full scale and lead the mix by its look-ahead.
+    pub fn sample(self, mixed: f32, mic: f32, sidetone: f32) -> f32 {
+        match self {
+            OutputChannel::Mix => mixed,
+            OutputChannel::Sidetone => sidetone.clamp(-1.0, 1.0),
+            OutputChannel::Mic => mic.clamp(-1.0, 1.0),
+            OutputChannel::Silent => 0.0,
+        }
+    }
+}
+
+/// Input downmix and output channel map of the current devices, handed to
+/// the callbacks without locks
+#[derive(Clone)]
+pub(super) struct ChannelControls {
+    pub input: Handoff<InputChannels>,
+    /// Role of each output channel; channels past the end carry the mix
+    pub output: Handoff<Vec<OutputChannel>>,
+}
+
+impl ChannelControls {
+    pub fn new() -> Self {
+        Self {
+            input: Handoff::new(InputChannels::default()),
+            output: Handoff::new(Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_downmix_and_output_map() {
+        let frame = [0.2_f32, 0.4, -0.6, 0.0];
+        assert!((InputChannels::Average.downmix(&frame) - 0.0).abs() < 1e-6);
+        assert_eq!(InputChannels::Single(1).downmix(&frame), 0.4);
+        assert_eq!(InputChannels::Single(7).downmix(&frame), 0.0);
+        assert!((InputChannels::Sum.downmix(&frame) - 0.0).abs() < 1e-6);
+        assert!((InputChannels::Gains(vec![1.0, 0.5]).downmix(&frame) - 0.4).abs() < 1e-6);
+
+        let controls = ChannelControls::new();
+        let mut output = controls.output.subscribe();
+        assert!(output.get().is_empty());
+        controls.output.set(vec![OutputChannel::Sidetone, OutputChannel::Mic]);
+        output.update(|_| ());
+        let map = output.get();
+        assert_eq!(*map, vec![OutputChannel::Sidetone, OutputChannel::Mic]);
+        assert_eq!(map[0].sample(0.5, 0.2, 1.5), 1.0);
+        assert_eq!(map[1].sample(0.5, 0.2, 1.5), 0.2);
+    }
+}
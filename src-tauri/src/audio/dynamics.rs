@@ -0,0 +1,589 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// Release time of the gate's and compressor's level detectors (rides over a waveform's zero crossings)
+const DETECTOR_RELEASE_MS: f32 = 10.0;
+
+/// How far ahead the output limiter looks (and so how long it delays the output)
+pub(super) const LIMITER_LOOKAHEAD_MS: f32 = 1.0;
+
+/// Highest level the output limiter lets through
+const LIMITER_CEILING_DB: f32 = -1.0;
+
+const LIMITER_RELEASE_MS: f32 = 50.0;
+
+/// Noise gate / downward expander settings
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GateSettings {
+    pub enabled: bool,
+    /// Level below which the mic is attenuated
+    pub threshold_db: f32,
+    /// Expansion ratio below the threshold (1 = no effect, large = hard gate)
+    pub ratio: f32,
+    /// Most attenuation applied when closed
+    pub range_db: f32,
+    pub attack_ms: f32,
+    /// How long the gate stays open after the level drops below the threshold
+    pub hold_ms: f32,
+    pub release_ms: f32,
+}
+
+impl Default for GateSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold_db: -45.0,
+            ratio: 10.0,
+            range_db: 40.0,
+            attack_ms: 1.0,
+            hold_ms: 100.0,
+            release_ms: 150.0,
+        }
+    }
+}
+
+/// Gate settings shared with the output callback, plus the gate's current gain
+#[derive(Clone)]
+pub(super) struct GateControls {
+    shared: Arc<GateShared>,
+}
+
+struct GateShared {
+    enabled: AtomicBool,
+    threshold_db: AtomicU32,
+    ratio: AtomicU32,
+    range_db: AtomicU32,
+    attack_ms: AtomicU32,
+    hold_ms: AtomicU32,
+    release_ms: AtomicU32,
+    /// Gain the gate applied at the end of the last buffer (0.0 - 1.0)
+    level: AtomicU32,
+}
+
+impl GateControls {
+    pub fn new() -> Self {
+        let controls = Self {
+            shared: Arc::new(GateShared {
+                enabled: AtomicBool::new(false),
+                threshold_db: AtomicU32::new(0),
+                ratio: AtomicU32::new(0),
+                range_db: AtomicU32::new(0),
+                attack_ms: AtomicU32::new(0),
+                hold_ms: AtomicU32::new(0),
+                release_ms: AtomicU32::new(0),
+                level: AtomicU32::new(1.0_f32.to_bits()),
+            }),
+        };
+        controls.set(&GateSettings::default());
+        controls
+    }
+
+    /// Called from the control side
+    pub fn set(&self, settings: &GateSettings) {
+        let shared = &self.shared;
+        let store = |atomic: &AtomicU32, value: f32| atomic.store(value.to_bits(), Ordering::Relaxed);
+        store(&shared.threshold_db, settings.threshold_db.clamp(-90.0, 0.0));
+        store(&shared.ratio, settings.ratio.clamp(1.0, 100.0));
+        store(&shared.range_db, settings.range_db.clamp(0.0, 90.0));
+        store(&shared.attack_ms, settings.attack_ms.clamp(0.1, 100.0));
+        store(&shared.hold_ms, settings.hold_ms.clamp(0.0, 2000.0));
+        store(&shared.release_ms, settings.release_ms.clamp(1.0, 2000.0));
+        shared.enabled.store(settings.enabled, Ordering::Relaxed);
+    }
+
+    fn settings(&self) -> GateSettings {
+        let shared = &self.shared;
+        let load = |atomic: &AtomicU32| f32::from_bits(atomic.load(Ordering::Relaxed));
+        GateSettings {
+            enabled: shared.enabled.load(Ordering::Relaxed),
+            threshold_db: load(&shared.threshold_db),
+            ratio: load(&shared.ratio),
+            range_db: load(&shared.range_db),
+            attack_ms: load(&shared.attack_ms),
+            hold_ms: load(&shared.hold_ms),
+            release_ms: load(&shared.release_ms),
+        }
+    }
+
+    /// Current gate gain (1.0 = open)
+    pub fn level(&self) -> f32 {
+        f32::from_bits(self.shared.level.load(Ordering::Relaxed))
+    }
+}
+
+/// Noise gate with a downward expander characteristic.
+/// Below the threshold the gain falls by `ratio - 1` dB per dB, down to
+/// `-range_db`; the gain opens with the attack time and, once the hold time
+/// has passed, closes with the release time.
+pub(super) struct NoiseGate {
+    controls: GateControls,
+    settings: GateSettings,
+    sample_rate: f32,
+    detector: f32,
+    detector_release: f32,
+    gain: f32,
+    attack_coeff: f32,
+    release_coeff: f32,
+    hold_samples: u32,
+    hold_remaining: u32,
+}
+
+impl NoiseGate {
+    pub fn new(controls: GateControls, sample_rate: f32) -> Self {
+        let mut gate = Self {
+            settings: controls.settings(),
+            controls,
+            sample_rate,
+            detector: 0.0,
+            detector_release: 0.0,
+            gain: 1.0,
+            attack_coeff: 0.0,
+            release_coeff: 0.0,
+            hold_samples: 0,
+            hold_remaining: 0,
+        };
+        gate.update_coefficients();
+        gate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.update_coefficients();
+    }
+
+    /// Pick up setting changes; called once per buffer
+    pub fn begin_block(&mut self) {
+        let settings = self.controls.settings();
+        if settings != self.settings {
+            self.settings = settings;
+            self.update_coefficients();
+        }
+    }
+
+    /// Publish the gain for the UI; called once per buffer
+    pub fn end_block(&self) {
+        let level = if self.settings.enabled { self.gain } else { 1.0 };
+        self.controls.shared.level.store(level.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        if !self.settings.enabled {
+            return sample;
+        }
+
+        self.detector = sample.abs().max(self.detector * self.detector_release);
+        let level_db = 20.0 * self.detector.max(1e-6).log10();
+        let below = self.settings.threshold_db - level_db;
+
+        let target = if below <= 0.0 {
+            self.hold_remaining = self.hold_samples;
+            1.0
+        } else if self.hold_remaining > 0 {
+            self.hold_remaining -= 1;
+            1.0
+        } else {
+            let attenuation = (below * (self.settings.ratio - 1.0)).min(self.settings.range_db);
+            db_to_gain(-attenuation)
+        };
+
+        let coeff = if target > self.gain { self.attack_coeff } else { self.release_coeff };
+        self.gain += (target - self.gain) * coeff;
+        sample * self.gain
+    }
+
+    fn update_coefficients(&mut self) {
+        self.detector_release = (-1.0 / (DETECTOR_RELEASE_MS / 1000.0 * self.sample_rate)).exp();
+        self.attack_coeff = one_pole(self.settings.attack_ms, self.sample_rate);
+        self.release_coeff = one_pole(self.settings.release_ms, self.sample_rate);
+        self.hold_samples = (self.settings.hold_ms / 1000.0 * self.sample_rate) as u32;
+    }
+}
+
+/// Mic compressor settings
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompressorSettings {
+    pub enabled: bool,
+    /// Level above which the mic is compressed
+    pub threshold_db: f32,
+    /// Input dB per output dB above the threshold
+    pub ratio: f32,
+    /// Gain added after compression
+    pub makeup_db: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+}
+
+impl Default for CompressorSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold_db: -24.0,
+            ratio: 3.0,
+            makeup_db: 6.0,
+            attack_ms: 5.0,
+            release_ms: 100.0,
+        }
+    }
+}
+
+/// Current gain reduction of the dynamics stages, in dB (0 = none)
+#[derive(Clone, Copy, Debug, Default, serde::Serialize)]
+pub struct GainReduction {
+    pub limiter_db: f32,
+    pub compressor_db: f32,
+}
+
+/// Compressor settings shared with the output callback, plus its current gain reduction
+#[derive(Clone)]
+pub(super) struct CompressorControls {
+    shared: Arc<CompressorShared>,
+}
+
+struct CompressorShared {
+    enabled: AtomicBool,
+    threshold_db: AtomicU32,
+    ratio: AtomicU32,
+    makeup_db: AtomicU32,
+    attack_ms: AtomicU32,
+    release_ms: AtomicU32,
+    /// Gain reduction at the end of the last buffer, in dB
+    reduction_db: AtomicU32,
+}
+
+impl CompressorControls {
+    pub fn new() -> Self {
+        let controls = Self {
+            shared: Arc::new(CompressorShared {
+                enabled: AtomicBool::new(false),
+                threshold_db: AtomicU32::new(0),
+                ratio: AtomicU32::new(0),
+                makeup_db: AtomicU32::new(0),
+                attack_ms: AtomicU32::new(0),
+                release_ms: AtomicU32::new(0),
+                reduction_db: AtomicU32::new(0),
+            }),
+        };
+        controls.set(&CompressorSettings::default());
+        controls
+    }
+
+    /// Called from the control side
+    pub fn set(&self, settings: &CompressorSettings) {
+        let shared = &self.shared;
+        let store = |atomic: &AtomicU32, value: f32| atomic.store(value.to_bits(), Ordering::Relaxed);
+        store(&shared.threshold_db, settings.threshold_db.clamp(-60.0, 0.0));
+        store(&shared.ratio, settings.ratio.clamp(1.0, 20.0));
+        store(&shared.makeup_db, settings.makeup_db.clamp(0.0, 24.0));
+        store(&shared.attack_ms, settings.attack_ms.clamp(0.1, 100.0));
+        store(&shared.release_ms, settings.release_ms.clamp(1.0, 2000.0));
+        shared.enabled.store(settings.enabled, Ordering::Relaxed);
+    }
+
+    fn settings(&self) -> CompressorSettings {
+        let shared = &self.shared;
+        let load = |atomic: &AtomicU32| f32::from_bits(atomic.load(Ordering::Relaxed));
+        CompressorSettings {
+            enabled: shared.enabled.load(Ordering::Relaxed),
+            threshold_db: load(&shared.threshold_db),
+            ratio: load(&shared.ratio),
+            makeup_db: load(&shared.makeup_db),
+            attack_ms: load(&shared.attack_ms),
+            release_ms: load(&shared.release_ms),
+        }
+    }
+
+    /// Current gain reduction in dB
+    pub fn reduction_db(&self) -> f32 {
+        f32::from_bits(self.shared.reduction_db.load(Ordering::Relaxed))
+    }
+}
+
+/// Feed-forward mic compressor with makeup gain
+pub(super) struct Compressor {
+    controls: CompressorControls,
+    settings: CompressorSettings,
+    sample_rate: f32,
+    detector: f32,
+    detector_release: f32,
+    /// Smoothed gain reduction in dB
+    reduction: f32,
+    attack_coeff: f32,
+    release_coeff: f32,
+    makeup: f32,
+}
+
+impl Compressor {
+    pub fn new(controls: CompressorControls, sample_rate: f32) -> Self {
+        let mut compressor = Self {
+            settings: controls.settings(),
+            controls,
+            sample_rate,
+            detector: 0.0,
+            detector_release: 0.0,
+            reduction: 0.0,
+            attack_coeff: 0.0,
+            release_coeff: 0.0,
+            makeup: 1.0,
+        };
+        compressor.update_coefficients();
+        compressor
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.update_coefficients();
+    }
+
+    /// Pick up setting changes; called once per buffer
+    pub fn begin_block(&mut self) {
+        let settings = self.controls.settings();
+        if settings != self.settings {
+            self.settings = settings;
+            self.update_coefficients();
+        }
+    }
+
+    /// Publish the gain reduction for the UI; called once per buffer
+    pub fn end_block(&self) {
+        let reduction = if self.settings.enabled { self.reduction } else { 0.0 };
+        self.controls.shared.reduction_db.store(reduction.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        if !self.settings.enabled {
+            return sample;
+        }
+
+        self.detector = sample.abs().max(self.detector * self.detector_release);
+        let level_db = 20.0 * self.detector.max(1e-6).log10();
+        let over = level_db - self.settings.threshold_db;
+        let target = if over > 0.0 { over * (1.0 - 1.0 / self.settings.ratio) } else { 0.0 };
+        let coeff = if target > self.reduction { self.attack_coeff } else { self.release_coeff };
+        self.reduction += (target - self.reduction) * coeff;
+        sample * db_to_gain(-self.reduction) * self.makeup
+    }
+
+    fn update_coefficients(&mut self) {
+        self.detector_release = (-1.0 / (DETECTOR_RELEASE_MS / 1000.0 * self.sample_rate)).exp();
+        self.attack_coeff = one_pole(self.settings.attack_ms, self.sample_rate);
+        self.release_coeff = one_pole(self.settings.release_ms, self.sample_rate);
+        self.makeup = db_to_gain(self.settings.makeup_db);
+    }
+}
+
+/// Look-ahead peak limiter for the final output bus.
+/// The gain needed for each incoming sample is held (sliding minimum) over
+/// the look-ahead window and then box-averaged over the same length, so the
+/// gain has fully ramped down by the time the delayed peak comes out and the
+/// output never exceeds the ceiling. Recovery uses a one-pole release.
+pub(super) struct Limiter {
+    ceiling: f32,
+    lookahead: usize,
+    /// Delayed input, `lookahead` samples
+    delay: Vec<f32>,
+    /// Gain each of the last `lookahead + 1` inputs needs
+    needed: Vec<f32>,
+    /// Sliding minimum of `needed` for the last `lookahead` samples
+    held: Vec<f32>,
+    held_sum: f64,
+    position: usize,
+    gain: f32,
+    release_coeff: f32,
+    /// Lowest gain in the current buffer, for metering
+    lowest_gain: f32,
+    reduction_db: Arc<AtomicU32>,
+}
+
+impl Limiter {
+    pub fn new(reduction_db: Arc<AtomicU32>, sample_rate: f32) -> Self {
+        let mut limiter = Self {
+            ceiling: db_to_gain(LIMITER_CEILING_DB),
+            lookahead: 0,
+            delay: Vec::new(),
+            needed: Vec::new(),
+            held: Vec::new(),
+            held_sum: 0.0,
+            position: 0,
+            gain: 1.0,
+            release_coeff: 0.0,
+            lowest_gain: 1.0,
+            reduction_db,
+        };
+        limiter.set_sample_rate(sample_rate);
+        limiter
+    }
+
+    /// Resets the delay line; not for use while the stream is running
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        let lookahead = ((LIMITER_LOOKAHEAD_MS / 1000.0 * sample_rate).round() as usize).max(1);
+        self.lookahead = lookahead;
+        self.delay = vec![0.0; lookahead];
+        self.needed = vec![1.0; lookahead + 1];
+        self.held = vec![1.0; lookahead];
+        self.held_sum = lookahead as f64;
+        self.position = 0;
+        self.gain = 1.0;
+        self.release_coeff = one_pole(LIMITER_RELEASE_MS, sample_rate);
+    }
+
+    /// Samples by which the output lags the input
+    pub fn lookahead(&self) -> usize {
+        self.lookahead
+    }
+
+    /// Start metering a new buffer
+    pub fn begin_block(&mut self) {
+        self.lowest_gain = self.gain;
+    }
+
+    /// Publish the deepest gain reduction of the buffer; called once per buffer
+    pub fn end_block(&self) {
+        let reduction = -20.0 * self.lowest_gain.max(1e-6).log10();
+        self.reduction_db.store(reduction.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let magnitude = sample.abs();
+        let needed = if magnitude > self.ceiling { self.ceiling / magnitude } else { 1.0 };
+        self.needed[self.position % (self.lookahead + 1)] = needed;
+        let held = self.needed.iter().fold(1.0_f32, |lowest, g| lowest.min(*g));
+
+        let slot = self.position % self.lookahead;
+        self.held_sum += held as f64 - self.held[slot] as f64;
+        self.held[slot] = held;
+        let target = (self.held_sum / self.lookahead as f64) as f32;
+        self.gain = if target < self.gain {
+            target
+        } else {
+            self.gain + (target - self.gain) * self.release_coeff
+        };
+        self.lowest_gain = self.lowest_gain.min(self.gain);
+
+        let delayed = self.delay[slot];
+        self.delay[slot] = sample;
+        self.position = self.position.wrapping_add(1);
+        delayed * self.gain
+    }
+}
+
+/// Smoothing coefficient of a one-pole filter with time constant `ms`
+fn one_pole(ms: f32, sample_rate: f32) -> f32 {
+    1.0 - (-1.0 / (ms.max(0.01) / 1000.0 * sample_rate)).exp()
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10.0_f32.powf(db / 20.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = 48000.0;
+
+    fn gate(settings: GateSettings) -> NoiseGate {
+        let controls = GateControls::new();
+        controls.set(&settings);
+        NoiseGate::new(controls, RATE)
+    }
+
+    /// Run a 600 Hz tone at `db` through the gate and return the final gain
+    fn settle(gate: &mut NoiseGate, db: f32, seconds: f32) -> f32 {
+        let amplitude = db_to_gain(db);
+        for i in 0..(seconds * RATE) as usize {
+            gate.process(amplitude * (2.0 * std::f32::consts::PI * 600.0 * i as f32 / RATE).sin());
+        }
+        gate.end_block();
+        gate.controls.level()
+    }
+
+    #[test]
+    fn test_gate_opens_holds_and_closes() {
+        let settings = GateSettings { enabled: true, ..Default::default() };
+        let mut gate = gate(settings);
+
+        // Speech-level signal passes at unity
+        assert!((settle(&mut gate, -20.0, 0.1) - 1.0).abs() < 1e-3);
+
+        // Clatter below the threshold stays open through the hold time...
+        assert!(settle(&mut gate, -70.0, 0.05) > 0.99);
+        // ...then closes to the range floor
+        let closed = settle(&mut gate, -70.0, 2.0);
+        assert!((closed - db_to_gain(-40.0)).abs() < 1e-3, "closed gain {}", closed);
+
+        // Disabled: untouched, and reported as open
+        gate.controls.set(&GateSettings { enabled: false, ..settings });
+        gate.begin_block();
+        assert_eq!(gate.process(0.001), 0.001);
+        assert_eq!(settle(&mut gate, -70.0, 0.01), 1.0);
+    }
+
+    #[test]
+    fn test_expander_ratio() {
+        let mut gate = gate(GateSettings { enabled: true, ratio: 2.0, hold_ms: 0.0, ..Default::default() });
+        // 10 dB under the threshold at 2:1 comes out another 10 dB down
+        let gain = settle(&mut gate, -55.0, 1.0);
+        assert!((20.0 * gain.log10() + 10.0).abs() < 0.5, "gain {} dB", 20.0 * gain.log10());
+    }
+
+    #[test]
+    fn test_compressor_ratio_and_makeup() {
+        let controls = CompressorControls::new();
+        controls.set(&CompressorSettings { enabled: true, threshold_db: -30.0, ratio: 4.0, makeup_db: 6.0, ..Default::default() });
+        let mut compressor = Compressor::new(controls.clone(), RATE);
+        // A steady -10 dB level is 20 dB over: 15 dB of reduction, 6 dB back
+        let mut out = 0.0;
+        for _ in 0..RATE as usize {
+            out = compressor.process(db_to_gain(-10.0));
+        }
+        compressor.end_block();
+        assert!((controls.reduction_db() - 15.0).abs() < 0.1, "reduction {}", controls.reduction_db());
+        assert!((20.0 * out.log10() + 19.0).abs() < 0.1, "output {} dB", 20.0 * out.log10());
+    }
+
+    #[test]
+    fn test_compressor_holds_steady_on_a_tone() {
+        let controls = CompressorControls::new();
+        controls.set(&CompressorSettings { enabled: true, threshold_db: -30.0, ratio: 4.0, makeup_db: 0.0, ..Default::default() });
+        let mut compressor = Compressor::new(controls, RATE);
+        // Once the attack has settled, the gain follows the tone's level, not its waveform
+        let tone = |i: usize| db_to_gain(-10.0) * (2.0 * std::f32::consts::PI * 600.0 * i as f32 / RATE).sin();
+        let gains: Vec<f32> = (0..RATE as usize)
+            .map(|i| (i, compressor.process(tone(i))))
+            .skip(RATE as usize / 2)
+            .filter(|(i, _)| tone(*i).abs() > 0.1)
+            .map(|(i, out)| 20.0 * (out / tone(i)).log10())
+            .collect();
+        let (min, max) = gains.iter().fold((f32::MAX, f32::MIN), |(lo, hi), g| (lo.min(*g), hi.max(*g)));
+        assert!(max - min < 0.5, "gain swings {} dB", max - min);
+        // Peaks 20 dB over at 4:1
+        assert!((min + 15.0).abs() < 0.5, "gain {} dB", min);
+    }
+
+    #[test]
+    fn test_limiter_holds_ceiling() {
+        let reduction = Arc::new(AtomicU32::new(0));
+        let mut limiter = Limiter::new(Arc::clone(&reduction), RATE);
+        let ceiling = db_to_gain(LIMITER_CEILING_DB);
+        // Quiet tone, then a sudden overload: delayed but never over the ceiling
+        let input: Vec<f32> = (0..9600)
+            .map(|i| {
+                let amplitude = if i < 4800 { 0.3 } else { 1.8 };
+                amplitude * (2.0 * std::f32::consts::PI * 600.0 * i as f32 / RATE).sin()
+            })
+            .collect();
+        limiter.begin_block();
+        let output: Vec<f32> = input.iter().map(|s| limiter.process(*s)).collect();
+        limiter.end_block();
+
+        let lookahead = limiter.lookahead();
+        assert_eq!(lookahead, 48);
+        for (out, original) in output[lookahead..4800].iter().zip(&input) {
+            assert!((out - original).abs() < 1e-6);
+        }
+        assert!(output.iter().all(|s| s.abs() <= ceiling + 1e-6));
+        assert!(output[4800..].iter().any(|s| s.abs() > ceiling - 0.01));
+        let reduction_db = f32::from_bits(reduction.load(Ordering::Relaxed));
+        assert!((reduction_db - (20.0 * (1.8 / ceiling).log10())).abs() < 0.1, "reduction {}", reduction_db);
+    }
+}
@@ -0,0 +1,104 @@
+use std::f32::consts::PI;
+
+/// Windowed power spectrum of fixed-size real frames (radix-2 FFT).
+/// Everything is allocated up front, so `power_spectrum` is safe to call
+/// from an audio callback.
+pub(super) struct Spectrum {
+    size: usize,
+    window: Vec<f32>,
+    /// Sum of the window, to scale a full-scale sine's bin to 1.0
+    window_gain: f32,
+    twiddles: Vec<(f32, f32)>,
+    bit_reverse: Vec<usize>,
+    re: Vec<f32>,
+    im: Vec<f32>,
+}
+
+impl Spectrum {
+    /// `size` must be a power of two
+    pub fn new(size: usize) -> Self {
+        assert!(size.is_power_of_two() && size >= 2, "FFT size must be a power of two");
+        let bits = size.trailing_zeros();
+        // Hann window
+        let window: Vec<f32> = (0..size)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos())
+            .collect();
+        let window_gain = window.iter().sum();
+        Self {
+            size,
+            window,
+            window_gain,
+            twiddles: (0..size / 2)
+                .map(|k| {
+                    let angle = -2.0 * PI * k as f32 / size as f32;
+                    (angle.cos(), angle.sin())
+                })
+                .collect(),
+            bit_reverse: (0..size).map(|i| i.reverse_bits() >> (usize::BITS - bits)).collect(),
+            re: vec![0.0; size],
+            im: vec![0.0; size],
+        }
+    }
+
+    /// Number of bins `power_spectrum` writes (DC to Nyquist)
+    pub fn bins(&self) -> usize {
+        self.size / 2 + 1
+    }
+
+    /// Power of each bin of the windowed `frame`, scaled so a full-scale
+    /// sine centred on a bin reads 1.0
+    pub fn power_spectrum(&mut self, frame: &[f32], power: &mut [f32]) {
+        let n = self.size;
+        for i in 0..n {
+            let j = self.bit_reverse[i];
+            self.re[j] = frame.get(i).copied().unwrap_or(0.0) * self.window[i];
+            self.im[j] = 0.0;
+        }
+
+        // Iterative Cooley-Tukey butterflies
+        let mut len = 2;
+        while len <= n {
+            let half = len / 2;
+            let stride = n / len;
+            for start in (0..n).step_by(len) {
+                for k in 0..half {
+                    let (wr, wi) = self.twiddles[k * stride];
+                    let (a, b) = (start + k, start + k + half);
+                    let tr = self.re[b] * wr - self.im[b] * wi;
+                    let ti = self.re[b] * wi + self.im[b] * wr;
+                    self.re[b] = self.re[a] - tr;
+                    self.im[b] = self.im[a] - ti;
+                    self.re[a] += tr;
+                    self.im[a] += ti;
+                }
+            }
+            len *= 2;
+        }
+
+        let scale = 2.0 / self.window_gain;
+        for (k, out) in power.iter_mut().take(self.bins()).enumerate() {
+            let (re, im) = (self.re[k] * scale, self.im[k] * scale);
+            *out = re * re + im * im;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sine_lands_in_its_bin() {
+        let mut spectrum = Spectrum::new(512);
+        let bin = 37;
+        let frame: Vec<f32> = (0..512).map(|i| (2.0 * PI * bin as f32 * i as f32 / 512.0).sin()).collect();
+        let mut power = vec![0.0; spectrum.bins()];
+        spectrum.power_spectrum(&frame, &mut power);
+
+        assert!((power[bin] - 1.0).abs() < 1e-3, "bin power {}", power[bin]);
+        // Hann leaks into the neighbours only
+        assert!((power[bin + 1] - 0.25).abs() < 1e-3);
+        let elsewhere: f32 = power.iter().enumerate().filter(|(k, _)| k.abs_diff(bin) > 1).map(|(_, p)| p).sum();
+        assert!(elsewhere < 1e-6, "leakage {}", elsewhere);
+    }
+}
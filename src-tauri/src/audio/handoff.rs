@@ -0,0 +1,163 @@
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Arc;
+
+use ringbuf::traits::{Consumer, Observer, Producer, Split};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
+
+/// Room for values a callback hands back. The control side empties it
+/// before every hand-over and a callback takes at most one value per
+/// hand-over, so it never holds more than one.
+const RETURN_SLOTS: usize = 2;
+
+/// A setting too big for an atomic, handed to the callbacks without locks.
+/// Each callback subscribes once, off the real-time path, and gets its own
+/// slot. The control side publishes a new value into every slot with an
+/// atomic swap; the callback swaps it in and sends the value it replaced
+/// back over a ring, so neither side ever waits and every value is freed
+/// on the control side.
+#[derive(Clone)]
+pub(super) struct Handoff<T> {
+    state: Arc<parking_lot::Mutex<HandoffState<T>>>,
+}
+
+/// Only ever locked on the control side
+struct HandoffState<T> {
+    latest: Arc<T>,
+    subscribers: Vec<Subscriber<T>>,
+}
+
+struct Subscriber<T> {
+    slot: Arc<Slot<T>>,
+    returns: HeapCons<Arc<T>>,
+}
+
+/// One callback's mailbox: a value waiting to be picked up, or null
+struct Slot<T> {
+    next: AtomicPtr<T>,
+    _owns: PhantomData<Arc<T>>,
+}
+
+impl<T> Slot<T> {
+    /// Put `value` in the slot, returning whatever was waiting there
+    fn swap(&self, value: Option<Arc<T>>) -> Option<Arc<T>> {
+        let new = value.map_or(ptr::null_mut(), |value| Arc::into_raw(value).cast_mut());
+        let old = self.next.swap(new, Ordering::AcqRel);
+        // Safety: the slot only ever holds pointers from `Arc::into_raw`,
+        // and the swap hands each one to exactly one side
+        (!old.is_null()).then(|| unsafe { Arc::from_raw(old) })
+    }
+}
+
+impl<T> Drop for Slot<T> {
+    fn drop(&mut self) {
+        self.swap(None);
+    }
+}
+
+impl<T: Send + Sync> Handoff<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            state: Arc::new(parking_lot::Mutex::new(HandoffState {
+                latest: Arc::new(value),
+                subscribers: Vec::new(),
+            })),
+        }
+    }
+
+    /// Called from the control side
+    pub fn set(&self, value: T) {
+        let mut state = self.state.lock();
+        let HandoffState { latest, subscribers } = &mut *state;
+        *latest = Arc::new(value);
+        // Forget callbacks whose stream has gone
+        subscribers.retain(|subscriber| Arc::strong_count(&subscriber.slot) > 1);
+        for subscriber in subscribers.iter_mut() {
+            // Values handed back, or never picked up, are freed here
+            subscriber.returns.clear();
+            subscriber.slot.swap(Some(Arc::clone(latest)));
+        }
+    }
+
+    /// Called when a callback is built, off the real-time path
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.state.lock();
+        state.subscribers.retain(|subscriber| Arc::strong_count(&subscriber.slot) > 1);
+        let (returns, returned) = HeapRb::new(RETURN_SLOTS).split();
+        let slot = Arc::new(Slot {
+            next: AtomicPtr::new(ptr::null_mut()),
+            _owns: PhantomData,
+        });
+        state.subscribers.push(Subscriber {
+            slot: Arc::clone(&slot),
+            returns: returned,
+        });
+        Receiver {
+            slot,
+            returns,
+            current: Arc::clone(&state.latest),
+        }
+    }
+}
+
+/// A callback's end of a `Handoff`
+pub(super) struct Receiver<T> {
+    slot: Arc<Slot<T>>,
+    returns: HeapProd<Arc<T>>,
+    current: Arc<T>,
+}
+
+impl<T> Receiver<T> {
+    pub fn get(&self) -> &T {
+        &self.current
+    }
+
+    /// Called from the callback: pick up a new value, if there is one, and
+    /// pass it to `apply`. The value it replaces goes back to the control
+    /// side only after `apply`, so anything `apply` drops that still points
+    /// into it is never the last reference.
+    pub fn update(&mut self, apply: impl FnOnce(&T)) {
+        // Only take a value once the old one can be handed back
+        if self.returns.is_full() {
+            return;
+        }
+        let Some(next) = self.slot.swap(None) else {
+            return;
+        };
+        let old = std::mem::replace(&mut self.current, next);
+        apply(&self.current);
+        let _ = self.returns.try_push(old);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values_are_freed_on_the_control_side() {
+        let handoff = Handoff::new(vec![1.0_f32]);
+        let mut receiver = handoff.subscribe();
+        assert_eq!(receiver.get(), &vec![1.0]);
+
+        handoff.set(vec![2.0]);
+        handoff.set(vec![3.0]);
+        let mut applied = Vec::new();
+        receiver.update(|value| applied.push(value.clone()));
+        // Only the newest value arrives; the one it replaced waits to go back
+        assert_eq!(applied, vec![vec![3.0]]);
+        assert_eq!(receiver.get(), &vec![3.0]);
+        assert_eq!(receiver.returns.occupied_len(), 1);
+        receiver.update(|_| panic!("nothing new"));
+
+        // The next hand-over frees it, and a dropped receiver is forgotten
+        let current = Arc::clone(&receiver.current);
+        handoff.set(vec![4.0]);
+        assert_eq!(receiver.returns.occupied_len(), 0);
+        drop(receiver);
+        handoff.set(vec![5.0]);
+        assert!(handoff.state.lock().subscribers.is_empty());
+        assert_eq!(Arc::strong_count(&current), 1);
+    }
+}
@@ -0,0 +1,246 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long a stream may go without a callback before it is considered dead
+const STALL_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// First retry delay after a failed rebuild (doubles on each further failure)
+const BACKOFF_BASE: Duration = Duration::from_millis(250);
+
+/// Upper bound for the retry delay
+const BACKOFF_MAX: Duration = Duration::from_secs(8);
+
+/// Which of the engine's streams an event refers to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub enum StreamKind {
+    Input,
+    Output,
+    Local,
+}
+
+/// Reported to the frontend for every rebuild attempt
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct RecoveryEvent {
+    pub stream: StreamKind,
+    /// Why the stream was rebuilt (stream error or stalled callbacks)
+    pub reason: String,
+    /// 1 for the first attempt, incremented on each retry
+    pub attempt: u32,
+    /// Whether this attempt brought the stream back
+    pub recovered: bool,
+}
+
+/// Heartbeat and error flag shared with a stream's callbacks
+#[derive(Clone)]
+pub(super) struct StreamMonitor {
+    callbacks: Arc<AtomicU32>,
+    error: Arc<parking_lot::Mutex<Option<String>>>,
+}
+
+impl StreamMonitor {
+    pub(super) fn new() -> Self {
+        Self {
+            callbacks: Arc::new(AtomicU32::new(0)),
+            error: Arc::new(parking_lot::Mutex::new(None)),
+        }
+    }
+
+    /// Called at the top of every data callback
+    pub(super) fn beat(&self) {
+        self.callbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Called from the cpal error callback
+    pub(super) fn report_error(&self, err: String) {
+        let mut slot = self.error.lock();
+        if slot.is_none() {
+            *slot = Some(err);
+        }
+    }
+
+    fn count(&self) -> u32 {
+        self.callbacks.load(Ordering::Relaxed)
+    }
+
+    fn take_error(&self) -> Option<String> {
+        self.error.lock().take()
+    }
+}
+
+struct PendingRecovery {
+    reason: String,
+    attempt: u32,
+    next_try: Instant,
+}
+
+/// Watchdog state for one stream, polled from the audio thread
+pub(super) struct StreamWatch {
+    pub(super) kind: StreamKind,
+    pub(super) monitor: StreamMonitor,
+    /// The stream is expected to be running
+    active: bool,
+    last_count: u32,
+    last_progress: Instant,
+    pending: Option<PendingRecovery>,
+}
+
+impl StreamWatch {
+    pub(super) fn new(kind: StreamKind) -> Self {
+        Self {
+            kind,
+            monitor: StreamMonitor::new(),
+            active: false,
+            last_count: 0,
+            last_progress: Instant::now(),
+            pending: None,
+        }
+    }
+
+    /// The stream was (re)started and should now be producing callbacks
+    pub(super) fn arm(&mut self, now: Instant) {
+        self.monitor.take_error();
+        self.active = true;
+        self.last_count = self.monitor.count();
+        self.last_progress = now;
+        self.pending = None;
+    }
+
+    /// Whether the stream is expected to be running (or being retried)
+    pub(super) fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// The stream was stopped on purpose
+    pub(super) fn disarm(&mut self) {
+        self.active = false;
+        self.pending = None;
+    }
+
+    /// Check the stream. Returns the reason and attempt number when a
+    /// rebuild is due now.
+    pub(super) fn poll(&mut self, now: Instant) -> Option<(String, u32)> {
+        if !self.active {
+            return None;
+        }
+
+        if self.pending.is_none() {
+            let reason = if let Some(err) = self.monitor.take_error() {
+                Some(format!("stream error: {}", err))
+            } else {
+                let count = self.monitor.count();
+                if count != self.last_count {
+                    self.last_count = count;
+                    self.last_progress = now;
+                    None
+                } else if now.duration_since(self.last_progress) >= STALL_TIMEOUT {
+                    Some(format!(
+                        "no callbacks for {} ms",
+                        now.duration_since(self.last_progress).as_millis()
+                    ))
+                } else {
+                    None
+                }
+            };
+            self.pending = reason.map(|reason| PendingRecovery { reason, attempt: 0, next_try: now });
+        }
+
+        match self.pending.as_mut() {
+            Some(pending) if now >= pending.next_try => {
+                pending.attempt += 1;
+                Some((pending.reason.clone(), pending.attempt))
+            }
+            _ => None,
+        }
+    }
+
+    /// The stream could not be opened; keep it expected to run and count
+    /// the open as a failed first attempt, so it is retried with backoff
+    pub(super) fn open_failed(&mut self, reason: String, now: Instant) {
+        self.active = true;
+        self.pending = Some(PendingRecovery {
+            reason,
+            attempt: 1,
+            next_try: now + backoff_delay(1),
+        });
+    }
+
+    /// The last rebuild attempt failed; schedule the next one
+    pub(super) fn retry_later(&mut self, now: Instant) {
+        if let Some(pending) = self.pending.as_mut() {
+            pending.next_try = now + backoff_delay(pending.attempt);
+        }
+    }
+}
+
+/// Delay before retrying after `attempt` failed rebuilds
+fn backoff_delay(attempt: u32) -> Duration {
+    let factor = 1u32 << attempt.saturating_sub(1).min(6);
+    (BACKOFF_BASE * factor).min(BACKOFF_MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stall_detected_after_timeout() {
+        let start = Instant::now();
+        let mut watch = StreamWatch::new(StreamKind::Input);
+        watch.arm(start);
+
+        watch.monitor.beat();
+        assert!(watch.poll(start + Duration::from_millis(500)).is_none());
+        // No further callbacks
+        assert!(watch.poll(start + Duration::from_millis(1500)).is_none());
+        let (reason, attempt) = watch.poll(start + Duration::from_millis(2100)).unwrap();
+        assert!(reason.contains("no callbacks"), "{}", reason);
+        assert_eq!(attempt, 1);
+    }
+
+    #[test]
+    fn test_error_triggers_rebuild_with_backoff() {
+        let start = Instant::now();
+        let mut watch = StreamWatch::new(StreamKind::Output);
+        watch.arm(start);
+
+        watch.monitor.report_error("device unplugged".to_string());
+        let (reason, attempt) = watch.poll(start).unwrap();
+        assert!(reason.contains("device unplugged"));
+        assert_eq!(attempt, 1);
+
+        // First rebuild fails: nothing until the backoff elapses
+        watch.retry_later(start);
+        assert!(watch.poll(start + Duration::from_millis(100)).is_none());
+        let (_, attempt) = watch.poll(start + BACKOFF_BASE).unwrap();
+        assert_eq!(attempt, 2);
+
+        // Successful rebuild clears the pending recovery
+        watch.arm(start + BACKOFF_BASE);
+        watch.monitor.beat();
+        assert!(watch.poll(start + BACKOFF_BASE + Duration::from_millis(10)).is_none());
+    }
+
+    #[test]
+    fn test_failed_open_is_retried() {
+        let start = Instant::now();
+        let mut watch = StreamWatch::new(StreamKind::Local);
+        watch.open_failed("failed to open: device unplugged".to_string(), start);
+
+        assert!(watch.poll(start).is_none());
+        let (reason, attempt) = watch.poll(start + BACKOFF_BASE).unwrap();
+        assert!(reason.contains("device unplugged"));
+        assert_eq!(attempt, 2);
+
+        // Stopping on purpose drops the retry
+        watch.disarm();
+        assert!(watch.poll(start + BACKOFF_MAX).is_none());
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        assert_eq!(backoff_delay(1), BACKOFF_BASE);
+        assert_eq!(backoff_delay(2), BACKOFF_BASE * 2);
+        assert_eq!(backoff_delay(30), BACKOFF_MAX);
+    }
+}
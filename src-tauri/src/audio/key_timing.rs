@@ -0,0 +1,254 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ringbuf::traits::{Consumer, Producer};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
+
+/// Key edges that can be queued per stream before the oldest are dropped
+const KEY_QUEUE_SIZE: usize = 256;
+
+/// How late the software keyer's poll loop may publish an edge it stamped
+const SOFTWARE_KEYER_SLACK: Duration = Duration::from_millis(5);
+
+/// A key down/up edge and the moment it happened
+#[derive(Clone, Copy, Debug)]
+pub struct KeyEdge {
+    pub down: bool,
+    pub at: Instant,
+}
+
+/// Key edge latency settings, read by the output callbacks once per buffer
+#[derive(Clone)]
+pub(super) struct KeyTimingControls {
+    /// Fixed delay applied to every key edge so it lands sample-accurately
+    /// (0 = one buffer period of the stream playing it)
+    pub latency_ms: Arc<AtomicU32>,
+    /// The software keyer is stepping; its edges are stamped up to a poll in the past
+    pub software_keyer: Arc<AtomicBool>,
+}
+
+impl KeyTimingControls {
+    pub fn new() -> Self {
+        Self {
+            latency_ms: Arc::new(AtomicU32::new(0.0_f32.to_bits())),
+            software_keyer: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Latency budget for a buffer of `frames` at `sample_rate`. While the
+    /// software keyer runs, the budget never drops below one buffer period
+    /// plus the poll slack, so its back-dated edges still land on their sample.
+    fn latency(&self, frames: usize, sample_rate: f32) -> Duration {
+        let period = Duration::from_secs_f64(frames as f64 / sample_rate as f64);
+        let latency_ms = f32::from_bits(self.latency_ms.load(Ordering::Relaxed));
+        let configured = if latency_ms > 0.0 {
+            Duration::from_secs_f32(latency_ms / 1000.0)
+        } else {
+            period
+        };
+        if self.software_keyer.load(Ordering::Relaxed) {
+            configured.max(period + SOFTWARE_KEYER_SLACK)
+        } else {
+            configured
+        }
+    }
+
+    /// How far behind its stamp to render each edge in a buffer of `frames`.
+    /// `output_delay` is how much later than rendered the stream's samples
+    /// leave it (the limiter look-ahead on the main output), so every stream
+    /// plays an edge stamped `at` at `at + latency`.
+    pub fn edge_delay(&self, frames: usize, sample_rate: f32, output_delay: Duration) -> Duration {
+        self.latency(frames, sample_rate).saturating_sub(output_delay)
+    }
+}
+
+/// Control-side end of the key edge queues, one per output stream.
+/// Producers are only touched from control threads; each stream callback
+/// owns the consumer of its queue.
+pub(super) struct KeyEdgeQueue {
+    main: Arc<HeapRb<KeyEdge>>,
+    local: Arc<HeapRb<KeyEdge>>,
+    producers: parking_lot::Mutex<(HeapProd<KeyEdge>, HeapProd<KeyEdge>)>,
+}
+
+impl KeyEdgeQueue {
+    pub fn new() -> Self {
+        let main = Arc::new(HeapRb::new(KEY_QUEUE_SIZE));
+        let local = Arc::new(HeapRb::new(KEY_QUEUE_SIZE));
+        let producers = parking_lot::Mutex::new((
+            HeapProd::new(Arc::clone(&main)),
+            HeapProd::new(Arc::clone(&local)),
+        ));
+        Self { main, local, producers }
+    }
+
+    /// Queue an edge for both the main and local outputs
+    pub fn push(&self, edge: KeyEdge) {
+        let mut producers = self.producers.lock();
+        let _ = producers.0.try_push(edge);
+        let _ = producers.1.try_push(edge);
+    }
+
+    /// Schedule for a newly opened main output stream
+    pub fn main_schedule(&self) -> KeySchedule {
+        KeySchedule::new(HeapCons::new(Arc::clone(&self.main)))
+    }
+
+    /// Schedule for a newly opened local output stream
+    pub fn local_schedule(&self) -> KeySchedule {
+        KeySchedule::new(HeapCons::new(Arc::clone(&self.local)))
+    }
+}
+
+/// Applies timestamped key edges at the matching sample inside output buffers.
+///
+/// An edge stamped `at` is played at `at + latency`. As long as the latency
+/// budget covers the buffer period plus scheduling jitter, every edge lands on
+/// its exact sample; edges that arrive later than that are applied at the
+/// start of the buffer and counted as late.
+pub(super) struct KeySchedule {
+    events: HeapCons<KeyEdge>,
+    key_down: bool,
+    block_start: Instant,
+    latency: Duration,
+    sample_rate: f32,
+    late: u32,
+    /// Stamp of the last key down edge applied, until taken
+    down_edge: Option<Instant>,
+}
+
+impl KeySchedule {
+    fn new(mut events: HeapCons<KeyEdge>) -> Self {
+        // Drop edges queued while no stream was consuming them
+        events.clear();
+        Self {
+            events,
+            key_down: false,
+            block_start: Instant::now(),
+            latency: Duration::ZERO,
+            sample_rate: 48000.0,
+            late: 0,
+            down_edge: None,
+        }
+    }
+
+    /// Call at the start of each buffer with the time its first sample is rendered
+    pub fn begin_block(&mut self, now: Instant, latency: Duration, sample_rate: f32) {
+        self.block_start = now;
+        self.latency = latency;
+        self.sample_rate = sample_rate;
+    }
+
+    /// Key state for the given frame of the current buffer (call with increasing frames)
+    pub fn key_at(&mut self, frame: usize) -> bool {
+        while let Some(edge) = self.events.try_peek() {
+            let due = edge.at + self.latency;
+            let offset = match due.checked_duration_since(self.block_start) {
+                Some(delay) => (delay.as_secs_f64() * self.sample_rate as f64).round() as usize,
+                None => {
+                    if !self.latency.is_zero() {
+                        self.late += 1;
+                    }
+                    0
+                }
+            };
+            if offset > frame {
+                break;
+            }
+            self.key_down = edge.down;
+            if edge.down {
+                self.down_edge = Some(edge.at);
+            }
+            self.events.try_pop();
+        }
+        self.key_down
+    }
+
+    /// Stamp of the key down edge applied since the last call, if any
+    pub fn take_down_edge(&mut self) -> Option<Instant> {
+        self.down_edge.take()
+    }
+
+    /// Edges that missed the latency budget since the last call
+    pub fn take_late(&mut self) -> u32 {
+        std::mem::take(&mut self.late)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = 48000.0;
+
+    fn samples(n: u64) -> Duration {
+        Duration::from_nanos(n * 1_000_000_000 / RATE as u64)
+    }
+
+    #[test]
+    fn test_edges_land_on_their_sample() {
+        let queue = KeyEdgeQueue::new();
+        let mut schedule = queue.main_schedule();
+        let t0 = Instant::now();
+        let latency = samples(960);
+
+        // Edges 100 and 700 samples after t0 (plus one sample of rounding slack)
+        queue.push(KeyEdge { down: true, at: t0 + samples(101) });
+        queue.push(KeyEdge { down: false, at: t0 + samples(701) });
+
+        // Render in 256-sample buffers starting at t0 + latency
+        let mut keyed = Vec::new();
+        for block in 0..4 {
+            schedule.begin_block(t0 + latency + samples(block * 256), latency, RATE);
+            for frame in 0..256 {
+                keyed.push(schedule.key_at(frame));
+            }
+        }
+
+        let first_down = keyed.iter().position(|&k| k).unwrap();
+        let first_up = keyed[first_down..].iter().position(|&k| !k).unwrap() + first_down;
+        assert!((first_down as i32 - 101).abs() <= 1, "down at {}", first_down);
+        assert!((first_up as i32 - 701).abs() <= 1, "up at {}", first_up);
+        assert_eq!(schedule.take_late(), 0);
+    }
+
+    #[test]
+    fn test_software_keyer_forces_minimum_latency() {
+        let timing = KeyTimingControls::new();
+        timing.latency_ms.store(2.0_f32.to_bits(), Ordering::Relaxed);
+        assert_eq!(timing.latency(480, RATE), Duration::from_millis(2));
+
+        // One 10 ms buffer plus the poll slack
+        timing.software_keyer.store(true, Ordering::Relaxed);
+        assert_eq!(timing.latency(480, RATE), Duration::from_millis(15));
+
+        // A larger configured budget still wins
+        timing.latency_ms.store(25.0_f32.to_bits(), Ordering::Relaxed);
+        assert_eq!(timing.latency(480, RATE), Duration::from_millis(25));
+    }
+
+    #[test]
+    fn test_default_latency_is_one_buffer_period() {
+        let timing = KeyTimingControls::new();
+        assert_eq!(timing.latency(480, RATE), Duration::from_millis(10));
+
+        // Output delay comes off the render delay, never below zero
+        assert_eq!(timing.edge_delay(480, RATE, Duration::from_millis(3)), Duration::from_millis(7));
+        assert_eq!(timing.edge_delay(48, RATE, Duration::from_millis(3)), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_late_edge_applied_at_block_start() {
+        let queue = KeyEdgeQueue::new();
+        let mut schedule = queue.main_schedule();
+        let t0 = Instant::now();
+        let latency = samples(240);
+
+        queue.push(KeyEdge { down: true, at: t0 });
+        // Buffer starts well after the edge was due
+        schedule.begin_block(t0 + samples(1000), latency, RATE);
+        assert!(schedule.key_at(0));
+        assert_eq!(schedule.take_late(), 1);
+    }
+}
@@ -0,0 +1,315 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use cpal::FromSample;
+use ringbuf::traits::{Consumer, Producer};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
+
+use super::pipeline::InputProcessor;
+
+/// Sidetone envelope level that counts as "the tone has started"
+const ENVELOPE_THRESHOLD: f32 = 0.5;
+
+/// Captured level that counts as a tone onset in the loopback test
+const LOOPBACK_THRESHOLD: f32 = 0.05;
+
+/// Quiet time needed before the loopback detector looks for the next onset
+const LOOPBACK_REARM: Duration = Duration::from_millis(20);
+
+/// Measurements that can be queued before the control side collects them
+const MEASUREMENT_QUEUE_SIZE: usize = 1024;
+
+/// Measurements kept for a report
+const MAX_MEASUREMENTS: usize = 10_000;
+
+/// Summary of a set of latency measurements, in milliseconds
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct LatencyStats {
+    pub count: usize,
+    pub min_ms: f32,
+    pub mean_ms: f32,
+    pub p99_ms: f32,
+    /// Standard deviation of the measurements
+    pub jitter_ms: f32,
+}
+
+impl LatencyStats {
+    pub fn from_samples(samples: &[f32]) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let count = sorted.len();
+        let mean = sorted.iter().sum::<f32>() / count as f32;
+        let variance = sorted.iter().map(|s| (s - mean).powi(2)).sum::<f32>() / count as f32;
+        let p99_index = ((count as f32 * 0.99).ceil() as usize).clamp(1, count) - 1;
+        Self {
+            count,
+            min_ms: sorted[0],
+            mean_ms: mean,
+            p99_ms: sorted[p99_index],
+            jitter_ms: variance.sqrt(),
+        }
+    }
+}
+
+/// Control-side end of the latency measurements.
+/// Like the key edge queue, each new output or loopback stream gets a fresh
+/// producer; the previous stream must be dropped first.
+pub(super) struct LatencyMeter {
+    enabled: Arc<AtomicBool>,
+    measurements: Arc<HeapRb<f32>>,
+    onsets: Arc<HeapRb<Instant>>,
+    consumers: parking_lot::Mutex<(HeapCons<f32>, HeapCons<Instant>)>,
+    samples: parking_lot::Mutex<Vec<f32>>,
+}
+
+impl LatencyMeter {
+    pub fn new() -> Self {
+        let measurements = Arc::new(HeapRb::new(MEASUREMENT_QUEUE_SIZE));
+        let onsets = Arc::new(HeapRb::new(MEASUREMENT_QUEUE_SIZE));
+        let consumers = parking_lot::Mutex::new((
+            HeapCons::new(Arc::clone(&measurements)),
+            HeapCons::new(Arc::clone(&onsets)),
+        ));
+        Self {
+            enabled: Arc::new(AtomicBool::new(false)),
+            measurements,
+            onsets,
+            consumers,
+            samples: parking_lot::Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Start (clearing earlier results) or stop measuring key-to-buffer latency
+    pub fn set_enabled(&self, enabled: bool) {
+        if enabled {
+            self.consumers.lock().0.clear();
+            self.samples.lock().clear();
+        }
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Probe for a newly opened main output stream
+    pub fn probe(&self) -> LatencyProbe {
+        LatencyProbe {
+            enabled: Arc::clone(&self.enabled),
+            results: HeapProd::new(Arc::clone(&self.measurements)),
+            pending: None,
+            last_envelope: 0.0,
+        }
+    }
+
+    /// Onset detector for a newly opened loopback capture stream
+    pub fn loopback_detector(&self) -> LoopbackDetector {
+        self.consumers.lock().1.clear();
+        LoopbackDetector::new(HeapProd::new(Arc::clone(&self.onsets)))
+    }
+
+    /// Key-to-buffer latency measured since measuring was enabled
+    pub fn report(&self) -> LatencyStats {
+        let mut samples = self.samples.lock();
+        let mut consumers = self.consumers.lock();
+        while let Some(sample) = consumers.0.try_pop() {
+            if samples.len() < MAX_MEASUREMENTS {
+                samples.push(sample);
+            }
+        }
+        LatencyStats::from_samples(&samples)
+    }
+
+    /// Tone onsets seen by the loopback detector since the last call
+    pub fn take_onsets(&self) -> Vec<Instant> {
+        self.consumers.lock().1.pop_iter().collect()
+    }
+}
+
+/// Measures, inside the output callback, how long after a key down edge was
+/// stamped the sidetone envelope crosses the threshold.
+///
+/// Crossings are timed when the sample leaves the limiter, so the reported
+/// key latency includes the limiter look-ahead as well as the key latency
+/// budget, the same as what the far end hears.
+pub(super) struct LatencyProbe {
+    enabled: Arc<AtomicBool>,
+    results: HeapProd<f32>,
+    /// Timestamp of the key down edge still waiting for its tone
+    pending: Option<Instant>,
+    last_envelope: f32,
+}
+
+impl LatencyProbe {
+    /// A key down edge stamped `at` was applied
+    pub fn key_down(&mut self, at: Instant) {
+        if self.enabled.load(Ordering::Relaxed) {
+            self.pending = Some(at);
+        }
+    }
+
+    /// Feed the envelope of the sample rendered at `sample_time`
+    pub fn envelope(&mut self, envelope: f32, sample_time: impl FnOnce() -> Instant) {
+        let crossed = self.last_envelope < ENVELOPE_THRESHOLD && envelope >= ENVELOPE_THRESHOLD;
+        self.last_envelope = envelope;
+        if crossed {
+            if let Some(at) = self.pending.take() {
+                let latency = sample_time().saturating_duration_since(at);
+                let _ = self.results.try_push(latency.as_secs_f32() * 1000.0);
+            }
+        }
+    }
+}
+
+/// Finds tone onsets in captured loopback audio
+pub(super) struct LoopbackDetector {
+    onsets: HeapProd<Instant>,
+    sample_rate: f32,
+    level: f32,
+    release: f32,
+    armed: bool,
+    quiet_samples: u32,
+}
+
+impl LoopbackDetector {
+    fn new(onsets: HeapProd<Instant>) -> Self {
+        let mut detector = Self {
+            onsets,
+            sample_rate: 48000.0,
+            level: 0.0,
+            release: 0.0,
+            armed: true,
+            quiet_samples: 0,
+        };
+        detector.prepare(48000);
+        detector
+    }
+}
+
+impl InputProcessor for LoopbackDetector {
+    fn prepare(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate as f32;
+        // ~5 ms release so the follower rides over the sine's zero crossings
+        self.release = (-1.0 / (0.005 * self.sample_rate)).exp();
+    }
+
+    fn process<T: Copy>(&mut self, data: &[T], channels: usize, now: Instant)
+    where
+        f32: FromSample<T>,
+    {
+        // `now` is when the buffer was delivered, i.e. just after its last frame
+        let frames = data.len() / channels.max(1);
+        let rearm_samples = (LOOPBACK_REARM.as_secs_f32() * self.sample_rate) as u32;
+
+        for (i, frame) in data.chunks(channels).enumerate() {
+            let sample = frame
+                .iter()
+                .map(|s| <f32 as FromSample<T>>::from_sample_(*s))
+                .sum::<f32>()
+                / channels as f32;
+            self.level = sample.abs().max(self.level * self.release);
+
+            if self.level >= LOOPBACK_THRESHOLD {
+                self.quiet_samples = 0;
+                if self.armed {
+                    self.armed = false;
+                    let age = Duration::from_secs_f64((frames - i) as f64 / self.sample_rate as f64);
+                    let _ = self.onsets.try_push(now.checked_sub(age).unwrap_or(now));
+                }
+            } else if !self.armed {
+                self.quiet_samples += 1;
+                if self.quiet_samples >= rearm_samples {
+                    self.armed = true;
+                }
+            }
+        }
+    }
+}
+
+/// Pair each key pulse with the first onset heard within `window` after it
+/// and return the delays in milliseconds. Pulses with no onset are skipped.
+pub(super) fn match_onsets(pulses: &[Instant], onsets: &[Instant], window: Duration) -> Vec<f32> {
+    let mut remaining = onsets.iter().peekable();
+    let mut delays = Vec::new();
+    for &pulse in pulses {
+        // Onsets before this pulse belong to nothing we sent
+        while remaining.next_if(|onset| **onset < pulse).is_some() {}
+        if let Some(onset) = remaining.next_if(|onset| onset.duration_since(pulse) < window) {
+            delays.push(onset.duration_since(pulse).as_secs_f32() * 1000.0);
+        }
+    }
+    delays
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ringbuf::traits::Split;
+
+    #[test]
+    fn test_stats() {
+        let samples: Vec<f32> = (1..=100).map(|i| i as f32).collect();
+        let stats = LatencyStats::from_samples(&samples);
+        assert_eq!(stats.count, 100);
+        assert_eq!(stats.min_ms, 1.0);
+        assert_eq!(stats.mean_ms, 50.5);
+        assert_eq!(stats.p99_ms, 99.0);
+        assert!((stats.jitter_ms - 28.866).abs() < 0.01);
+
+        assert_eq!(LatencyStats::from_samples(&[]).count, 0);
+    }
+
+    #[test]
+    fn test_match_onsets() {
+        let t0 = Instant::now();
+        let ms = |n: u64| t0 + Duration::from_millis(n);
+        let pulses = [ms(0), ms(300), ms(600)];
+        // Stray onset before the first pulse, none for the second pulse
+        let onsets = [t0, ms(40), ms(650)];
+        let delays = match_onsets(&pulses[1..], &onsets, Duration::from_millis(300));
+        assert_eq!(delays.len(), 1);
+        assert!((delays[0] - 50.0).abs() < 0.01);
+
+        let delays = match_onsets(&pulses, &onsets, Duration::from_millis(300));
+        assert_eq!(delays.len(), 2);
+        assert!(delays[0].abs() < 0.01);
+        assert!((delays[1] - 50.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_loopback_detector_measures_delay() {
+        const RATE: u32 = 48000;
+        let (onsets, mut heard) = HeapRb::new(16).split();
+        let mut detector = LoopbackDetector::new(onsets);
+        detector.prepare(RATE);
+
+        // Pulses 300 ms apart, each heard as an 80 ms 600 Hz burst 37.5 ms later
+        let pulse_frames = [0, RATE as usize * 3 / 10];
+        let delay = RATE as usize * 375 / 10000;
+        let tone = |frame: usize| {
+            pulse_frames
+                .iter()
+                .filter_map(|&pulse| frame.checked_sub(pulse + delay))
+                .filter(|&t| t < RATE as usize * 8 / 100)
+                .map(|t| (2.0 * std::f32::consts::PI * 600.0 * t as f32 / RATE as f32).sin() * 0.5)
+                .sum::<f32>()
+        };
+
+        let t0 = Instant::now();
+        let time_of = |frame: usize| t0 + Duration::from_secs_f64(frame as f64 / RATE as f64);
+        let block: usize = 256;
+        for start in (0..RATE as usize).step_by(block) {
+            let data: Vec<f32> = (start..start + block).map(tone).collect();
+            // Delivered once the last frame is in
+            detector.process(&data, 1, time_of(start + block));
+        }
+
+        let pulses: Vec<Instant> = pulse_frames.iter().map(|&frame| time_of(frame)).collect();
+        let onsets: Vec<Instant> = heard.pop_iter().collect();
+        let delays = match_onsets(&pulses, &onsets, Duration::from_millis(300));
+        assert_eq!(delays.len(), 2, "onsets {:?}", onsets);
+        for delay in delays {
+            assert!((delay - 37.5).abs() < 0.2, "delay {}", delay);
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// Reading for silence; keeps the values finite for the frontend
+const FLOOR_DB: f32 = -120.0;
+
+/// Time constant of the RMS average
+const RMS_WINDOW_MS: f32 = 300.0;
+
+/// How long the peak hold keeps its value before falling back to the peak
+const PEAK_HOLD_MS: f32 = 1500.0;
+
+/// Fall rate of the (otherwise instant) peak reading
+const PEAK_FALL_DB_PER_S: f32 = 20.0;
+
+/// EBU R128 loudness blocks and short-term window
+const LOUDNESS_BLOCK_MS: f32 = 100.0;
+const SHORT_TERM_BLOCKS: usize = 30;
+
+/// True-peak oversampling (BS.1770 annex 2) and taps per polyphase branch
+const OVERSAMPLING: usize = 4;
+const PHASE_TAPS: usize = 12;
+
+/// Metered points in the output mix
+#[derive(Clone, Copy)]
+pub(super) enum Bus {
+    /// Processed mic, after ducking and mic volume
+    Mic,
+    /// Sidetone going to the output
+    Sidetone,
+    /// Final output after the limiter
+    Output,
+}
+
+/// Readings for one bus
+#[derive(Clone, Copy, Debug, serde::Serialize)]
+pub struct BusMeters {
+    pub rms_db: f32,
+    /// True peak (dBTP), falling back slowly after each peak
+    pub peak_db: f32,
+    /// Highest true peak of the last 1.5 s
+    pub peak_hold_db: f32,
+    /// Samples at or over full scale since the stream started
+    pub clips: u32,
+    /// Short-term loudness (3 s window, EBU R128)
+    pub lufs_short_term: f32,
+}
+
+impl Default for BusMeters {
+    fn default() -> Self {
+        Self {
+            rms_db: FLOOR_DB,
+            peak_db: FLOOR_DB,
+            peak_hold_db: FLOOR_DB,
+            clips: 0,
+            lufs_short_term: FLOOR_DB,
+        }
+    }
+}
+
+/// Readings for the mic, sidetone and output buses
+#[derive(Clone, Copy, Debug, Default, serde::Serialize)]
+pub struct Meters {
+    pub mic: BusMeters,
+    pub sidetone: BusMeters,
+    pub output: BusMeters,
+}
+
+/// Meter readings published by the output callback
+#[derive(Clone)]
+pub(super) struct MeterControls {
+    shared: Arc<[BusShared; 3]>,
+}
+
+struct BusShared {
+    rms_db: AtomicU32,
+    peak_db: AtomicU32,
+    peak_hold_db: AtomicU32,
+    clips: AtomicU32,
+    lufs: AtomicU32,
+}
+
+impl BusShared {
+    fn new() -> Self {
+        let floor = FLOOR_DB.to_bits();
+        Self {
+            rms_db: AtomicU32::new(floor),
+            peak_db: AtomicU32::new(floor),
+            peak_hold_db: AtomicU32::new(floor),
+            clips: AtomicU32::new(0),
+            lufs: AtomicU32::new(floor),
+        }
+    }
+
+    fn read(&self) -> BusMeters {
+        let load = |atomic: &AtomicU32| f32::from_bits(atomic.load(Ordering::Relaxed));
+        BusMeters {
+            rms_db: load(&self.rms_db),
+            peak_db: load(&self.peak_db),
+            peak_hold_db: load(&self.peak_hold_db),
+            clips: self.clips.load(Ordering::Relaxed),
+            lufs_short_term: load(&self.lufs),
+        }
+    }
+}
+
+impl MeterControls {
+    pub fn new() -> Self {
+        Self {
+            shared: Arc::new([BusShared::new(), BusShared::new(), BusShared::new()]),
+        }
+    }
+
+    pub fn read(&self) -> Meters {
+        Meters {
+            mic: self.shared[Bus::Mic as usize].read(),
+            sidetone: self.shared[Bus::Sidetone as usize].read(),
+            output: self.shared[Bus::Output as usize].read(),
+        }
+    }
+}
+
+/// Biquad in direct form I
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    x: [f32; 2],
+    y: [f32; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, input: f32) -> f32 {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+}
+
+/// BS.1770 K-weighting (high shelf for the head + high-pass), recomputed for any sample rate
+fn k_weighting(sample_rate: f32) -> [Biquad; 2] {
+    let (f0, gain_db, q) = (1681.97445_f64, 3.99984385_f64, 0.70717524_f64);
+    let k = (std::f64::consts::PI * f0 / sample_rate as f64).tan();
+    let vh = 10.0_f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.49966677);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            ((vh + vb * k / q + k * k) / a0) as f32,
+            (2.0 * (k * k - vh) / a0) as f32,
+            ((vh - vb * k / q + k * k) / a0) as f32,
+        ],
+        a: [(2.0 * (k * k - 1.0) / a0) as f32, ((1.0 - k / q + k * k) / a0) as f32],
+        ..Biquad::default()
+    };
+
+    let (f0, q) = (38.1354709_f64, 0.50032704_f64);
+    let k = (std::f64::consts::PI * f0 / sample_rate as f64).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [(2.0 * (k * k - 1.0) / a0) as f32, ((1.0 - k / q + k * k) / a0) as f32],
+        ..Biquad::default()
+    };
+    [shelf, high_pass]
+}
+
+/// Windowed-sinc interpolation filter, split into `OVERSAMPLING` branches
+fn true_peak_filter() -> [[f32; PHASE_TAPS]; OVERSAMPLING] {
+    let length = OVERSAMPLING * PHASE_TAPS;
+    let centre = (length - 1) as f32 / 2.0;
+    let mut phases = [[0.0; PHASE_TAPS]; OVERSAMPLING];
+    for (i, phase) in phases.iter_mut().enumerate() {
+        for (j, tap) in phase.iter_mut().enumerate() {
+            let n = (j * OVERSAMPLING + i) as f32;
+            let t = (n - centre) / OVERSAMPLING as f32;
+            let sinc = if t.abs() < 1e-6 { 1.0 } else { (PI * t).sin() / (PI * t) };
+            let window = 0.5 - 0.5 * (2.0 * PI * (n + 0.5) / length as f32).cos();
+            *tap = sinc * window;
+        }
+    }
+    // Unity gain at DC for every branch
+    for phase in phases.iter_mut() {
+        let sum: f32 = phase.iter().sum();
+        phase.iter_mut().for_each(|tap| *tap /= sum);
+    }
+    phases
+}
+
+fn to_db(amplitude: f32) -> f32 {
+    if amplitude > 0.0 {
+        (20.0 * amplitude.log10()).max(FLOOR_DB)
+    } else {
+        FLOOR_DB
+    }
+}
+
+fn power_to_db(power: f32) -> f32 {
+    if power > 0.0 {
+        (10.0 * power.log10()).max(FLOOR_DB)
+    } else {
+        FLOOR_DB
+    }
+}
+
+/// Meter for one bus, run inside the output callback
+pub(super) struct BusMeter {
+    controls: MeterControls,
+    bus: Bus,
+    sample_rate: f32,
+    rms_coefficient: f32,
+    mean_square: f32,
+    /// Recent samples for the true-peak interpolator, stored twice so the
+    /// newest `PHASE_TAPS` are one contiguous slice starting at `position`
+    history: [f32; 2 * PHASE_TAPS],
+    position: usize,
+    phases: [[f32; PHASE_TAPS]; OVERSAMPLING],
+    block_peak: f32,
+    peak_db: f32,
+    hold_db: f32,
+    /// Samples left before the hold starts falling back
+    hold_remaining: u32,
+    clips: u32,
+    k_filter: [Biquad; 2],
+    block_energy: f32,
+    block_samples: usize,
+    block_length: usize,
+    short_term: [f32; SHORT_TERM_BLOCKS],
+    short_term_filled: usize,
+    short_term_next: usize,
+}
+
+impl BusMeter {
+    pub fn new(controls: MeterControls, bus: Bus, sample_rate: f32) -> Self {
+        let mut meter = Self {
+            controls,
+            bus,
+            sample_rate,
+            rms_coefficient: 0.0,
+            mean_square: 0.0,
+            history: [0.0; 2 * PHASE_TAPS],
+            position: 0,
+            phases: true_peak_filter(),
+            block_peak: 0.0,
+            peak_db: FLOOR_DB,
+            hold_db: FLOOR_DB,
+            hold_remaining: 0,
+            clips: 0,
+            k_filter: k_weighting(sample_rate),
+            block_energy: 0.0,
+            block_samples: 0,
+            block_length: 1,
+            short_term: [0.0; SHORT_TERM_BLOCKS],
+            short_term_filled: 0,
+            short_term_next: 0,
+        };
+        meter.set_sample_rate(sample_rate);
+        meter
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.rms_coefficient = 1.0 - (-1.0 / (RMS_WINDOW_MS / 1000.0 * sample_rate)).exp();
+        self.k_filter = k_weighting(sample_rate);
+        self.block_length = (LOUDNESS_BLOCK_MS / 1000.0 * sample_rate) as usize;
+        self.block_energy = 0.0;
+        self.block_samples = 0;
+        self.short_term_filled = 0;
+    }
+
+    pub fn process(&mut self, sample: f32) {
+        self.mean_square += (sample * sample - self.mean_square) * self.rms_coefficient;
+        if sample.abs() >= 1.0 {
+            self.clips = self.clips.wrapping_add(1);
+        }
+
+        // True peak: the sample itself plus the points interpolated before it
+        self.position = (self.position + PHASE_TAPS - 1) % PHASE_TAPS;
+        self.history[self.position] = sample;
+        self.history[self.position + PHASE_TAPS] = sample;
+        let window = &self.history[self.position..self.position + PHASE_TAPS];
+        let mut peak = sample.abs();
+        for phase in &self.phases {
+            let value: f32 = phase.iter().zip(window).map(|(h, x)| h * x).sum();
+            peak = peak.max(value.abs());
+        }
+        self.block_peak = self.block_peak.max(peak);
+
+        // Loudness in 100 ms blocks; short-term is the mean of the last 30
+        let weighted = self.k_filter.iter_mut().fold(sample, |x, stage| stage.process(x));
+        self.block_energy += weighted * weighted;
+        self.block_samples += 1;
+        if self.block_samples >= self.block_length {
+            self.short_term[self.short_term_next] = self.block_energy / self.block_samples as f32;
+            self.short_term_next = (self.short_term_next + 1) % SHORT_TERM_BLOCKS;
+            self.short_term_filled = (self.short_term_filled + 1).min(SHORT_TERM_BLOCKS);
+            self.block_energy = 0.0;
+            self.block_samples = 0;
+        }
+    }
+
+    /// Publish the readings; called once per buffer of `frames` samples
+    pub fn end_block(&mut self, frames: usize) {
+        let block_peak_db = to_db(self.block_peak);
+        self.block_peak = 0.0;
+        let fall = PEAK_FALL_DB_PER_S * frames as f32 / self.sample_rate;
+        self.peak_db = block_peak_db.max(self.peak_db - fall).max(FLOOR_DB);
+
+        if block_peak_db >= self.hold_db {
+            self.hold_db = block_peak_db;
+            self.hold_remaining = (PEAK_HOLD_MS / 1000.0 * self.sample_rate) as u32;
+        } else if self.hold_remaining > frames as u32 {
+            self.hold_remaining -= frames as u32;
+        } else {
+            self.hold_remaining = 0;
+            self.hold_db = self.peak_db;
+        }
+
+        let lufs = if self.short_term_filled > 0 {
+            let mean = self.short_term[..self.short_term_filled].iter().sum::<f32>() / self.short_term_filled as f32;
+            (-0.691 + power_to_db(mean)).max(FLOOR_DB)
+        } else {
+            FLOOR_DB
+        };
+
+        let shared = &self.controls.shared[self.bus as usize];
+        let store = |atomic: &AtomicU32, value: f32| atomic.store(value.to_bits(), Ordering::Relaxed);
+        store(&shared.rms_db, power_to_db(self.mean_square));
+        store(&shared.peak_db, self.peak_db);
+        store(&shared.peak_hold_db, self.hold_db);
+        store(&shared.lufs, lufs);
+        shared.clips.store(self.clips, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = 48000.0;
+
+    fn run(meter: &mut BusMeter, seconds: f32, signal: impl Fn(usize) -> f32) {
+        let samples: Vec<f32> = (0..(seconds * RATE) as usize).map(signal).collect();
+        for block in samples.chunks(480) {
+            for &sample in block {
+                meter.process(sample);
+            }
+            meter.end_block(block.len());
+        }
+    }
+
+    #[test]
+    fn test_sine_readings() {
+        let controls = MeterControls::new();
+        let mut meter = BusMeter::new(controls.clone(), Bus::Output, RATE);
+
+        // Half-scale 1 kHz sine: -9 dB RMS and -9 LUFS (the loudness offset cancels the K-weighting there)
+        run(&mut meter, 4.0, |i| (2.0 * PI * 1000.0 * i as f32 / RATE).sin() * 0.5);
+        let reading = controls.read().output;
+        assert!((reading.rms_db + 9.03).abs() < 0.1, "RMS {}", reading.rms_db);
+        assert!((reading.lufs_short_term + 9.0).abs() < 0.2, "LUFS {}", reading.lufs_short_term);
+        assert!((reading.peak_db + 6.02).abs() < 0.1, "peak {}", reading.peak_db);
+        assert_eq!(reading.clips, 0);
+
+        // A quarter-rate sine sampled 45 degrees off its crests: samples read
+        // 3 dB under its 0.9 crest, which the true peak recovers; still no clips
+        run(&mut meter, 0.1, |i| (PI / 2.0 * i as f32 + PI / 4.0).sin() * 0.9);
+        let reading = controls.read().output;
+        assert!((reading.peak_db - to_db(0.9)).abs() < 0.3, "true peak {}", reading.peak_db);
+        assert_eq!(reading.clips, 0);
+
+        // Overs are counted; the hold keeps the peak after the signal stops
+        run(&mut meter, 0.01, |_| 1.0);
+        assert_eq!(controls.read().output.clips, 480);
+        run(&mut meter, 1.0, |_| 0.0);
+        let reading = controls.read().output;
+        assert!(reading.peak_hold_db > -0.1, "hold {}", reading.peak_hold_db);
+        assert!(reading.peak_db < -15.0, "peak {}", reading.peak_db);
+        run(&mut meter, 1.0, |_| 0.0);
+        assert!(controls.read().output.peak_hold_db < -30.0);
+    }
+}
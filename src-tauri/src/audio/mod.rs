@@ -1,13 +1,31 @@
+mod analyzer;
+mod backend;
+mod bleed;
+mod channels;
+mod dynamics;
+mod fft;
+mod handoff;
+mod health;
+mod key_timing;
+mod latency;
+mod meters;
+#[cfg(test)]
+mod offline;
+mod pipeline;
+mod resampler;
+mod session;
 mod sidetone;
+mod stream_config;
+mod tone;
+mod vad;
 
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, Stream, StreamConfig, FromSample};
+use cpal::traits::{DeviceTrait, HostTrait};
 use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
-use crossbeam_channel::{bounded, Sender, Receiver};
-use ringbuf::{HeapRb, traits::{Producer, Consumer, Split}};
+use std::time::{Duration, Instant};
+use crossbeam_channel::{bounded, Sender, Receiver, RecvTimeoutError};
+use ringbuf::{HeapCons, HeapProd, HeapRb, traits::{Consumer, Observer}};
 
 #[cfg(target_os = "linux")]
 use std::process::Command;
@@ -18,7 +36,32 @@ extern "C" {
     fn check_microphone_permission() -> i32;
 }
 
-pub use sidetone::SidetoneGenerator;
+pub use analyzer::{SpectrumFrame, SpectrumSettings};
+pub use sidetone::{load_wavetable, EnvelopeShape, SidetoneGenerator, Waveform, HARMONIC_COUNT, MAX_SQUARE_DRIVE};
+pub use bleed::BleedStatus;
+pub use channels::{InputChannels, OutputChannel};
+pub use dynamics::{CompressorSettings, GainReduction, GateSettings};
+pub use health::{RecoveryEvent, StreamKind};
+pub use key_timing::KeyEdge;
+pub use latency::LatencyStats;
+pub use meters::Meters;
+pub use session::SessionStatus;
+pub use stream_config::{SampleFormatPreference, StreamConfigs, StreamPreferences};
+pub use vad::VadSettings;
+use analyzer::{analyzer_thread, SpectrumControls};
+use backend::{AudioBackend, CpalBackend};
+use bleed::BleedControls;
+use channels::ChannelControls;
+use dynamics::{CompressorControls, GateControls};
+use health::{StreamMonitor, StreamWatch};
+use key_timing::{KeyEdgeQueue, KeyTimingControls};
+use latency::{match_onsets, LatencyMeter};
+use meters::MeterControls;
+use session::{SessionControls, SessionRecorder};
+use tone::ToneMonitor;
+use vad::VadControls;
+pub use pipeline::DuckingSettings;
+use pipeline::{DuckingControls, EnvelopeControls, LocalControls, LocalPipeline, MicCapture, MicRecorder, MixControls, MixPipeline, PlaybackPipeline, WaveformControls};
 
 /// Device info with display name and internal name for selection
 #[derive(Clone, serde::Serialize)]
@@ -27,16 +70,79 @@ pub struct DeviceInfo {
     pub display_name: String,
     /// Internal name used for device selection (cpal name)
     pub internal_name: String,
+    /// Channels the device offers by default (0 if unknown). The input and
+    /// main output streams open with this many, so channel selection and
+    /// the output channel map address the device's own channels.
+    pub channels: u16,
 }
 
-/// Ring buffer size for mic audio (holds ~100ms at 48kHz)
-const RING_BUFFER_SIZE: usize = 4800;
+/// Ring buffer size for mic audio (holds ~200ms at 48kHz)
+/// The resampler keeps it filled to a much lower target level.
+const RING_BUFFER_SIZE: usize = 9600;
+
+/// How often the audio thread checks stream health while idle
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Mic callbacks between diagnostic log lines (~once per second at typical buffer sizes)
+const MIC_LOG_CALLBACKS: u32 = 100;
+
+/// How long a command waits for the audio thread to open a capture stream
+/// (PulseAudio routing alone can take a second or so)
+const CAPTURE_OPEN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Test recording samples in flight between a callback and the audio thread
+/// (5 seconds at 48kHz, far more than one watchdog interval)
+const RECORDING_RING_SIZE: usize = 48000 * 5;
+
+/// Local monitor samples in flight to the main output's bleed canceller
+/// (~340ms at 48kHz; the mix keeps it far lower)
+const BLEED_RING_SIZE: usize = 16384;
+
+/// Test recording length unless configured otherwise, and the longest allowed
+const DEFAULT_RECORDING_SECONDS: f32 = 5.0;
+const MAX_RECORDING_SECONDS: f32 = 300.0;
+
+/// Mic ring buffer health, reported to the frontend
+#[derive(Clone, Default, serde::Serialize)]
+pub struct AudioStats {
+    /// Output callbacks that ran out of mic samples
+    pub mic_underruns: u32,
+    /// Times mic samples were dropped because the ring was too full
+    pub mic_overruns: u32,
+    /// Mic device sample rate (0 if no mic is running)
+    pub input_sample_rate: u32,
+    /// Output device sample rate
+    pub output_sample_rate: u32,
+    /// Current drift correction applied by the resampler
+    pub drift_ppm: f32,
+    /// Key edges that arrived after their latency budget had passed
+    pub late_key_edges: u32,
+}
 
-/// Mic ducking hold time after key up (~250ms at 48kHz)
-const MIC_DUCKING_HOLD_SAMPLES: u32 = 12000;
+/// Mic ring buffer counters shared between the audio callbacks and the handle
+#[derive(Clone)]
+struct MicRingStats {
+    input_sample_rate: Arc<AtomicU32>,
+    underruns: Arc<AtomicU32>,
+    overruns: Arc<AtomicU32>,
+    drift_ppm: Arc<AtomicU32>,
+    /// Capture callbacks so far and the peak of the latest, for the audio thread's log
+    callbacks: Arc<AtomicU32>,
+    peak: Arc<AtomicU32>,
+}
 
-/// Max test recording samples (5 seconds at 48kHz)
-const MAX_RECORDING_SAMPLES: usize = 48000 * 5;
+impl MicRingStats {
+    fn new() -> Self {
+        Self {
+            input_sample_rate: Arc::new(AtomicU32::new(0)),
+            underruns: Arc::new(AtomicU32::new(0)),
+            overruns: Arc::new(AtomicU32::new(0)),
+            drift_ppm: Arc::new(AtomicU32::new(0.0_f32.to_bits())),
+            callbacks: Arc::new(AtomicU32::new(0)),
+            peak: Arc::new(AtomicU32::new(0.0_f32.to_bits())),
+        }
+    }
+}
 
 /// Sidetone routing mode
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -46,6 +152,47 @@ pub enum SidetoneRoute {
     Both,        // Both outputs
 }
 
+impl SidetoneRoute {
+    fn from_u32(value: u32) -> Self {
+        match value {
+            0 => SidetoneRoute::OutputOnly,
+            1 => SidetoneRoute::LocalOnly,
+            _ => SidetoneRoute::Both,
+        }
+    }
+
+    /// Whether sidetone is mixed into the main output (Zoom)
+    fn includes_output(self) -> bool {
+        self == SidetoneRoute::OutputOnly || self == SidetoneRoute::Both
+    }
+
+    /// Whether sidetone plays on the local monitor
+    fn includes_local(self) -> bool {
+        self == SidetoneRoute::LocalOnly || self == SidetoneRoute::Both
+    }
+}
+
+/// How the mic is combined with the sidetone in the output mix
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MixMode {
+    AlwaysMix,        // Mic and sidetone always mixed
+    CwMutesMic,       // Mic muted while sending CW (plus tail)
+    PushToTalkVoice,  // Mic only passes while PTT is held
+}
+
+impl MixMode {
+    fn from_u32(value: u32) -> Self {
+        match value {
+            1 => MixMode::CwMutesMic,
+            2 => MixMode::PushToTalkVoice,
+            _ => MixMode::AlwaysMix,
+        }
+    }
+}
+
+/// Where the audio thread reports whether a capture it was asked to open came up
+type OpenReply = Sender<Result<(), String>>;
+
 /// Commands sent to the audio thread
 enum AudioCommand {
     Start {
@@ -55,14 +202,17 @@ enum AudioCommand {
         sidetone_route: SidetoneRoute,
     },
     Stop,
-    SetFrequency(f32),
-    SetVolume(f32),
-    SetLocalVolume(f32),
-    SetMicVolume(f32),
     SetSidetoneRoute(SidetoneRoute),
+    SetStreamPreferences(StreamPreferences),
+    StartLoopback { device: String, reply: OpenReply },
+    StopLoopback,
+    StartRxDecoder { device: String, reply: OpenReply },
+    StopRxDecoder,
+    StartToneKeying { device: String, reply: OpenReply },
+    StopToneKeying,
     StartTestRecording,
     StopTestRecording,
-    StartPlayback { device: Option<String> },
+    StartPlayback { device: Option<String>, recording_rate: u32 },
     StopPlayback,
     Shutdown,
 }
@@ -71,100 +221,111 @@ enum AudioCommand {
 /// This is Send + Sync safe because it only holds channels and atomics
 pub struct AudioEngineHandle {
     command_tx: Sender<AudioCommand>,
-    is_key_down: Arc<AtomicBool>,
-    frequency: Arc<AtomicU32>,
-    volume: Arc<AtomicU32>,           // Sidetone volume for output (Zoom)
-    local_volume: Arc<AtomicU32>,     // Sidetone volume for local monitoring
-    mic_volume: Arc<AtomicU32>,
+    recovery_rx: Receiver<RecoveryEvent>,
+    spectrum_tx: Sender<SpectrumSettings>, // Settings for the spectrum analyzer thread
+    spectrum_rx: Receiver<SpectrumFrame>,
+    controls: MixControls,                 // Settings and readouts shared with the callbacks
+    shared: EngineShared,                  // Everything else the audio thread shares with the handle
+    session: SessionRecorder,              // Full-session stems and sidecar on disk
+    recording_seconds: AtomicU32,          // Configured maximum test recording length
+    recording_rate: AtomicU32,             // Rate of the test recording's source stream
+}
+
+/// What the audio thread shares with the handle besides the mix controls
+#[derive(Clone)]
+struct EngineShared {
+    key_edges: Arc<KeyEdgeQueue>,
+    latency: Arc<LatencyMeter>,            // Key-to-buffer and loopback latency measurement
+    rx_tone: Arc<ToneMonitor>,             // Key timings heard in the conference audio
+    tone_key: Arc<ToneMonitor>,            // Key edges heard on the tone keying input
     mic_level: Arc<AtomicU32>,
-    output_level: Arc<AtomicU32>,
-    sidetone_route: Arc<AtomicU32>,  // Store as u32 for atomic ops
-    mic_ducking_enabled: Arc<AtomicBool>,  // Whether to mute mic while sending
-    mic_ducking_hold: Arc<AtomicU32>,      // Samples remaining for ducking hold after key up
-    // Test recording state
-    is_recording: Arc<AtomicBool>,
-    is_playing: Arc<AtomicBool>,
+    recording_limit: Arc<AtomicUsize>,     // Maximum test recording length in samples at its rate
     recording_buffer: Arc<parking_lot::Mutex<Vec<f32>>>,
+    is_playing: Arc<AtomicBool>,
     playback_position: Arc<AtomicUsize>,
-    sample_rate: Arc<AtomicU32>,
+    stream_configs: Arc<parking_lot::Mutex<StreamConfigs>>,
+}
+
+impl EngineShared {
+    fn new() -> Self {
+        Self {
+            key_edges: Arc::new(KeyEdgeQueue::new()),
+            latency: Arc::new(LatencyMeter::new()),
+            rx_tone: Arc::new(ToneMonitor::new()),
+            tone_key: Arc::new(ToneMonitor::new()),
+            mic_level: Arc::new(AtomicU32::new(0.0_f32.to_bits())),
+            recording_limit: Arc::new(AtomicUsize::new((DEFAULT_RECORDING_SECONDS * 48000.0) as usize)),
+            recording_buffer: Arc::new(parking_lot::Mutex::new(Vec::new())),
+            is_playing: Arc::new(AtomicBool::new(false)),
+            playback_position: Arc::new(AtomicUsize::new(0)),
+            stream_configs: Arc::new(parking_lot::Mutex::new(StreamConfigs::default())),
+        }
+    }
 }
 
 impl AudioEngineHandle {
     /// Create a new audio engine handle and spawn the audio thread
     pub fn new(frequency: f32, volume: f32) -> Result<Self, String> {
         let (command_tx, command_rx) = bounded::<AudioCommand>(16);
-        let is_key_down = Arc::new(AtomicBool::new(false));
-        let frequency_atomic = Arc::new(AtomicU32::new(frequency.to_bits()));
-        let volume_atomic = Arc::new(AtomicU32::new(volume.to_bits()));
-        let local_volume_atomic = Arc::new(AtomicU32::new(0.3_f32.to_bits())); // Default local volume 30%
-        let mic_volume_atomic = Arc::new(AtomicU32::new(1.0_f32.to_bits())); // Default mic volume 100%
-        let mic_level_atomic = Arc::new(AtomicU32::new(0.0_f32.to_bits())); // Current mic level
-        let output_level_atomic = Arc::new(AtomicU32::new(0.0_f32.to_bits())); // Current output level
-        let sidetone_route_atomic = Arc::new(AtomicU32::new(0)); // 0 = OutputOnly
-        let mic_ducking_enabled = Arc::new(AtomicBool::new(false));
-        let mic_ducking_hold = Arc::new(AtomicU32::new(0));
-        // Test recording state
-        let is_recording = Arc::new(AtomicBool::new(false));
-        let is_playing = Arc::new(AtomicBool::new(false));
-        let recording_buffer = Arc::new(parking_lot::Mutex::new(Vec::with_capacity(MAX_RECORDING_SAMPLES)));
-        let playback_position = Arc::new(AtomicUsize::new(0));
-        let sample_rate = Arc::new(AtomicU32::new(48000)); // Default sample rate
-
-        let is_key_down_clone = Arc::clone(&is_key_down);
-        let frequency_clone = Arc::clone(&frequency_atomic);
-        let volume_clone = Arc::clone(&volume_atomic);
-        let local_volume_clone = Arc::clone(&local_volume_atomic);
-        let mic_volume_clone = Arc::clone(&mic_volume_atomic);
-        let mic_level_clone = Arc::clone(&mic_level_atomic);
-        let output_level_clone = Arc::clone(&output_level_atomic);
-        let sidetone_route_clone = Arc::clone(&sidetone_route_atomic);
-        let mic_ducking_enabled_clone = Arc::clone(&mic_ducking_enabled);
-        let mic_ducking_hold_clone = Arc::clone(&mic_ducking_hold);
-        let is_recording_clone = Arc::clone(&is_recording);
-        let recording_buffer_clone = Arc::clone(&recording_buffer);
-        let is_playing_clone = Arc::clone(&is_playing);
-        let playback_position_clone = Arc::clone(&playback_position);
-        let sample_rate_clone = Arc::clone(&sample_rate);
+        let (recovery_tx, recovery_rx) = bounded::<RecoveryEvent>(32);
+        let (spectrum_tx, spectrum_settings_rx) = bounded::<SpectrumSettings>(4);
+        let (spectrum_frame_tx, spectrum_rx) = bounded::<SpectrumFrame>(4);
+        let controls = MixControls {
+            frequency: Arc::new(AtomicU32::new(frequency.to_bits())),
+            volume: Arc::new(AtomicU32::new(volume.to_bits())),
+            envelope: EnvelopeControls::new(),
+            waveform: WaveformControls::new(),
+            sidetone_route: Arc::new(AtomicU32::new(SidetoneRoute::OutputOnly as u32)),
+            mic_volume: Arc::new(AtomicU32::new(1.0_f32.to_bits())), // Default mic volume 100%
+            mic_gate: GateControls::new(),
+            mic_compressor: CompressorControls::new(),
+            local: LocalControls::new(frequency, 0.3), // Default local volume 30%
+            bleed: BleedControls::new(),
+            output_level: Arc::new(AtomicU32::new(0.0_f32.to_bits())),
+            meters: MeterControls::new(),
+            spectrum: SpectrumControls::new(),
+            session: SessionControls::new(),
+            channels: ChannelControls::new(),
+            limiter_reduction: Arc::new(AtomicU32::new(0.0_f32.to_bits())),
+            ducking: DuckingControls::new(),
+            vad: VadControls::new(),
+            mix_mode: Arc::new(AtomicU32::new(MixMode::AlwaysMix as u32)),
+            cw_mute_tail_ms: Arc::new(AtomicU32::new(250.0_f32.to_bits())),
+            ptt_active: Arc::new(AtomicBool::new(false)),
+            is_recording: Arc::new(AtomicBool::new(false)),
+            record_mic_only: Arc::new(AtomicBool::new(false)),
+            key_timing: KeyTimingControls::new(), // Auto: one buffer period
+            late_key_edges: Arc::new(AtomicU32::new(0)),
+            sample_rate: Arc::new(AtomicU32::new(48000)), // Default sample rate
+            ring_stats: MicRingStats::new(),
+        };
+        let shared = EngineShared::new();
 
         // Spawn the audio thread
+        let thread_controls = controls.clone();
+        let thread_shared = shared.clone();
+        thread::spawn(move || {
+            let backend = CpalBackend::new(Arc::clone(&thread_shared.stream_configs));
+            audio_thread(backend, command_rx, recovery_tx, thread_controls, thread_shared);
+        });
+
+        // Spectrum analysis runs on its own thread, off the real-time path
+        let spectrum = controls.spectrum.clone();
+        let spectrum_rate = Arc::clone(&controls.sample_rate);
         thread::spawn(move || {
-            audio_thread(
-                command_rx,
-                is_key_down_clone,
-                frequency_clone,
-                volume_clone,
-                local_volume_clone,
-                mic_volume_clone,
-                mic_level_clone,
-                output_level_clone,
-                sidetone_route_clone,
-                mic_ducking_enabled_clone,
-                mic_ducking_hold_clone,
-                is_recording_clone,
-                recording_buffer_clone,
-                is_playing_clone,
-                playback_position_clone,
-                sample_rate_clone,
-            );
+            analyzer_thread(spectrum, spectrum_rate, spectrum_settings_rx, spectrum_frame_tx);
         });
 
         Ok(Self {
             command_tx,
-            is_key_down,
-            frequency: frequency_atomic,
-            volume: volume_atomic,
-            local_volume: local_volume_atomic,
-            mic_volume: mic_volume_atomic,
-            mic_level: mic_level_atomic,
-            output_level: output_level_atomic,
-            sidetone_route: sidetone_route_atomic,
-            mic_ducking_enabled,
-            mic_ducking_hold,
-            is_recording,
-            is_playing,
-            recording_buffer,
-            playback_position,
-            sample_rate,
+            recovery_rx,
+            spectrum_tx,
+            spectrum_rx,
+            session: SessionRecorder::new(controls.session.clone()),
+            controls,
+            shared,
+            recording_seconds: AtomicU32::new(DEFAULT_RECORDING_SECONDS.to_bits()),
+            recording_rate: AtomicU32::new(48000),
         })
     }
 
@@ -183,9 +344,11 @@ impl AudioEngineHandle {
             .map(|devices| {
                 devices
                     .filter_map(|d| {
+                        let channels = d.default_output_config().map(|c| c.channels()).unwrap_or(0);
                         d.name().ok().map(|name| DeviceInfo {
                             display_name: name.clone(),
                             internal_name: name,
+                            channels,
                         })
                     })
                     .collect()
@@ -208,9 +371,11 @@ impl AudioEngineHandle {
             .map(|devices| {
                 devices
                     .filter_map(|d| {
+                        let channels = d.default_input_config().map(|c| c.channels()).unwrap_or(0);
                         d.name().ok().map(|name| DeviceInfo {
                             display_name: name.clone(),
                             internal_name: name,
+                            channels,
                         })
                     })
                     .collect()
@@ -249,7 +414,7 @@ impl AudioEngineHandle {
         local_device: Option<String>,
         sidetone_route: SidetoneRoute,
     ) -> Result<(), String> {
-        self.sidetone_route.store(sidetone_route as u32, Ordering::Relaxed);
+        self.controls.sidetone_route.store(sidetone_route as u32, Ordering::Relaxed);
         self.command_tx.send(AudioCommand::Start {
             output_device,
             input_device,
@@ -265,134 +430,426 @@ impl AudioEngineHandle {
             .map_err(|_| "Audio thread not responding".to_string())
     }
 
-    /// Signal key down (start sidetone)
-    pub fn key_down(&self) {
+    /// Signal key down (start sidetone) at the time the edge happened
+    pub fn key_down(&self, at: Instant) {
         eprintln!("[audio] *** KEY DOWN - sidetone ON ***");
-        self.is_key_down.store(true, Ordering::Relaxed);
-        // Reset ducking hold to max while key is down
-        self.mic_ducking_hold.store(MIC_DUCKING_HOLD_SAMPLES, Ordering::Relaxed);
+        self.shared.key_edges.push(KeyEdge { down: true, at });
+        self.session.key_edge(true, at);
     }
 
-    /// Signal key up (stop sidetone)
-    pub fn key_up(&self) {
+    /// Signal key up (stop sidetone) at the time the edge happened
+    pub fn key_up(&self, at: Instant) {
         eprintln!("[audio] *** KEY UP - sidetone OFF ***");
-        self.is_key_down.store(false, Ordering::Relaxed);
-        // Start the ducking hold countdown (will be decremented in audio callback)
-        self.mic_ducking_hold.store(MIC_DUCKING_HOLD_SAMPLES, Ordering::Relaxed);
+        self.shared.key_edges.push(KeyEdge { down: false, at });
+        self.session.key_edge(false, at);
+    }
+
+    /// Configure mic ducking while sending (hold, depth and fade ramps)
+    pub fn set_mic_ducking(&self, settings: &DuckingSettings) {
+        self.controls.ducking.set(settings);
+    }
+
+    /// Set how the mic is mixed with the sidetone
+    pub fn set_mix_mode(&self, mode: MixMode) {
+        self.controls.mix_mode.store(mode as u32, Ordering::Relaxed);
     }
 
-    /// Enable or disable mic ducking while sending
-    pub fn set_mic_ducking(&self, enabled: bool) {
-        self.mic_ducking_enabled.store(enabled, Ordering::Relaxed);
+    /// Set how long the mic stays muted after key up in CwMutesMic mode
+    pub fn set_cw_mute_tail_ms(&self, tail_ms: f32) {
+        self.controls.cw_mute_tail_ms.store(tail_ms.max(0.0).to_bits(), Ordering::Relaxed);
     }
 
-    /// Update sidetone frequency
+    /// Set the fixed delay applied to key edges. Edges are placed on their
+    /// exact sample as long as this covers the output buffer period, at the
+    /// cost of that much extra latency on every route; 0 uses one buffer
+    /// period of each output stream.
+    pub fn set_key_latency_ms(&self, latency_ms: f32) {
+        self.controls.key_timing.latency_ms.store(latency_ms.clamp(0.0, 200.0).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Tell the callbacks whether the software keyer is producing key edges.
+    /// Its edges are stamped when the poll loop steps it, so while it runs the
+    /// latency budget is held at one buffer period plus the poll slack at least.
+    pub fn set_software_keyer(&self, enabled: bool) {
+        self.controls.key_timing.software_keyer.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Press or release push-to-talk (PushToTalkVoice mode)
+    pub fn set_ptt(&self, active: bool) {
+        self.controls.ptt_active.store(active, Ordering::Relaxed);
+    }
+
+    /// Update sidetone frequency (for output to Zoom)
     pub fn set_sidetone_frequency(&self, frequency: f32) {
-        self.frequency.store(frequency.to_bits(), Ordering::Relaxed);
-        let _ = self.command_tx.send(AudioCommand::SetFrequency(frequency));
+        self.controls.frequency.store(frequency.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Update local sidetone frequency (for local monitoring)
+    pub fn set_local_sidetone_frequency(&self, frequency: f32) {
+        self.controls.local.frequency.store(frequency.to_bits(), Ordering::Relaxed);
     }
 
     /// Update sidetone volume (for output to Zoom)
     pub fn set_sidetone_volume(&self, volume: f32) {
-        self.volume.store(volume.to_bits(), Ordering::Relaxed);
-        let _ = self.command_tx.send(AudioCommand::SetVolume(volume));
+        self.controls.volume.store(volume.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
     }
 
     /// Update local sidetone volume (for local monitoring)
     pub fn set_local_sidetone_volume(&self, volume: f32) {
-        self.local_volume.store(volume.to_bits(), Ordering::Relaxed);
-        let _ = self.command_tx.send(AudioCommand::SetLocalVolume(volume));
+        self.controls.local.volume.store(volume.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Set the output sidetone envelope shape and rise/fall times in milliseconds
+    pub fn set_envelope(&self, shape: EnvelopeShape, rise_ms: f32, fall_ms: f32) {
+        self.controls.envelope.set(shape, rise_ms, fall_ms);
+    }
+
+    /// Set the local sidetone envelope shape and rise/fall times in milliseconds
+    pub fn set_local_envelope(&self, shape: EnvelopeShape, rise_ms: f32, fall_ms: f32) {
+        self.controls.local.envelope.set(shape, rise_ms, fall_ms);
+    }
+
+    /// Set the sidetone waveform going to the output (Zoom)
+    pub fn set_output_waveform(&self, waveform: &Waveform) {
+        self.controls.waveform.set(waveform);
+    }
+
+    /// Set the sidetone waveform on the local monitor
+    pub fn set_local_waveform(&self, waveform: &Waveform) {
+        self.controls.local.waveform.set(waveform);
+    }
+
+    /// Choose how the input device's channels make up the mic
+    pub fn set_input_channels(&self, channels: &InputChannels) {
+        self.controls.channels.input.set(channels.clone());
+    }
+
+    /// Choose what each output channel carries; an empty map puts the mix on every channel
+    pub fn set_output_channels(&self, channels: &[OutputChannel]) {
+        self.controls.channels.output.set(channels.to_vec());
     }
 
     /// Update microphone volume
     pub fn set_mic_volume(&self, volume: f32) {
-        self.mic_volume.store(volume.to_bits(), Ordering::Relaxed);
-        let _ = self.command_tx.send(AudioCommand::SetMicVolume(volume));
+        self.controls.mic_volume.store(volume.to_bits(), Ordering::Relaxed);
     }
 
     /// Get current microphone level (0.0 to 1.0)
     pub fn get_mic_level(&self) -> f32 {
-        f32::from_bits(self.mic_level.load(Ordering::Relaxed))
+        f32::from_bits(self.shared.mic_level.load(Ordering::Relaxed))
+    }
+
+    /// Configure the mic noise gate / downward expander
+    pub fn set_mic_gate(&self, settings: &GateSettings) {
+        self.controls.mic_gate.set(settings);
+    }
+
+    /// Get current mic gate gain (1.0 = open, lower = attenuating)
+    pub fn get_mic_gate_level(&self) -> f32 {
+        self.controls.mic_gate.level()
+    }
+
+    /// Configure the mic compressor
+    pub fn set_mic_compressor(&self, settings: &CompressorSettings) {
+        self.controls.mic_compressor.set(settings);
+    }
+
+    /// Enable or disable cancelling the local sidetone picked up by the mic
+    pub fn set_bleed_cancellation(&self, enabled: bool) {
+        self.controls.bleed.set_enabled(enabled);
+    }
+
+    /// Get the sidetone-bleed canceller's state and convergence
+    pub fn get_bleed_status(&self) -> BleedStatus {
+        self.controls.bleed.status()
+    }
+
+    /// Configure ducking the sidetone while the operator is speaking
+    pub fn set_voice_ducking(&self, settings: &VadSettings) {
+        self.controls.vad.set(settings);
+    }
+
+    /// Whether the voice activity detector currently hears the operator
+    pub fn is_voice_active(&self) -> bool {
+        self.controls.vad.is_active()
+    }
+
+    /// Get the current gain reduction of the output limiter and mic compressor
+    pub fn get_gain_reduction(&self) -> GainReduction {
+        GainReduction {
+            limiter_db: f32::from_bits(self.controls.limiter_reduction.load(Ordering::Relaxed)),
+            compressor_db: self.controls.mic_compressor.reduction_db(),
+        }
     }
 
     /// Get current output level (0.0 to 1.0)
     pub fn get_output_level(&self) -> f32 {
-        f32::from_bits(self.output_level.load(Ordering::Relaxed))
+        f32::from_bits(self.controls.output_level.load(Ordering::Relaxed))
+    }
+
+    /// Get RMS, true peak, clip counts and short-term loudness for the mic, sidetone and output
+    pub fn get_meters(&self) -> Meters {
+        self.controls.meters.read()
     }
 
     /// Set sidetone routing mode
     pub fn set_sidetone_route(&self, route: SidetoneRoute) {
-        self.sidetone_route.store(route as u32, Ordering::Relaxed);
+        self.controls.sidetone_route.store(route as u32, Ordering::Relaxed);
         let _ = self.command_tx.send(AudioCommand::SetSidetoneRoute(route));
     }
 
+    /// Set the preferred buffer size, sample rate and format.
+    /// Running streams are reopened when the preferences change.
+    pub fn set_stream_preferences(&self, preferences: StreamPreferences) {
+        let _ = self.command_tx.send(AudioCommand::SetStreamPreferences(preferences));
+    }
+
+    /// Configuration each running stream was actually opened with
+    pub fn get_stream_configs(&self) -> StreamConfigs {
+        self.shared.stream_configs.lock().clone()
+    }
+
     /// Get current sidetone routing mode
     pub fn get_sidetone_route(&self) -> SidetoneRoute {
-        match self.sidetone_route.load(Ordering::Relaxed) {
-            0 => SidetoneRoute::OutputOnly,
-            1 => SidetoneRoute::LocalOnly,
-            _ => SidetoneRoute::Both,
-        }
+        SidetoneRoute::from_u32(self.controls.sidetone_route.load(Ordering::Relaxed))
+    }
+
+    /// Set the longest test recording kept, in seconds
+    pub fn set_test_recording_max_seconds(&self, seconds: f32) {
+        self.recording_seconds.store(seconds.clamp(1.0, MAX_RECORDING_SECONDS).to_bits(), Ordering::Relaxed);
     }
 
-    /// Start test recording - captures 5 seconds of mixed audio
-    pub fn start_test_recording(&self) -> Result<(), String> {
+    /// Start test recording - captures up to the configured length of the
+    /// mixed output, or with `mic_only` the raw mic at its own rate, bypassing
+    /// the mix and the output routing
+    pub fn start_test_recording(&self, mic_only: bool) -> Result<(), String> {
+        let rate = if mic_only {
+            self.controls.ring_stats.input_sample_rate.load(Ordering::Relaxed)
+        } else {
+            self.controls.sample_rate.load(Ordering::Relaxed)
+        };
+        if rate == 0 {
+            return Err("Microphone is not running".to_string());
+        }
+        let seconds = f32::from_bits(self.recording_seconds.load(Ordering::Relaxed));
+        self.recording_rate.store(rate, Ordering::Relaxed);
+        self.shared.recording_limit.store((seconds * rate as f32) as usize, Ordering::Relaxed);
+        self.controls.record_mic_only.store(mic_only, Ordering::Relaxed);
+
         // Clear buffer and start recording
         {
-            let mut buf = self.recording_buffer.lock();
+            let mut buf = self.shared.recording_buffer.lock();
             buf.clear();
         }
-        self.is_recording.store(true, Ordering::Relaxed);
+        self.controls.is_recording.store(true, Ordering::Relaxed);
         self.command_tx.send(AudioCommand::StartTestRecording)
             .map_err(|_| "Audio thread not responding".to_string())
     }
 
     /// Stop test recording
     pub fn stop_test_recording(&self) -> Result<(), String> {
-        self.is_recording.store(false, Ordering::Relaxed);
+        self.controls.is_recording.store(false, Ordering::Relaxed);
         self.command_tx.send(AudioCommand::StopTestRecording)
             .map_err(|_| "Audio thread not responding".to_string())
     }
 
+    /// Write the test recording to a mono WAV file at the rate it was recorded at
+    pub fn export_test_recording(&self, path: &std::path::Path) -> Result<(), String> {
+        let recording = self.shared.recording_buffer.lock().clone();
+        if recording.is_empty() {
+            return Err("Nothing has been recorded".to_string());
+        }
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: self.recording_rate.load(Ordering::Relaxed),
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).map_err(|e| e.to_string())?;
+        for sample in recording {
+            writer.write_sample(sample).map_err(|e| e.to_string())?;
+        }
+        writer.finalize().map_err(|e| e.to_string())
+    }
+
+    /// Start recording the whole session into `directory`: the mixed output,
+    /// mic and sidetone as WAV stems plus a JSON sidecar of key events and
+    /// decoded text. Returns the sidecar's path.
+    pub fn start_session_recording(&self, directory: &std::path::Path) -> Result<std::path::PathBuf, String> {
+        self.session.start(directory, self.controls.sample_rate.load(Ordering::Relaxed))
+    }
+
+    /// Stop the session recording and write its sidecar
+    pub fn stop_session_recording(&self) -> Result<SessionStatus, String> {
+        self.session.stop()
+    }
+
+    /// Duration and size of the current (or last) session recording
+    pub fn get_session_recording_status(&self) -> SessionStatus {
+        self.session.status()
+    }
+
+    /// Add decoded text to the session sidecar; `received` marks text
+    /// decoded from the conference audio rather than our own keying
+    pub fn record_decoded(&self, text: &str, wpm: f32, received: bool) {
+        self.session.decoded(text, wpm, received);
+    }
+
     /// Start playback of recorded audio
     pub fn start_playback(&self, device: Option<String>) -> Result<(), String> {
-        self.playback_position.store(0, Ordering::Relaxed);
-        self.is_playing.store(true, Ordering::Relaxed);
-        self.command_tx.send(AudioCommand::StartPlayback { device })
+        self.shared.playback_position.store(0, Ordering::Relaxed);
+        self.shared.is_playing.store(true, Ordering::Relaxed);
+        let recording_rate = self.recording_rate.load(Ordering::Relaxed);
+        self.command_tx.send(AudioCommand::StartPlayback { device, recording_rate })
             .map_err(|_| "Audio thread not responding".to_string())
     }
 
     /// Stop playback
     pub fn stop_playback(&self) -> Result<(), String> {
-        self.is_playing.store(false, Ordering::Relaxed);
+        self.shared.is_playing.store(false, Ordering::Relaxed);
         self.command_tx.send(AudioCommand::StopPlayback)
             .map_err(|_| "Audio thread not responding".to_string())
     }
 
     /// Check if currently recording
     pub fn is_recording(&self) -> bool {
-        self.is_recording.load(Ordering::Relaxed)
+        self.controls.is_recording.load(Ordering::Relaxed)
     }
 
     /// Check if currently playing back
     pub fn is_playing(&self) -> bool {
-        self.is_playing.load(Ordering::Relaxed)
+        self.shared.is_playing.load(Ordering::Relaxed)
     }
 
     /// Get number of samples recorded
     pub fn get_recording_samples(&self) -> usize {
-        self.recording_buffer.lock().len()
+        self.shared.recording_buffer.lock().len()
     }
 
     /// Get current sample rate
     pub fn get_sample_rate(&self) -> u32 {
-        self.sample_rate.load(Ordering::Relaxed)
+        self.controls.sample_rate.load(Ordering::Relaxed)
+    }
+
+    /// Get the rate the test recording was made at
+    pub fn get_recording_sample_rate(&self) -> u32 {
+        self.recording_rate.load(Ordering::Relaxed)
+    }
+
+    /// Get mic ring buffer statistics (underruns, overruns, drift)
+    pub fn get_audio_stats(&self) -> AudioStats {
+        AudioStats {
+            mic_underruns: self.controls.ring_stats.underruns.load(Ordering::Relaxed),
+            mic_overruns: self.controls.ring_stats.overruns.load(Ordering::Relaxed),
+            input_sample_rate: self.controls.ring_stats.input_sample_rate.load(Ordering::Relaxed),
+            output_sample_rate: self.get_sample_rate(),
+            drift_ppm: f32::from_bits(self.controls.ring_stats.drift_ppm.load(Ordering::Relaxed)),
+            late_key_edges: self.controls.late_key_edges.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Start or stop measuring key-to-buffer latency. Starting clears earlier results.
+    pub fn set_latency_measurement(&self, enabled: bool) {
+        self.shared.latency.set_enabled(enabled);
+    }
+
+    /// Delay from each key down event to the sidetone crossing half level in the output buffer
+    pub fn get_latency_report(&self) -> LatencyStats {
+        self.shared.latency.report()
+    }
+
+    /// Send a command that opens a capture stream and wait for the audio
+    /// thread to say whether it opened
+    fn open_capture(&self, command: impl FnOnce(OpenReply) -> AudioCommand) -> Result<(), String> {
+        let (reply, result) = bounded(1);
+        self.command_tx.send(command(reply))
+            .map_err(|_| "Audio thread not responding".to_string())?;
+        result.recv_timeout(CAPTURE_OPEN_TIMEOUT)
+            .map_err(|_| "Audio thread not responding".to_string())?
+    }
+
+    /// Start capturing a loopback of the main output (e.g. "VailZoomer.monitor")
+    /// to measure the full path. Key the sidetone afterwards and call
+    /// `finish_loopback_test` with the key down times.
+    pub fn start_loopback_test(&self, device: String) -> Result<(), String> {
+        if !self.get_sidetone_route().includes_output() {
+            return Err("Sidetone must be routed to the output for the loopback test".to_string());
+        }
+        self.shared.latency.take_onsets();
+        self.open_capture(|reply| AudioCommand::StartLoopback { device, reply })
+    }
+
+    /// Stop the loopback capture and match the tones it heard to the key down times
+    pub fn finish_loopback_test(&self, key_downs: &[Instant], window: Duration) -> Result<LatencyStats, String> {
+        let _ = self.command_tx.send(AudioCommand::StopLoopback);
+        let delays = match_onsets(key_downs, &self.shared.latency.take_onsets(), window);
+        if delays.is_empty() {
+            return Err("No sidetone was heard on the loopback device".to_string());
+        }
+        Ok(LatencyStats::from_samples(&delays))
+    }
+
+    /// Configure the spectrum feed
+    pub fn set_spectrum(&self, settings: &SpectrumSettings) {
+        let _ = self.spectrum_tx.try_send(*settings);
+    }
+
+    /// Take the newest spectrum frame since the last call, if any
+    pub fn latest_spectrum_frame(&self) -> Option<SpectrumFrame> {
+        self.spectrum_rx.try_iter().last()
+    }
+
+    /// Start listening for CW in the conference audio captured from `device`
+    /// (e.g. the speakers' monitor source)
+    pub fn start_rx_decoder(&self, device: String) -> Result<(), String> {
+        self.open_capture(|reply| AudioCommand::StartRxDecoder { device, reply })
+    }
+
+    pub fn stop_rx_decoder(&self) {
+        let _ = self.command_tx.send(AudioCommand::StopRxDecoder);
+    }
+
+    /// Received key timings since the last call, for `CwDecoder::add_timing`
+    pub fn drain_rx_timings(&self) -> Vec<f32> {
+        self.shared.rx_tone.take_timings()
+    }
+
+    /// Pitch of the CW the receive decoder is tracking
+    pub fn get_rx_pitch(&self) -> f32 {
+        self.shared.rx_tone.pitch_hz()
+    }
+
+    /// Key the sidetone from the CW tone captured from `device` (e.g. a
+    /// receiver or practice oscillator); edges come out of `drain_tone_key_edges`
+    pub fn start_tone_keying(&self, device: String) -> Result<(), String> {
+        self.open_capture(|reply| AudioCommand::StartToneKeying { device, reply })
+    }
+
+    pub fn stop_tone_keying(&self) {
+        let _ = self.command_tx.send(AudioCommand::StopToneKeying);
+    }
+
+    /// How long the keying tone must stay on or off before an edge is believed.
+    /// Applies from the next `start_tone_keying`.
+    pub fn set_tone_key_debounce_ms(&self, debounce_ms: f32) {
+        self.shared.tone_key.set_debounce_ms(debounce_ms);
+    }
+
+    /// Key edges from the tone keying input since the last call, stamped
+    /// with when they happened, for `key_down`/`key_up`
+    pub fn drain_tone_key_edges(&self) -> Vec<KeyEdge> {
+        self.shared.tone_key.take_edges()
+    }
+
+    /// Take stream recovery attempts reported by the audio thread since the last call
+    pub fn drain_recovery_events(&self) -> Vec<RecoveryEvent> {
+        self.recovery_rx.try_iter().collect()
     }
 
     /// Get recording duration in seconds
     pub fn get_recording_duration(&self) -> f32 {
         let samples = self.get_recording_samples();
-        let rate = self.get_sample_rate();
+        let rate = self.recording_rate.load(Ordering::Relaxed);
         if rate > 0 {
             samples as f32 / rate as f32
         } else {
@@ -406,7 +863,7 @@ impl AudioEngineHandle {
         if total == 0 {
             return 0.0;
         }
-        let pos = self.playback_position.load(Ordering::Relaxed);
+        let pos = self.shared.playback_position.load(Ordering::Relaxed);
         (pos as f32 / total as f32).min(1.0)
     }
 }
@@ -417,54 +874,168 @@ impl Drop for AudioEngineHandle {
     }
 }
 
-/// Audio thread that owns the cpal Streams (not Send)
-fn audio_thread(
-    command_rx: Receiver<AudioCommand>,
-    is_key_down: Arc<AtomicBool>,
-    frequency: Arc<AtomicU32>,
-    volume: Arc<AtomicU32>,
-    local_volume: Arc<AtomicU32>,
-    mic_volume: Arc<AtomicU32>,
-    mic_level: Arc<AtomicU32>,
-    output_level: Arc<AtomicU32>,
-    sidetone_route: Arc<AtomicU32>,
-    mic_ducking_enabled: Arc<AtomicBool>,
-    mic_ducking_hold: Arc<AtomicU32>,
-    is_recording: Arc<AtomicBool>,
-    recording_buffer: Arc<parking_lot::Mutex<Vec<f32>>>,
-    is_playing: Arc<AtomicBool>,
-    playback_position: Arc<AtomicUsize>,
-    sample_rate: Arc<AtomicU32>,
-) {
-    let mut output_stream: Option<Stream> = None;
-    let mut local_stream: Option<Stream> = None;
-    let mut input_stream: Option<Stream> = None;
-    let mut playback_stream: Option<Stream> = None;
-
-    let init_freq = f32::from_bits(frequency.load(Ordering::Relaxed));
-    let init_vol = f32::from_bits(volume.load(Ordering::Relaxed));
-    let init_local_vol = f32::from_bits(local_volume.load(Ordering::Relaxed));
-
-    eprintln!("[audio] Initializing sidetone generators:");
-    eprintln!("[audio]   Main sidetone: freq={} Hz, volume={}", init_freq, init_vol);
-    eprintln!("[audio]   Local sidetone: freq={} Hz, volume={}", init_freq, init_local_vol);
-
-    let sidetone = Arc::new(parking_lot::Mutex::new(SidetoneGenerator::new(
-        init_freq,
-        init_vol,
-        48000.0,
-    )));
-
-    // Create a second sidetone generator for local output (independent phase)
-    let local_sidetone = Arc::new(parking_lot::Mutex::new(SidetoneGenerator::new(
-        init_freq,
-        init_local_vol,
-        48000.0,
-    )));
+/// The audio thread's side of the engine: owns the backend and every stream
+/// it opened. Streams are not Send, so this never leaves the audio thread.
+struct AudioEngine<B: AudioBackend> {
+    backend: B,
+    controls: MixControls,
+    shared: EngineShared,
+    recovery_tx: Sender<RecoveryEvent>,
+    output_stream: Option<B::Stream>,
+    local_stream: Option<B::Stream>,
+    input_stream: Option<B::Stream>,
+    playback_stream: Option<B::Stream>,
+    loopback_stream: Option<B::Stream>,
+    rx_stream: Option<B::Stream>,
+    tone_key_stream: Option<B::Stream>,
+    // Devices from the last Start, used to rebuild streams after a failure
+    // and to reopen the local stream on route changes
+    current_output_device: Option<String>,
+    current_input_device: Option<String>,
+    current_local_device: Option<String>,
+    // Mic ring buffer from the last Start, reused when a single stream is rebuilt
+    mic_ring: Option<MicRing>,
+    // Test recording samples from the output callback (or the mic callback
+    // when recording the mic alone); drained into recording_buffer here
+    recording_ring: MicRing,
+    recording_rx: HeapCons<f32>,
+    mic_recording_ring: MicRing,
+    mic_recording_rx: HeapCons<f32>,
+    // What the local monitor plays, fed back to the main output's bleed canceller
+    bleed_ring: MicRing,
+    // Per-stream heartbeat and error watchdogs
+    input_watch: StreamWatch,
+    output_watch: StreamWatch,
+    local_watch: StreamWatch,
+    // Mic callback count at the last diagnostic log
+    mic_callbacks_logged: u32,
+}
 
-    loop {
-        match command_rx.recv() {
-            Ok(AudioCommand::Start { output_device, input_device, local_device, sidetone_route: route }) => {
+impl<B: AudioBackend> AudioEngine<B> {
+    fn new(backend: B, controls: MixControls, shared: EngineShared, recovery_tx: Sender<RecoveryEvent>) -> Self {
+        eprintln!("[audio] Sidetone: freq={} Hz, volume={}, local freq={} Hz, local volume={}",
+            f32::from_bits(controls.frequency.load(Ordering::Relaxed)),
+            f32::from_bits(controls.volume.load(Ordering::Relaxed)),
+            f32::from_bits(controls.local.frequency.load(Ordering::Relaxed)),
+            f32::from_bits(controls.local.volume.load(Ordering::Relaxed)));
+
+        let recording_ring: MicRing = Arc::new(HeapRb::new(RECORDING_RING_SIZE));
+        let recording_rx = HeapCons::new(Arc::clone(&recording_ring));
+        let mic_recording_ring: MicRing = Arc::new(HeapRb::new(RECORDING_RING_SIZE));
+        let mic_recording_rx = HeapCons::new(Arc::clone(&mic_recording_ring));
+        Self {
+            backend,
+            controls,
+            shared,
+            recovery_tx,
+            output_stream: None,
+            local_stream: None,
+            input_stream: None,
+            playback_stream: None,
+            loopback_stream: None,
+            rx_stream: None,
+            tone_key_stream: None,
+            current_output_device: None,
+            current_input_device: None,
+            current_local_device: None,
+            mic_ring: None,
+            recording_ring,
+            recording_rx,
+            mic_recording_ring,
+            mic_recording_rx,
+            bleed_ring: Arc::new(HeapRb::new(BLEED_RING_SIZE)),
+            input_watch: StreamWatch::new(StreamKind::Input),
+            output_watch: StreamWatch::new(StreamKind::Output),
+            local_watch: StreamWatch::new(StreamKind::Local),
+            mic_callbacks_logged: 0,
+        }
+    }
+
+    /// Open the mic capture on the current input device.
+    /// Each stream owns its end of the ring; the previous owner must already be dropped.
+    fn open_input(&mut self) -> Result<B::Stream, String> {
+        let ring = self.mic_ring.as_ref().ok_or_else(|| "No mic ring buffer".to_string())?;
+        let controls = &self.controls;
+        let recorder = MicRecorder {
+            producer: HeapProd::new(Arc::clone(&self.mic_recording_ring)),
+            is_recording: Arc::clone(&controls.is_recording),
+            mic_only: Arc::clone(&controls.record_mic_only),
+        };
+        let capture = MicCapture::new(
+            HeapProd::new(Arc::clone(ring)),
+            Arc::clone(&self.shared.mic_level),
+            recorder,
+            controls,
+        );
+        let monitor = self.input_watch.monitor.clone();
+        self.backend.open_input(self.current_input_device.as_deref(), capture, monitor)
+    }
+
+    /// Open the main output on the current output device
+    fn open_output(&mut self) -> Result<B::Stream, String> {
+        let ring = self.mic_ring.as_ref().ok_or_else(|| "No mic ring buffer".to_string())?;
+        let pipeline = MixPipeline::new(
+            self.controls.clone(),
+            self.shared.key_edges.main_schedule(),
+            self.shared.latency.probe(),
+            HeapCons::new(Arc::clone(ring)),
+            HeapProd::new(Arc::clone(&self.recording_ring)),
+            HeapCons::new(Arc::clone(&self.bleed_ring)),
+        );
+        let monitor = self.output_watch.monitor.clone();
+        self.backend.open_output(self.current_output_device.as_deref(), pipeline, monitor)
+    }
+
+    /// Open the local sidetone monitor on the current local device, logging any failure
+    fn open_local(&mut self) -> Result<B::Stream, String> {
+        start_local_output(
+            &mut self.backend,
+            self.current_local_device.as_deref(),
+            &self.shared.key_edges,
+            HeapProd::new(Arc::clone(&self.bleed_ring)),
+            &self.controls,
+            self.local_watch.monitor.clone(),
+        )
+    }
+
+    /// Move recorded samples off the real-time path
+    fn drain_recording(&mut self) {
+        if self.recording_rx.is_empty() && self.mic_recording_rx.is_empty() {
+            return;
+        }
+        let limit = self.shared.recording_limit.load(Ordering::Relaxed);
+        let mut buf = self.shared.recording_buffer.lock();
+        for sample in self.recording_rx.pop_iter().chain(self.mic_recording_rx.pop_iter()) {
+            if buf.len() < limit {
+                buf.push(sample);
+            }
+        }
+    }
+
+    /// Log what the callbacks flagged since the last pass, off the real-time path
+    fn log_callbacks(&mut self) {
+        let ring_stats = &self.controls.ring_stats;
+        let count = ring_stats.callbacks.load(Ordering::Relaxed);
+        if count.wrapping_sub(self.mic_callbacks_logged) >= MIC_LOG_CALLBACKS {
+            self.mic_callbacks_logged = count;
+            eprintln!("[mic-input] callback #{}, peak={:.6}",
+                count, f32::from_bits(ring_stats.peak.load(Ordering::Relaxed)));
+        }
+
+        let local = &self.controls.local.stats;
+        if local.started.swap(false, Ordering::Relaxed) {
+            eprintln!("[audio-local] *** First callback! Stream is running. Buffer size: {} ***",
+                local.buffer_len.load(Ordering::Relaxed));
+        }
+        if local.keyed.swap(false, Ordering::Relaxed) {
+            eprintln!("[audio-local] *** First key_down detected! Generating sidetone ***");
+        }
+    }
+
+    /// Carry out one command from the handle. Returns false once the engine should shut down.
+    fn handle(&mut self, command: AudioCommand) -> bool {
+        match command {
+            AudioCommand::Start { output_device, input_device, local_device, sidetone_route: route } => {
                 eprintln!("[audio] === Starting audio ===");
                 eprintln!("[audio] Output device: {:?}", output_device);
                 eprintln!("[audio] Input device: {:?}", input_device);
@@ -472,795 +1043,396 @@ fn audio_thread(
                 eprintln!("[audio] Sidetone route: {:?} (0=OutputOnly, 1=LocalOnly, 2=Both)", route as u32);
 
                 // Stop existing streams
-                output_stream = None;
-                local_stream = None;
-                input_stream = None;
+                self.output_stream = None;
+                self.local_stream = None;
+                self.input_stream = None;
+                self.input_watch.disarm();
+                self.output_watch.disarm();
+                self.local_watch.disarm();
+                *self.shared.stream_configs.lock() = StreamConfigs::default();
 
                 // Create fresh ring buffer for mic audio (prevents stale data issues)
-                let ring_buffer = HeapRb::<f32>::new(RING_BUFFER_SIZE);
-                let (producer, consumer) = ring_buffer.split();
-                let producer = Arc::new(parking_lot::Mutex::new(producer));
-                let consumer = Arc::new(parking_lot::Mutex::new(consumer));
+                self.mic_ring = Some(Arc::new(HeapRb::new(RING_BUFFER_SIZE)));
+                self.current_output_device = output_device;
+                self.current_input_device = input_device;
+                self.current_local_device = local_device;
 
                 // Update sidetone route
-                sidetone_route.store(route as u32, Ordering::Relaxed);
+                self.controls.sidetone_route.store(route as u32, Ordering::Relaxed);
 
-                // Start input stream (mic capture)
-                if let Some(ref input_name) = input_device {
-                    match create_input_stream(Some(input_name.as_str()), Arc::clone(&producer), Arc::clone(&mic_level)) {
-                        Ok(new_stream) => {
-                            if let Err(e) = new_stream.play() {
-                                eprintln!("Failed to start mic input: {}", e);
-                            } else {
-                                input_stream = Some(new_stream);
-                                eprintln!("[audio] Mic input started: {}", input_name);
-                            }
-                        }
-                        Err(e) => eprintln!("Failed to create mic input stream: {}", e),
+                // No mic until the input stream reports its sample rate
+                self.controls.ring_stats.input_sample_rate.store(0, Ordering::Relaxed);
+
+                // Start input stream (mic capture), falling back to the default device
+                match self.open_input() {
+                    Ok(new_stream) => {
+                        self.input_stream = Some(new_stream);
+                        self.input_watch.arm(Instant::now());
+                        eprintln!("[audio] Mic input started: {:?}", self.current_input_device);
                     }
-                } else {
-                    // Try default input device
-                    match create_input_stream(None, Arc::clone(&producer), Arc::clone(&mic_level)) {
-                        Ok(new_stream) => {
-                            if let Err(e) = new_stream.play() {
-                                eprintln!("Failed to start default mic: {}", e);
-                            } else {
-                                input_stream = Some(new_stream);
-                                eprintln!("[audio] Default mic input started");
-                            }
-                        }
-                        Err(e) => eprintln!("No mic available: {}", e),
+                    Err(e) => {
+                        eprintln!("Failed to start mic input: {}", e);
+                        report_open_failure(&mut self.input_watch, &self.recovery_tx, e, Instant::now());
                     }
                 }
 
-                // Determine if sidetone should go to main output
-                let include_sidetone_in_output = route == SidetoneRoute::OutputOnly || route == SidetoneRoute::Both;
-
                 // Start main output stream (mic + optionally sidetone mixed) for VB-Cable/Zoom
-                match create_output_stream(
-                    output_device.as_deref(),
-                    Arc::clone(&sidetone),
-                    Arc::clone(&is_key_down),
-                    Arc::clone(&consumer),
-                    Arc::clone(&mic_volume),
-                    Arc::clone(&output_level),
-                    include_sidetone_in_output,
-                    Arc::clone(&mic_ducking_enabled),
-                    Arc::clone(&mic_ducking_hold),
-                    Arc::clone(&is_recording),
-                    Arc::clone(&recording_buffer),
-                    Arc::clone(&sample_rate),
-                ) {
+                match self.open_output() {
                     Ok(new_stream) => {
-                        if let Err(e) = new_stream.play() {
-                            eprintln!("Failed to start audio output: {}", e);
-                        } else {
-                            output_stream = Some(new_stream);
-                            eprintln!("[audio] Audio output started (sidetone: {})", include_sidetone_in_output);
-                        }
+                        self.output_stream = Some(new_stream);
+                        self.output_watch.arm(Instant::now());
+                        eprintln!("[audio] Audio output started (sidetone: {})", route.includes_output());
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to start audio output: {}", e);
+                        report_open_failure(&mut self.output_watch, &self.recovery_tx, e, Instant::now());
                     }
-                    Err(e) => eprintln!("Failed to create audio output stream: {}", e),
                 }
 
                 // Start local output stream (sidetone only) if routing requires it
-                let need_local_output = route == SidetoneRoute::LocalOnly || route == SidetoneRoute::Both;
+                let need_local_output = route.includes_local();
                 eprintln!("[audio] Need local output: {} (route={:?})", need_local_output, route as u32);
                 if need_local_output {
-                    let local_dev = local_device.as_deref();
-                    eprintln!("[audio] Creating local output stream with device: {:?}", local_dev);
-                    match create_local_output_stream(
-                        local_dev,
-                        Arc::clone(&local_sidetone),
-                        Arc::clone(&is_key_down),
-                        Arc::clone(&local_volume),
-                    ) {
+                    match self.open_local() {
                         Ok(new_stream) => {
-                            if let Err(e) = new_stream.play() {
-                                eprintln!("[audio] Failed to start local output: {}", e);
-                            } else {
-                                local_stream = Some(new_stream);
-                                eprintln!("[audio] Local sidetone output started successfully!");
-                                // Routing is now handled in create_local_output_stream
-                            }
+                            self.local_stream = Some(new_stream);
+                            self.local_watch.arm(Instant::now());
                         }
-                        Err(e) => eprintln!("[audio] Failed to create local output stream: {}", e),
+                        Err(e) => report_open_failure(&mut self.local_watch, &self.recovery_tx, e, Instant::now()),
                     }
                 } else {
                     eprintln!("[audio] Skipping local output (not needed for this route)");
                 }
             }
-            Ok(AudioCommand::Stop) => {
-                output_stream = None;
-                local_stream = None;
-                input_stream = None;
+            AudioCommand::Stop => {
+                self.output_stream = None;
+                self.local_stream = None;
+                self.input_stream = None;
+                self.input_watch.disarm();
+                self.output_watch.disarm();
+                self.local_watch.disarm();
+                *self.shared.stream_configs.lock() = StreamConfigs::default();
+                self.controls.ring_stats.input_sample_rate.store(0, Ordering::Relaxed);
             }
-            Ok(AudioCommand::SetFrequency(freq)) => {
-                sidetone.lock().set_frequency(freq);
-                local_sidetone.lock().set_frequency(freq);
+            AudioCommand::SetSidetoneRoute(route) => {
+                // The callbacks read the route atomic directly, so the main output
+                // (and the mic going to Zoom) keeps running. Only the local monitor
+                // stream is opened or closed on demand.
+                self.controls.sidetone_route.store(route as u32, Ordering::Relaxed);
+                // A local monitor that failed to open is already being retried by the watchdog
+                let local_expected = self.local_stream.is_some() || self.local_watch.is_active();
+                if self.output_stream.is_some() {
+                    if route.includes_local() && !local_expected {
+                        eprintln!("[audio] Route change: opening local output");
+                        match self.open_local() {
+                            Ok(new_stream) => {
+                                self.local_stream = Some(new_stream);
+                                self.local_watch.arm(Instant::now());
+                            }
+                            Err(e) => report_open_failure(&mut self.local_watch, &self.recovery_tx, e, Instant::now()),
+                        }
+                    } else if !route.includes_local() && local_expected {
+                        eprintln!("[audio] Route change: closing local output");
+                        self.local_stream = None;
+                        self.local_watch.disarm();
+                        self.shared.stream_configs.lock().local = None;
+                    }
+                }
             }
-            Ok(AudioCommand::SetVolume(vol)) => {
-                sidetone.lock().set_volume(vol);
+            AudioCommand::SetStreamPreferences(preferences) => {
+                if self.backend.set_preferences(preferences) {
+                    eprintln!("[audio] Stream preferences changed: {:?}", preferences);
+
+                    // Reopen whatever is running so the new config takes effect
+                    let had_input = self.input_stream.is_some();
+                    let had_output = self.output_stream.is_some();
+                    let had_local = self.local_stream.is_some();
+                    self.input_stream = None;
+                    self.output_stream = None;
+                    self.local_stream = None;
+
+                    if had_input {
+                        match self.open_input() {
+                            Ok(new_stream) => {
+                                self.input_stream = Some(new_stream);
+                                self.input_watch.arm(Instant::now());
+                            }
+                            Err(e) => {
+                                eprintln!("[audio] Failed to reopen mic input: {}", e);
+                                self.shared.stream_configs.lock().input = None;
+                                report_open_failure(&mut self.input_watch, &self.recovery_tx, e, Instant::now());
+                            }
+                        }
+                    }
+                    if had_output {
+                        match self.open_output() {
+                            Ok(new_stream) => {
+                                self.output_stream = Some(new_stream);
+                                self.output_watch.arm(Instant::now());
+                            }
+                            Err(e) => {
+                                eprintln!("[audio] Failed to reopen audio output: {}", e);
+                                self.shared.stream_configs.lock().output = None;
+                                report_open_failure(&mut self.output_watch, &self.recovery_tx, e, Instant::now());
+                            }
+                        }
+                    }
+                    if had_local {
+                        match self.open_local() {
+                            Ok(new_stream) => {
+                                self.local_stream = Some(new_stream);
+                                self.local_watch.arm(Instant::now());
+                            }
+                            Err(e) => {
+                                self.shared.stream_configs.lock().local = None;
+                                report_open_failure(&mut self.local_watch, &self.recovery_tx, e, Instant::now());
+                            }
+                        }
+                    }
+                }
+            }
+            AudioCommand::StartLoopback { device, reply } => {
+                eprintln!("[audio] Starting loopback capture from: {}", device);
+                self.loopback_stream = None;
+                let opened = match self.backend.open_loopback(&device, self.shared.latency.loopback_detector(), StreamMonitor::new()) {
+                    Ok(stream) => {
+                        self.loopback_stream = Some(stream);
+                        Ok(())
+                    }
+                    Err(e) => {
+                        eprintln!("[audio] Failed to start loopback capture: {}", e);
+                        Err(e)
+                    }
+                };
+                let _ = reply.send(opened);
             }
-            Ok(AudioCommand::SetLocalVolume(vol)) => {
-                local_sidetone.lock().set_volume(vol);
+            AudioCommand::StopLoopback => {
+                self.loopback_stream = None;
             }
-            Ok(AudioCommand::SetMicVolume(_vol)) => {
-                // mic_volume is read directly from atomic in the callback
+            AudioCommand::StartRxDecoder { device, reply } => {
+                eprintln!("[audio] Starting receive decoder capture from: {}", device);
+                self.rx_stream = None;
+                let opened = match self.backend.open_tone_capture(&device, self.shared.rx_tone.detector(), StreamMonitor::new()) {
+                    Ok(stream) => {
+                        self.rx_stream = Some(stream);
+                        Ok(())
+                    }
+                    Err(e) => {
+                        eprintln!("[audio] Failed to start receive decoder capture: {}", e);
+                        Err(e)
+                    }
+                };
+                let _ = reply.send(opened);
             }
-            Ok(AudioCommand::SetSidetoneRoute(_route)) => {
-                // Route changes require restart of audio to take effect
-                // The atomic is updated, but streams need restart
+            AudioCommand::StopRxDecoder => {
+                self.rx_stream = None;
+            }
+            AudioCommand::StartToneKeying { device, reply } => {
+                eprintln!("[audio] Starting tone keying capture from: {}", device);
+                self.tone_key_stream = None;
+                let opened = match self.backend.open_tone_capture(&device, self.shared.tone_key.key_detector(), StreamMonitor::new()) {
+                    Ok(stream) => {
+                        self.tone_key_stream = Some(stream);
+                        Ok(())
+                    }
+                    Err(e) => {
+                        eprintln!("[audio] Failed to start tone keying capture: {}", e);
+                        Err(e)
+                    }
+                };
+                let _ = reply.send(opened);
             }
-            Ok(AudioCommand::StartTestRecording) => {
+            AudioCommand::StopToneKeying => {
+                self.tone_key_stream = None;
+            }
+            AudioCommand::StartTestRecording => {
                 eprintln!("[audio] Starting test recording...");
                 // Recording flag is already set by handle method
             }
-            Ok(AudioCommand::StopTestRecording) => {
-                eprintln!("[audio] Stopped test recording. Samples: {}", recording_buffer.lock().len());
+            AudioCommand::StopTestRecording => {
+                eprintln!("[audio] Stopped test recording. Samples: {}", self.shared.recording_buffer.lock().len());
                 // Recording flag is already cleared by handle method
             }
-            Ok(AudioCommand::StartPlayback { device }) => {
+            AudioCommand::StartPlayback { device, recording_rate } => {
                 eprintln!("[audio] Starting playback on device: {:?}", device);
                 // Stop any existing playback stream
-                playback_stream = None;
-
-                // Create playback stream
-                match create_playback_stream(
-                    device.as_deref(),
-                    Arc::clone(&recording_buffer),
-                    Arc::clone(&is_playing),
-                    Arc::clone(&playback_position),
-                ) {
+                self.playback_stream = None;
+
+                let pipeline = PlaybackPipeline::new(
+                    self.shared.recording_buffer.lock().clone(),
+                    recording_rate,
+                    Arc::clone(&self.shared.is_playing),
+                    Arc::clone(&self.shared.playback_position),
+                );
+                match self.backend.open_playback(device.as_deref(), pipeline, StreamMonitor::new()) {
                     Ok(new_stream) => {
-                        if let Err(e) = new_stream.play() {
-                            eprintln!("[audio] Failed to start playback: {}", e);
-                            is_playing.store(false, Ordering::Relaxed);
-                        } else {
-                            playback_stream = Some(new_stream);
-                            eprintln!("[audio] Playback started");
-                        }
+                        self.playback_stream = Some(new_stream);
+                        eprintln!("[audio] Playback started");
                     }
                     Err(e) => {
-                        eprintln!("[audio] Failed to create playback stream: {}", e);
-                        is_playing.store(false, Ordering::Relaxed);
+                        eprintln!("[audio] Failed to start playback: {}", e);
+                        self.shared.is_playing.store(false, Ordering::Relaxed);
                     }
                 }
             }
-            Ok(AudioCommand::StopPlayback) => {
+            AudioCommand::StopPlayback => {
                 eprintln!("[audio] Stopping playback");
-                playback_stream = None;
+                self.playback_stream = None;
                 // is_playing flag is already cleared by handle method
             }
-            Ok(AudioCommand::Shutdown) | Err(_) => {
-                output_stream = None;
-                local_stream = None;
-                input_stream = None;
-                playback_stream = None;
-                break;
-            }
+            AudioCommand::Shutdown => return false,
         }
+        true
     }
-}
-
-type MicConsumer = Arc<parking_lot::Mutex<ringbuf::HeapCons<f32>>>;
-type MicProducer = Arc<parking_lot::Mutex<ringbuf::HeapProd<f32>>>;
 
-/// Create an audio input stream (microphone capture)
-fn create_input_stream(
-    device_name: Option<&str>,
-    producer: MicProducer,
-    mic_level: Arc<AtomicU32>,
-) -> Result<Stream, String> {
-    // On macOS, always request microphone permission via AVFoundation.
-    // CoreAudio alone doesn't always trigger the TCC permission dialog,
-    // especially with ad-hoc signed apps. We call requestAccess every time
-    // (not just when status != authorized) because it also initializes the
-    // AVFoundation mic subsystem, which may be needed for CoreAudio to work.
-    #[cfg(target_os = "macos")]
-    {
-        let status = unsafe { check_microphone_permission() };
-        eprintln!("[audio] macOS mic permission status: {} (0=notDetermined, 1=restricted, 2=denied, 3=authorized)", status);
-
-        eprintln!("[audio] Requesting microphone permission...");
-        let result = unsafe { request_microphone_permission() };
-        eprintln!("[audio] Microphone permission result: {} (1=granted, 0=denied, -1=timeout)", result);
-
-        if result != 1 {
-            return Err("Microphone permission denied. Please enable in System Settings > Privacy & Security > Microphone".to_string());
+    /// Rebuild any stream that reported an error or stopped calling back
+    fn check_streams(&mut self, now: Instant) {
+        if let Some((reason, attempt)) = self.input_watch.poll(now) {
+            eprintln!("[audio] Rebuilding mic input ({}), attempt {}", reason, attempt);
+            self.input_stream = None;
+            let recovered = match self.open_input() {
+                Ok(new_stream) => {
+                    self.input_stream = Some(new_stream);
+                    true
+                }
+                Err(e) => {
+                    eprintln!("[audio] Mic input rebuild failed: {}", e);
+                    false
+                }
+            };
+            finish_recovery(&mut self.input_watch, &self.recovery_tx, reason, attempt, recovered, now);
         }
-    }
-
-    let host = cpal::default_host();
 
-    // On Linux, always use the "pipewire" ALSA device and route using pactl
-    // The device_name parameter is a PulseAudio source name, not an ALSA name
-    #[cfg(target_os = "linux")]
-    let (device, pulse_source) = {
-        eprintln!("[audio] Looking for 'pipewire' or 'default' ALSA input device...");
-        let devices: Vec<_> = host.input_devices()
-            .map_err(|e| e.to_string())?
-            .collect();
-
-        eprintln!("[audio] Available ALSA input devices:");
-        for d in &devices {
-            if let Ok(n) = d.name() {
-                eprintln!("[audio]   - '{}'", n);
-            }
+        if let Some((reason, attempt)) = self.output_watch.poll(now) {
+            eprintln!("[audio] Rebuilding audio output ({}), attempt {}", reason, attempt);
+            self.output_stream = None;
+            let recovered = match self.open_output() {
+                Ok(new_stream) => {
+                    self.output_stream = Some(new_stream);
+                    true
+                }
+                Err(e) => {
+                    eprintln!("[audio] Audio output rebuild failed: {}", e);
+                    false
+                }
+            };
+            finish_recovery(&mut self.output_watch, &self.recovery_tx, reason, attempt, recovered, now);
         }
 
-        let dev = devices.iter()
-            .find(|d| d.name().map(|n| n == "pipewire" || n == "default").unwrap_or(false))
-            .cloned()
-            .or_else(|| host.default_input_device())
-            .ok_or_else(|| "No pipewire/default input device available".to_string())?;
-
-        eprintln!("[audio] Using ALSA input device: {:?}", dev.name());
-        if let Some(name) = device_name {
-            eprintln!("[audio] Will route to PulseAudio source: {}", name);
+        if let Some((reason, attempt)) = self.local_watch.poll(now) {
+            eprintln!("[audio] Rebuilding local output ({}), attempt {}", reason, attempt);
+            self.local_stream = None;
+            let recovered = match self.open_local() {
+                Ok(new_stream) => {
+                    self.local_stream = Some(new_stream);
+                    true
+                }
+                Err(_) => false,
+            };
+            finish_recovery(&mut self.local_watch, &self.recovery_tx, reason, attempt, recovered, now);
         }
-
-        (dev, device_name.map(|s| s.to_string()))
-    };
-
-    #[cfg(not(target_os = "linux"))]
-    let device = if let Some(name) = device_name {
-        let devices: Vec<_> = host.input_devices()
-            .map_err(|e| e.to_string())?
-            .collect();
-
-        devices.iter()
-            .find(|d| d.name().map(|n| n == name).unwrap_or(false))
-            .cloned()
-            .or_else(|| host.default_input_device())
-            .ok_or_else(|| format!("Input device '{}' not found", name))?
-    } else {
-        host.default_input_device()
-            .ok_or_else(|| "No default input device".to_string())?
-    };
-
-    let config = device
-        .default_input_config()
-        .map_err(|e| e.to_string())?;
-
-    eprintln!("[audio] Input device config: channels={}, sample_rate={}, format={:?}",
-        config.channels(), config.sample_rate().0, config.sample_format());
-    eprintln!("[audio] Input device name: {:?}", device.name());
-
-    let channels = config.channels() as usize;
-
-    // Capture baseline source-output IDs before creating stream
-    #[cfg(target_os = "linux")]
-    let baseline_source_outputs = if pulse_source.is_some() {
-        get_source_output_ids()
-    } else {
-        Vec::new()
-    };
-
-    let stream = match config.sample_format() {
-        cpal::SampleFormat::F32 => build_input_stream::<f32>(&device, &config.into(), producer, channels, mic_level),
-        cpal::SampleFormat::I16 => build_input_stream::<i16>(&device, &config.into(), producer, channels, mic_level),
-        cpal::SampleFormat::U16 => build_input_stream::<u16>(&device, &config.into(), producer, channels, mic_level),
-        _ => return Err("Unsupported input sample format".to_string()),
-    }?;
-
-    // On Linux, route the source-output to the user's selected PulseAudio source
-    #[cfg(target_os = "linux")]
-    if let Some(source_name) = pulse_source {
-        route_source_output_to_device_with_baseline(source_name, baseline_source_outputs);
     }
-
-    Ok(stream)
 }
 
-fn build_input_stream<T: cpal::SizedSample>(
-    device: &Device,
-    config: &StreamConfig,
-    producer: MicProducer,
-    channels: usize,
-    mic_level: Arc<AtomicU32>,
-) -> Result<Stream, String>
-where
-    f32: FromSample<T>,
-{
-    let callback_count = Arc::new(AtomicU32::new(0));
-    let callback_count_clone = Arc::clone(&callback_count);
-
-    let stream = device
-        .build_input_stream(
-            config,
-            move |data: &[T], _: &cpal::InputCallbackInfo| {
-                let mut producer = producer.lock();
-                let mut peak: f32 = 0.0;
-
-                // Convert to mono (average channels) and push to ring buffer
-                for frame in data.chunks(channels) {
-                    let sample: f32 = frame
-                        .iter()
-                        .map(|s| <f32 as FromSample<T>>::from_sample_(*s))
-                        .sum::<f32>()
-                        / channels as f32;
-                    let _ = producer.try_push(sample);
-
-                    // Track peak level
-                    peak = peak.max(sample.abs());
-                }
-
-                // Periodic diagnostic log (~once per second at typical callback rates)
-                let count = callback_count_clone.fetch_add(1, Ordering::Relaxed);
-                if count % 100 == 0 {
-                    eprintln!("[mic-input] callback #{}, peak={:.6}, samples={}, channels={}",
-                        count, peak, data.len(), channels);
+/// Audio thread: runs the engine's commands and watchdog until shutdown.
+/// The engine is built here because its streams must stay on this thread.
+fn audio_thread<B: AudioBackend>(
+    backend: B,
+    command_rx: Receiver<AudioCommand>,
+    recovery_tx: Sender<RecoveryEvent>,
+    controls: MixControls,
+    shared: EngineShared,
+) {
+    let mut engine = AudioEngine::new(backend, controls, shared, recovery_tx);
+    // Checked on a deadline, so a burst of commands can't hold off the watchdog
+    let mut next_check = Instant::now() + WATCHDOG_INTERVAL;
+    loop {
+        let command = command_rx.recv_timeout(next_check.saturating_duration_since(Instant::now()));
+        engine.drain_recording();
+        engine.log_callbacks();
+
+        match command {
+            Ok(command) => {
+                if !engine.handle(command) {
+                    break;
                 }
-
-                // Update mic level with smoothing (fast attack, slow decay)
-                let current = f32::from_bits(mic_level.load(Ordering::Relaxed));
-                let new_level = if peak > current {
-                    peak // Fast attack
-                } else {
-                    current * 0.95 + peak * 0.05 // Slow decay
-                };
-                mic_level.store(new_level.to_bits(), Ordering::Relaxed);
-            },
-            |err| eprintln!("Input stream error: {}", err),
-            None,
-        )
-        .map_err(|e| e.to_string())?;
-
-    Ok(stream)
-}
-
-/// Create an audio output stream (mic + optionally sidetone mixed) for VB-Cable/Zoom
-fn create_output_stream(
-    device_name: Option<&str>,
-    sidetone: Arc<parking_lot::Mutex<SidetoneGenerator>>,
-    is_key_down: Arc<AtomicBool>,
-    consumer: MicConsumer,
-    mic_volume: Arc<AtomicU32>,
-    output_level: Arc<AtomicU32>,
-    include_sidetone: bool,
-    mic_ducking_enabled: Arc<AtomicBool>,
-    mic_ducking_hold: Arc<AtomicU32>,
-    is_recording: Arc<AtomicBool>,
-    recording_buffer: Arc<parking_lot::Mutex<Vec<f32>>>,
-    sample_rate_out: Arc<AtomicU32>,
-) -> Result<Stream, String> {
-    let host = cpal::default_host();
-
-    // On Linux, always use the "pipewire" ALSA device and route using pactl
-    // The device_name parameter is a PulseAudio sink name, not an ALSA name
-    #[cfg(target_os = "linux")]
-    let (device, pulse_sink) = {
-        eprintln!("[audio] Looking for 'pipewire' or 'default' ALSA output device...");
-        let devices: Vec<_> = host.output_devices()
-            .map_err(|e| e.to_string())?
-            .collect();
-
-        eprintln!("[audio] Available ALSA output devices:");
-        for d in &devices {
-            if let Ok(n) = d.name() {
-                eprintln!("[audio]   - '{}'", n);
             }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
         }
 
-        let dev = devices.iter()
-            .find(|d| d.name().map(|n| n == "pipewire" || n == "default").unwrap_or(false))
-            .cloned()
-            .or_else(|| host.default_output_device())
-            .ok_or_else(|| "No pipewire/default output device available".to_string())?;
-
-        eprintln!("[audio] Using ALSA output device: {:?}", dev.name());
-        if let Some(name) = device_name {
-            eprintln!("[audio] Will route to PulseAudio sink: {}", name);
+        let now = Instant::now();
+        if now >= next_check {
+            engine.check_streams(now);
+            next_check = now + WATCHDOG_INTERVAL;
         }
-
-        (dev, device_name.map(|s| s.to_string()))
-    };
-
-    #[cfg(not(target_os = "linux"))]
-    let device = if let Some(name) = device_name {
-        let devices: Vec<_> = host.output_devices()
-            .map_err(|e| e.to_string())?
-            .collect();
-
-        devices.iter()
-            .find(|d| d.name().map(|n| n == name).unwrap_or(false))
-            .cloned()
-            .or_else(|| host.default_output_device())
-            .ok_or_else(|| format!("Output device '{}' not found", name))?
-    } else {
-        host.default_output_device()
-            .ok_or_else(|| "No default output device".to_string())?
-    };
-
-    let config = device
-        .default_output_config()
-        .map_err(|e| e.to_string())?;
-
-    let sample_rate = config.sample_rate().0 as f32;
-    let channels = config.channels() as usize;
-
-    // Update sidetone sample rate and store it for recording duration calculation
-    sidetone.lock().set_sample_rate(sample_rate);
-    sample_rate_out.store(sample_rate as u32, Ordering::Relaxed);
-
-    // Capture baseline sink-input IDs before creating stream
-    #[cfg(target_os = "linux")]
-    let baseline_sink_inputs = if pulse_sink.is_some() {
-        get_sink_input_ids()
-    } else {
-        Vec::new()
-    };
-
-    let stream = match config.sample_format() {
-        cpal::SampleFormat::F32 => build_output_stream::<f32>(&device, &config.into(), sidetone, is_key_down, consumer, mic_volume, output_level, include_sidetone, channels, mic_ducking_enabled, mic_ducking_hold, is_recording, recording_buffer),
-        cpal::SampleFormat::I16 => build_output_stream::<i16>(&device, &config.into(), sidetone, is_key_down, consumer, mic_volume, output_level, include_sidetone, channels, mic_ducking_enabled, mic_ducking_hold, is_recording, recording_buffer),
-        cpal::SampleFormat::U16 => build_output_stream::<u16>(&device, &config.into(), sidetone, is_key_down, consumer, mic_volume, output_level, include_sidetone, channels, mic_ducking_enabled, mic_ducking_hold, is_recording, recording_buffer),
-        _ => return Err("Unsupported output sample format".to_string()),
-    }?;
-
-    // On Linux, route the sink-input to the user's selected PulseAudio sink
-    #[cfg(target_os = "linux")]
-    if let Some(sink_name) = pulse_sink {
-        route_sink_input_to_device_with_baseline(sink_name, baseline_sink_inputs);
     }
-
-    Ok(stream)
-}
-
-fn build_output_stream<T: cpal::SizedSample + cpal::FromSample<f32>>(
-    device: &Device,
-    config: &StreamConfig,
-    sidetone: Arc<parking_lot::Mutex<SidetoneGenerator>>,
-    is_key_down: Arc<AtomicBool>,
-    consumer: MicConsumer,
-    mic_volume: Arc<AtomicU32>,
-    output_level: Arc<AtomicU32>,
-    include_sidetone: bool,
-    channels: usize,
-    mic_ducking_enabled: Arc<AtomicBool>,
-    mic_ducking_hold: Arc<AtomicU32>,
-    is_recording: Arc<AtomicBool>,
-    recording_buffer: Arc<parking_lot::Mutex<Vec<f32>>>,
-) -> Result<Stream, String> {
-    let stream = device
-        .build_output_stream(
-            config,
-            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-                let key_down = is_key_down.load(Ordering::Relaxed);
-                let mic_vol = f32::from_bits(mic_volume.load(Ordering::Relaxed));
-                let ducking_enabled = mic_ducking_enabled.load(Ordering::Relaxed);
-                let mut sidetone = sidetone.lock();
-                let mut consumer = consumer.lock();
-                let mut peak: f32 = 0.0;
-
-                // Track samples processed for ducking hold countdown
-                let mut samples_in_frame = 0u32;
-
-                for frame in data.chunks_mut(channels) {
-                    samples_in_frame += 1;
-
-                    // Get sidetone sample (only if routing includes it)
-                    let tone_sample = if include_sidetone {
-                        sidetone.next_sample(key_down)
-                    } else {
-                        // Still need to advance the generator to keep it in sync
-                        let _ = sidetone.next_sample(key_down);
-                        0.0
-                    };
-
-                    // Get mic sample from ring buffer (or silence if empty)
-                    let raw_mic = consumer.try_pop().unwrap_or(0.0);
-
-                    // Apply mic ducking: mute mic while key is down or during hold period
-                    let ducking_hold = mic_ducking_hold.load(Ordering::Relaxed);
-                    let should_duck = ducking_enabled && (key_down || ducking_hold > 0);
-                    let mic_sample = if should_duck { 0.0 } else { raw_mic * mic_vol };
-
-                    // Mix: add sidetone and mic together
-                    let mixed = (tone_sample + mic_sample).clamp(-1.0, 1.0);
-
-                    // Capture sample for test recording if active
-                    if is_recording.load(Ordering::Relaxed) {
-                        if let Some(mut buf) = recording_buffer.try_lock() {
-                            if buf.len() < MAX_RECORDING_SAMPLES {
-                                buf.push(mixed);
-                            }
-                        }
-                    }
-
-                    // Track output peak level
-                    peak = peak.max(mixed.abs());
-
-                    let value = T::from_sample(mixed);
-                    for channel in frame.iter_mut() {
-                        *channel = value;
-                    }
-                }
-
-                // Decrement ducking hold counter (only when key is up and ducking is enabled)
-                if ducking_enabled && !key_down {
-                    let current_hold = mic_ducking_hold.load(Ordering::Relaxed);
-                    if current_hold > 0 {
-                        let new_hold = current_hold.saturating_sub(samples_in_frame);
-                        mic_ducking_hold.store(new_hold, Ordering::Relaxed);
-                    }
-                }
-
-                // Update output level with smoothing
-                let current = f32::from_bits(output_level.load(Ordering::Relaxed));
-                let new_level = if peak > current {
-                    peak
-                } else {
-                    current * 0.95 + peak * 0.05
-                };
-                output_level.store(new_level.to_bits(), Ordering::Relaxed);
-            },
-            |err| eprintln!("Output stream error: {}", err),
-            None,
-        )
-        .map_err(|e| e.to_string())?;
-
-    Ok(stream)
 }
 
-/// Create a local output stream (sidetone only) for monitoring through headphones/speakers
-fn create_local_output_stream(
-    device_name: Option<&str>,
-    sidetone: Arc<parking_lot::Mutex<SidetoneGenerator>>,
-    is_key_down: Arc<AtomicBool>,
-    _local_volume: Arc<AtomicU32>,
-) -> Result<Stream, String> {
-    let host = cpal::default_host();
-
-    // On Linux, always use the "pipewire" ALSA device and route using pactl
-    // The device_name parameter is a PulseAudio sink name, not an ALSA name
-    #[cfg(target_os = "linux")]
-    let (device, pulse_sink) = {
-        eprintln!("[audio] Looking for 'pipewire' or 'default' ALSA device for local output...");
-        let devices: Vec<_> = host.output_devices()
-            .map_err(|e| e.to_string())?
-            .collect();
-
-        let dev = devices.iter()
-            .find(|d| d.name().map(|n| n == "pipewire" || n == "default").unwrap_or(false))
-            .cloned()
-            .or_else(|| host.default_output_device())
-            .ok_or_else(|| "No pipewire/default output device for local monitoring".to_string())?;
-
-        eprintln!("[audio] Using ALSA local output device: {:?}", dev.name());
-        if let Some(name) = device_name {
-            eprintln!("[audio] Will route local sidetone to PulseAudio sink: {}", name);
-        }
-
-        (dev, device_name.map(|s| s.to_string()))
-    };
-
-    #[cfg(not(target_os = "linux"))]
-    let device = if let Some(name) = device_name {
-        let devices: Vec<_> = host.output_devices()
-            .map_err(|e| e.to_string())?
-            .collect();
-
-        devices.iter()
-            .find(|d| d.name().map(|n| n == name).unwrap_or(false))
-            .cloned()
-            .or_else(|| host.default_output_device())
-            .ok_or_else(|| format!("Local output device '{}' not found", name))?
-    } else {
-        host.default_output_device()
-            .ok_or_else(|| "No default output device for local monitoring".to_string())?
-    };
-
-    let config = device
-        .default_output_config()
-        .map_err(|e| e.to_string())?;
-
-    let sample_rate = config.sample_rate().0 as f32;
-    let channels = config.channels() as usize;
-
-    // Update sidetone sample rate
-    sidetone.lock().set_sample_rate(sample_rate);
-
-    // Capture baseline sink-input IDs before creating stream
-    #[cfg(target_os = "linux")]
-    let baseline_sink_inputs = get_sink_input_ids();
-
-    let stream = match config.sample_format() {
-        cpal::SampleFormat::F32 => build_local_output_stream::<f32>(&device, &config.into(), sidetone, is_key_down, channels),
-        cpal::SampleFormat::I16 => build_local_output_stream::<i16>(&device, &config.into(), sidetone, is_key_down, channels),
-        cpal::SampleFormat::U16 => build_local_output_stream::<u16>(&device, &config.into(), sidetone, is_key_down, channels),
-        _ => return Err("Unsupported output sample format".to_string()),
-    }?;
-
-    // On Linux, route the local sidetone to the user's selected PulseAudio sink
-    // If no specific device selected, route to default speakers (away from VailZoomer)
-    #[cfg(target_os = "linux")]
-    if let Some(sink_name) = pulse_sink {
-        route_sink_input_to_device_with_baseline(sink_name, baseline_sink_inputs);
+/// Record the outcome of a rebuild attempt and report it to the frontend
+fn finish_recovery(
+    watch: &mut StreamWatch,
+    recovery_tx: &Sender<RecoveryEvent>,
+    reason: String,
+    attempt: u32,
+    recovered: bool,
+    now: Instant,
+) {
+    if recovered {
+        eprintln!("[audio] {:?} stream recovered after {} attempt(s)", watch.kind, attempt);
+        watch.arm(now);
     } else {
-        // No specific device - route to default speakers using baseline
-        route_local_stream_to_default_speakers_with_baseline(baseline_sink_inputs);
+        watch.retry_later(now);
     }
-
-    Ok(stream)
+    let _ = recovery_tx.try_send(RecoveryEvent {
+        stream: watch.kind,
+        reason,
+        attempt,
+        recovered,
+    });
 }
 
-fn build_local_output_stream<T: cpal::SizedSample + cpal::FromSample<f32>>(
-    device: &Device,
-    config: &StreamConfig,
-    sidetone: Arc<parking_lot::Mutex<SidetoneGenerator>>,
-    is_key_down: Arc<AtomicBool>,
-    channels: usize,
-) -> Result<Stream, String> {
-    // Debug counters for local output
-    let callback_count = Arc::new(AtomicU32::new(0));
-    let callback_count_clone = Arc::clone(&callback_count);
-    let has_logged_first = Arc::new(AtomicBool::new(false));
-    let has_logged_first_clone = Arc::clone(&has_logged_first);
-    let has_logged_keydown = Arc::new(AtomicBool::new(false));
-    let has_logged_keydown_clone = Arc::clone(&has_logged_keydown);
-
-    let stream_result = device
-        .build_output_stream(
-            config,
-            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-                let key_down = is_key_down.load(Ordering::Relaxed);
-                let mut sidetone = sidetone.lock();
-
-                // Debug: log first callback to confirm stream is running
-                let count = callback_count_clone.fetch_add(1, Ordering::Relaxed);
-                if count == 0 && !has_logged_first_clone.swap(true, Ordering::Relaxed) {
-                    eprintln!("[audio-local] *** First callback! Stream is running. Buffer size: {} ***", data.len());
-                }
-
-                // Debug: log first key_down detection
-                if key_down && !has_logged_keydown_clone.swap(true, Ordering::Relaxed) {
-                    eprintln!("[audio-local] *** First key_down detected! Generating sidetone ***");
-                }
-
-                for frame in data.chunks_mut(channels) {
-                    // Get sidetone sample (volume is already in the generator)
-                    let tone_sample = sidetone.next_sample(key_down);
-
-                    let value = T::from_sample(tone_sample);
-                    for channel in frame.iter_mut() {
-                        *channel = value;
-                    }
-                }
-            },
-            |err| eprintln!("Local output stream error: {}", err),
-            None,
-        )
-        .map_err(|e| e.to_string())?;
-
-    Ok(stream_result)
+/// A stream failed to open: keep expecting it, so the watchdog retries it
+/// with backoff like a failed rebuild, and report the failure to the frontend
+fn report_open_failure(watch: &mut StreamWatch, recovery_tx: &Sender<RecoveryEvent>, err: String, now: Instant) {
+    let reason = format!("failed to open: {}", err);
+    watch.open_failed(reason.clone(), now);
+    let _ = recovery_tx.try_send(RecoveryEvent {
+        stream: watch.kind,
+        reason,
+        attempt: 1,
+        recovered: false,
+    });
 }
 
-/// Create a playback stream for test recording playback
-fn create_playback_stream(
-    device_name: Option<&str>,
-    recording_buffer: Arc<parking_lot::Mutex<Vec<f32>>>,
-    is_playing: Arc<AtomicBool>,
-    playback_position: Arc<AtomicUsize>,
-) -> Result<Stream, String> {
-    let host = cpal::default_host();
-
-    // On Linux, always use the "pipewire" ALSA device and route using pactl
-    #[cfg(target_os = "linux")]
-    let (device, pulse_sink) = {
-        eprintln!("[audio] Looking for 'pipewire' or 'default' ALSA device for playback...");
-        let devices: Vec<_> = host.output_devices()
-            .map_err(|e| e.to_string())?
-            .collect();
-
-        let dev = devices.iter()
-            .find(|d| d.name().map(|n| n == "pipewire" || n == "default").unwrap_or(false))
-            .cloned()
-            .or_else(|| host.default_output_device())
-            .ok_or_else(|| "No pipewire/default output device for playback".to_string())?;
-
-        eprintln!("[audio] Using ALSA playback device: {:?}", dev.name());
-        if let Some(name) = device_name {
-            eprintln!("[audio] Will route playback to PulseAudio sink: {}", name);
-        }
-
-        (dev, device_name.map(|s| s.to_string()))
-    };
-
-    #[cfg(not(target_os = "linux"))]
-    let device = if let Some(name) = device_name {
-        let devices: Vec<_> = host.output_devices()
-            .map_err(|e| e.to_string())?
-            .collect();
-
-        devices.iter()
-            .find(|d| d.name().map(|n| n == name).unwrap_or(false))
-            .cloned()
-            .or_else(|| host.default_output_device())
-            .ok_or_else(|| format!("Playback device '{}' not found", name))?
-    } else {
-        host.default_output_device()
-            .ok_or_else(|| "No default output device for playback".to_string())?
-    };
-
-    let config = device
-        .default_output_config()
-        .map_err(|e| e.to_string())?;
-
-    let channels = config.channels() as usize;
-
-    // Capture baseline sink-input IDs before creating stream
-    #[cfg(target_os = "linux")]
-    let baseline_sink_inputs = get_sink_input_ids();
-
-    let stream = match config.sample_format() {
-        cpal::SampleFormat::F32 => build_playback_stream::<f32>(&device, &config.into(), recording_buffer, is_playing, playback_position, channels),
-        cpal::SampleFormat::I16 => build_playback_stream::<i16>(&device, &config.into(), recording_buffer, is_playing, playback_position, channels),
-        cpal::SampleFormat::U16 => build_playback_stream::<u16>(&device, &config.into(), recording_buffer, is_playing, playback_position, channels),
-        _ => return Err("Unsupported output sample format".to_string()),
-    }?;
-
-    // On Linux, route playback to the user's selected PulseAudio sink or default speakers
-    #[cfg(target_os = "linux")]
-    if let Some(sink_name) = pulse_sink {
-        route_sink_input_to_device_with_baseline(sink_name, baseline_sink_inputs);
-    } else {
-        route_local_stream_to_default_speakers_with_baseline(baseline_sink_inputs);
-    }
-
+/// Create the local sidetone monitor stream, logging any failure
+fn start_local_output<B: AudioBackend>(
+    backend: &mut B,
+    local_device: Option<&str>,
+    key_edges: &KeyEdgeQueue,
+    bleed_tap: HeapProd<f32>,
+    controls: &MixControls,
+    monitor: StreamMonitor,
+) -> Result<B::Stream, String> {
+    eprintln!("[audio] Creating local output stream with device: {:?}", local_device);
+    let pipeline = LocalPipeline::new(
+        key_edges.local_schedule(),
+        bleed_tap,
+        controls.bleed.clone(),
+        controls.local.clone(),
+        Arc::clone(&controls.sidetone_route),
+        controls.key_timing.clone(),
+    );
+    let stream = backend
+        .open_local_output(local_device, pipeline, monitor)
+        .inspect_err(|e| eprintln!("[audio] Failed to start local output: {}", e))?;
+    eprintln!("[audio] Local sidetone output started successfully!");
+    // Routing is handled by the backend
     Ok(stream)
 }
 
-fn build_playback_stream<T: cpal::SizedSample + cpal::FromSample<f32>>(
-    device: &Device,
-    config: &StreamConfig,
-    recording_buffer: Arc<parking_lot::Mutex<Vec<f32>>>,
-    is_playing: Arc<AtomicBool>,
-    playback_position: Arc<AtomicUsize>,
-    channels: usize,
-) -> Result<Stream, String> {
-    let stream = device
-        .build_output_stream(
-            config,
-            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-                // Check if playback should continue
-                if !is_playing.load(Ordering::Relaxed) {
-                    // Fill with silence
-                    for sample in data.iter_mut() {
-                        *sample = T::from_sample(0.0f32);
-                    }
-                    return;
-                }
-
-                let buf = recording_buffer.lock();
-                let buf_len = buf.len();
-
-                for frame in data.chunks_mut(channels) {
-                    let pos = playback_position.load(Ordering::Relaxed);
-
-                    let sample = if pos < buf_len {
-                        buf[pos]
-                    } else {
-                        // Playback finished
-                        is_playing.store(false, Ordering::Relaxed);
-                        0.0
-                    };
-
-                    playback_position.fetch_add(1, Ordering::Relaxed);
-
-                    let value = T::from_sample(sample);
-                    for channel in frame.iter_mut() {
-                        *channel = value;
-                    }
-                }
-            },
-            |err| eprintln!("Playback stream error: {}", err),
-            None,
-        )
-        .map_err(|e| e.to_string())?;
-
-    Ok(stream)
-}
+/// Shared SPSC ring; the producer and consumer halves live in the callbacks
+type MicRing = Arc<HeapRb<f32>>;
 
 /// On Linux, move our local sidetone stream to the default speakers.
 /// Uses baseline IDs to identify our newly created stream.
@@ -1512,6 +1684,7 @@ fn list_pulseaudio_sinks() -> Option<Vec<DeviceInfo>> {
                 display_name: description,
                 // Store PulseAudio name - used with pactl move-sink-input
                 internal_name: pa_name,
+                channels: dev.sample_spec.channels as u16,
             })
         })
         .collect();
@@ -1523,6 +1696,18 @@ fn list_pulseaudio_sinks() -> Option<Vec<DeviceInfo>> {
     }
 }
 
+/// Channel count of a PulseAudio sink, to open the stream routed to it with
+#[cfg(target_os = "linux")]
+fn pulseaudio_sink_channels(name: &str) -> Option<u16> {
+    list_pulseaudio_sinks()?.into_iter().find(|d| d.internal_name == name).map(|d| d.channels)
+}
+
+/// Channel count of a PulseAudio source, to open the stream routed to it with
+#[cfg(target_os = "linux")]
+fn pulseaudio_source_channels(name: &str) -> Option<u16> {
+    list_pulseaudio_sources()?.into_iter().find(|d| d.internal_name == name).map(|d| d.channels)
+}
+
 #[cfg(target_os = "linux")]
 fn list_pulseaudio_sources() -> Option<Vec<DeviceInfo>> {
     use pulsectl::controllers::SourceController;
@@ -1542,6 +1727,7 @@ fn list_pulseaudio_sources() -> Option<Vec<DeviceInfo>> {
                 display_name: description,
                 // Store PulseAudio name - used with pactl move-source-output
                 internal_name: pa_name,
+                channels: dev.sample_spec.channels as u16,
             })
         })
         .collect();
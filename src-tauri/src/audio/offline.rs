@@ -0,0 +1,891 @@
+use std::f32::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::{MappedMutexGuard, Mutex, MutexGuard};
+
+use super::backend::AudioBackend;
+use super::health::StreamMonitor;
+use super::latency::LoopbackDetector;
+use super::pipeline::{InputProcessor, LocalPipeline, MicCapture, MixPipeline, PlaybackPipeline};
+use super::stream_config::StreamPreferences;
+use super::tone::ToneDetector;
+
+/// Where the offline backend gets its "microphone" audio from
+pub(super) enum OfflineSource {
+    Silence,
+    Sine { frequency: f32, amplitude: f32 },
+    Samples(Vec<f32>),
+}
+
+impl OfflineSource {
+    /// Load a WAV file as a mono source (channels are averaged)
+    pub fn from_wav(path: &Path) -> Result<Self, String> {
+        let mut reader = hound::WavReader::open(path).map_err(|e| e.to_string())?;
+        let spec = reader.spec();
+        let channels = spec.channels as usize;
+        let samples: Vec<f32> = match spec.sample_format {
+            hound::SampleFormat::Float => reader
+                .samples::<f32>()
+                .collect::<Result<_, _>>()
+                .map_err(|e| e.to_string())?,
+            hound::SampleFormat::Int => {
+                let scale = (1_i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|s| s.map(|s| s as f32 / scale))
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| e.to_string())?
+            }
+        };
+        let mono = samples
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect();
+        Ok(Self::Samples(mono))
+    }
+
+    fn sample(&self, index: usize, sample_rate: f32) -> f32 {
+        match self {
+            Self::Silence => 0.0,
+            Self::Sine { frequency, amplitude } => {
+                (2.0 * PI * frequency * index as f32 / sample_rate).sin() * amplitude
+            }
+            Self::Samples(samples) => samples.get(index).copied().unwrap_or(0.0),
+        }
+    }
+}
+
+/// Handle returned for each opened offline stream; dropping it stops the
+/// stream and drops its pipeline, as closing a device stream would
+pub(super) struct OfflineStream {
+    close: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl Drop for OfflineStream {
+    fn drop(&mut self) {
+        if let Some(close) = self.close.take() {
+            close();
+        }
+    }
+}
+
+/// A pipeline the offline backend runs until its stream is dropped
+struct OfflineSlot<P> {
+    pipeline: P,
+    monitor: StreamMonitor,
+    /// Identifies the stream that opened this slot
+    token: Arc<()>,
+}
+
+/// Slot shared between the backend and the stream handle that can close it
+type SharedSlot<P> = Arc<Mutex<Option<OfflineSlot<P>>>>;
+
+impl<P: Send + 'static> OfflineSlot<P> {
+    /// Put `pipeline` in `slot`, replacing whatever ran there
+    fn open(slot: &SharedSlot<P>, pipeline: P, monitor: StreamMonitor) -> OfflineStream {
+        let token = Arc::new(());
+        *slot.lock() = Some(Self {
+            pipeline,
+            monitor,
+            token: Arc::clone(&token),
+        });
+        let slot = Arc::clone(slot);
+        OfflineStream {
+            close: Some(Box::new(move || {
+                let mut slot = slot.lock();
+                // A stream opened since then owns the slot now
+                if slot.as_ref().is_some_and(|open| Arc::ptr_eq(&open.token, &token)) {
+                    *slot = None;
+                }
+            })),
+        }
+    }
+
+    /// The pipeline for one callback, or None if no stream is open
+    fn run(slot: &SharedSlot<P>) -> Option<MappedMutexGuard<'_, P>> {
+        MutexGuard::try_map(slot.lock(), |slot| {
+            let slot = slot.as_mut()?;
+            slot.monitor.beat();
+            Some(&mut slot.pipeline)
+        })
+        .ok()
+    }
+}
+
+/// Backend that runs the pipelines without sound devices.
+///
+/// Input, main output and local output share one clock and are advanced
+/// block by block with `render`; the outputs are collected in memory.
+/// The clock is virtual: it starts at construction and advances by exactly
+/// one sample period per rendered frame.
+pub(super) struct OfflineBackend {
+    sample_rate: u32,
+    block_size: usize,
+    start: Instant,
+    frames_rendered: usize,
+    source: OfflineSource,
+    source_pos: usize,
+    capture: SharedSlot<MicCapture>,
+    mix: SharedSlot<MixPipeline>,
+    local: SharedSlot<LocalPipeline>,
+    /// Hears the main output directly, like a sink monitor
+    loopback: SharedSlot<LoopbackDetector>,
+    /// Tone detector (receive decoder or tone keying), also hearing the main output
+    tone: SharedSlot<ToneDetector>,
+    playback: SharedSlot<PlaybackPipeline>,
+    /// Rate the playback device runs at, one frame per rendered frame
+    pub playback_rate: u32,
+    /// Rate the local monitor device runs at, also one frame per rendered frame
+    pub local_rate: u32,
+    /// Device names that fail to open, like an unplugged interface
+    pub unavailable: Vec<String>,
+    /// Local monitor leaking into the mic, like speakers: (gain, delay in frames)
+    pub bleed: Option<(f32, usize)>,
+    /// Everything the main output produced (mono)
+    pub output: Vec<f32>,
+    /// Everything the local monitor produced (mono)
+    pub local_output: Vec<f32>,
+    /// Everything the test recording playback produced (mono)
+    pub playback_output: Vec<f32>,
+}
+
+impl OfflineBackend {
+    pub fn new(sample_rate: u32, block_size: usize, source: OfflineSource) -> Self {
+        Self {
+            sample_rate,
+            block_size,
+            start: Instant::now(),
+            frames_rendered: 0,
+            source,
+            source_pos: 0,
+            capture: SharedSlot::default(),
+            mix: SharedSlot::default(),
+            local: SharedSlot::default(),
+            loopback: SharedSlot::default(),
+            tone: SharedSlot::default(),
+            playback: SharedSlot::default(),
+            playback_rate: sample_rate,
+            local_rate: sample_rate,
+            unavailable: Vec::new(),
+            bleed: None,
+            output: Vec::new(),
+            local_output: Vec::new(),
+            playback_output: Vec::new(),
+        }
+    }
+
+    /// Fail like a device that isn't there if `device_name` is unavailable
+    fn check_available(&self, device_name: Option<&str>) -> Result<(), String> {
+        match device_name {
+            Some(name) if self.unavailable.iter().any(|unavailable| unavailable == name) => {
+                Err(format!("Device '{}' not found", name))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Virtual time of the next frame to be rendered
+    pub fn now(&self) -> Instant {
+        self.time_of(self.frames_rendered)
+    }
+
+    /// Virtual time of the given frame
+    pub fn time_of(&self, frame: usize) -> Instant {
+        self.start + Duration::from_secs_f64(frame as f64 / self.sample_rate as f64)
+    }
+
+    /// Run all open streams for `frames` samples
+    pub fn render(&mut self, frames: usize) {
+        let mut mic_block = vec![0.0_f32; self.block_size];
+        let mut out_block = vec![0.0_f32; self.block_size];
+        let mut remaining = frames;
+
+        while remaining > 0 {
+            let n = remaining.min(self.block_size);
+            let now = self.now();
+            let end = self.time_of(self.frames_rendered + n);
+
+            if let Some(mut capture) = OfflineSlot::run(&self.capture) {
+                for (i, sample) in mic_block[..n].iter_mut().enumerate() {
+                    *sample = self.source.sample(self.source_pos, self.sample_rate as f32);
+                    self.source_pos += 1;
+                    if let Some((gain, delay)) = self.bleed {
+                        let frame = (self.frames_rendered + i).checked_sub(delay);
+                        *sample += gain * frame.and_then(|f| self.local_output.get(f)).copied().unwrap_or(0.0);
+                    }
+                }
+                capture.process(&mic_block[..n], 1, now);
+            }
+
+            if let Some(mut mix) = OfflineSlot::run(&self.mix) {
+                mix.process(&mut out_block[..n], 1, now);
+                self.output.extend_from_slice(&out_block[..n]);
+
+                // Captured buffers are delivered once their last frame is in
+                if let Some(mut loopback) = OfflineSlot::run(&self.loopback) {
+                    loopback.process(&out_block[..n], 1, end);
+                }
+                if let Some(mut tone) = OfflineSlot::run(&self.tone) {
+                    tone.process(&out_block[..n], 1, end);
+                }
+            }
+
+            if let Some(mut local) = OfflineSlot::run(&self.local) {
+                local.process(&mut out_block[..n], 1, now);
+                self.local_output.extend_from_slice(&out_block[..n]);
+            }
+
+            if let Some(mut playback) = OfflineSlot::run(&self.playback) {
+                playback.process(&mut out_block[..n], 1, now);
+                self.playback_output.extend_from_slice(&out_block[..n]);
+            }
+
+            self.frames_rendered += n;
+            remaining -= n;
+        }
+    }
+}
+
+impl AudioBackend for OfflineBackend {
+    type Stream = OfflineStream;
+
+    /// Offline streams always run at the backend's own rate and block size
+    fn set_preferences(&mut self, _preferences: StreamPreferences) -> bool {
+        false
+    }
+
+    fn open_input(&mut self, device_name: Option<&str>, mut capture: MicCapture, monitor: StreamMonitor) -> Result<OfflineStream, String> {
+        self.check_available(device_name)?;
+        capture.prepare(self.sample_rate);
+        Ok(OfflineSlot::open(&self.capture, capture, monitor))
+    }
+
+    fn open_output(&mut self, device_name: Option<&str>, mut pipeline: MixPipeline, monitor: StreamMonitor) -> Result<OfflineStream, String> {
+        self.check_available(device_name)?;
+        pipeline.prepare(self.sample_rate);
+        Ok(OfflineSlot::open(&self.mix, pipeline, monitor))
+    }
+
+    fn open_local_output(&mut self, device_name: Option<&str>, mut pipeline: LocalPipeline, monitor: StreamMonitor) -> Result<OfflineStream, String> {
+        self.check_available(device_name)?;
+        pipeline.prepare(self.local_rate);
+        Ok(OfflineSlot::open(&self.local, pipeline, monitor))
+    }
+
+    fn open_loopback(&mut self, device_name: &str, mut detector: LoopbackDetector, monitor: StreamMonitor) -> Result<OfflineStream, String> {
+        self.check_available(Some(device_name))?;
+        detector.prepare(self.sample_rate);
+        Ok(OfflineSlot::open(&self.loopback, detector, monitor))
+    }
+
+    fn open_tone_capture(&mut self, device_name: &str, mut detector: ToneDetector, monitor: StreamMonitor) -> Result<OfflineStream, String> {
+        self.check_available(Some(device_name))?;
+        detector.prepare(self.sample_rate);
+        Ok(OfflineSlot::open(&self.tone, detector, monitor))
+    }
+
+    fn open_playback(&mut self, device_name: Option<&str>, mut pipeline: PlaybackPipeline, monitor: StreamMonitor) -> Result<OfflineStream, String> {
+        self.check_available(device_name)?;
+        pipeline.prepare(self.playback_rate);
+        Ok(OfflineSlot::open(&self.playback, pipeline, monitor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::analyzer::SpectrumControls;
+    use crate::audio::bleed::BleedControls;
+    use crate::audio::channels::ChannelControls;
+    use crate::audio::dynamics::{CompressorControls, GateControls};
+    use crate::audio::health::{RecoveryEvent, StreamKind};
+    use crate::audio::key_timing::{KeyEdge, KeyEdgeQueue, KeyTimingControls};
+    use crate::audio::latency::{match_onsets, LatencyMeter};
+    use crate::audio::meters::MeterControls;
+    use crate::audio::pipeline::{DuckingControls, DuckingSettings, EnvelopeControls, LocalControls, MicRecorder, MixControls, WaveformControls};
+    use crate::audio::session::SessionControls;
+    use crate::audio::vad::{VadControls, VadSettings};
+    use crate::audio::{AudioCommand, AudioEngine, EngineShared, EnvelopeShape, MicRingStats, MixMode, Waveform, SidetoneRoute, RING_BUFFER_SIZE};
+    use ringbuf::traits::{Consumer, Observer, Split};
+    use ringbuf::{HeapCons, HeapRb};
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    const RATE: u32 = 48000;
+
+    struct Rig {
+        backend: OfflineBackend,
+        controls: MixControls,
+        keys: KeyEdgeQueue,
+        latency: LatencyMeter,
+        local: LocalControls,
+        /// Test recording taps of the mix and of the mic alone
+        mix_recording: HeapCons<f32>,
+        mic_recording: HeapCons<f32>,
+        /// Capture, mix and local monitor run as long as the rig holds them
+        _streams: [OfflineStream; 3],
+    }
+
+    impl Rig {
+        /// Key edge at the current virtual time, as the handle does
+        fn key(&self, down: bool) {
+            self.keys.push(KeyEdge { down, at: self.backend.now() });
+        }
+    }
+
+    fn rig(source: OfflineSource, route: SidetoneRoute) -> Rig {
+        rig_with_block(source, route, 256)
+    }
+
+    /// Controls for a 600 Hz sidetone at half volume on both routes
+    fn controls(route: SidetoneRoute) -> MixControls {
+        MixControls {
+            frequency: Arc::new(AtomicU32::new(600.0_f32.to_bits())),
+            volume: Arc::new(AtomicU32::new(0.5_f32.to_bits())),
+            envelope: EnvelopeControls::new(),
+            waveform: WaveformControls::new(),
+            sidetone_route: Arc::new(AtomicU32::new(route as u32)),
+            mic_volume: Arc::new(AtomicU32::new(1.0_f32.to_bits())),
+            mic_gate: GateControls::new(),
+            mic_compressor: CompressorControls::new(),
+            local: LocalControls::new(600.0, 0.5),
+            bleed: BleedControls::new(),
+            output_level: Arc::new(AtomicU32::new(0.0_f32.to_bits())),
+            meters: MeterControls::new(),
+            spectrum: SpectrumControls::new(),
+            session: SessionControls::new(),
+            channels: ChannelControls::new(),
+            limiter_reduction: Arc::new(AtomicU32::new(0.0_f32.to_bits())),
+            ducking: DuckingControls::new(),
+            vad: VadControls::new(),
+            mix_mode: Arc::new(AtomicU32::new(MixMode::AlwaysMix as u32)),
+            cw_mute_tail_ms: Arc::new(AtomicU32::new(0.0_f32.to_bits())),
+            ptt_active: Arc::new(AtomicBool::new(false)),
+            is_recording: Arc::new(AtomicBool::new(false)),
+            record_mic_only: Arc::new(AtomicBool::new(false)),
+            key_timing: KeyTimingControls::new(),
+            late_key_edges: Arc::new(AtomicU32::new(0)),
+            sample_rate: Arc::new(AtomicU32::new(RATE)),
+            ring_stats: MicRingStats::new(),
+        }
+    }
+
+    fn rig_with_block(source: OfflineSource, route: SidetoneRoute, block_size: usize) -> Rig {
+        rig_on(OfflineBackend::new(RATE, block_size, source), route)
+    }
+
+    /// Open the capture, mix and local monitor on an already configured backend
+    fn rig_on(mut backend: OfflineBackend, route: SidetoneRoute) -> Rig {
+        let controls = controls(route);
+        let local = controls.local.clone();
+        let (producer, consumer) = HeapRb::<f32>::new(RING_BUFFER_SIZE).split();
+        let (recorder, mix_recording) = HeapRb::<f32>::new(RATE as usize).split();
+        let (mic_recorder, mic_recording) = HeapRb::<f32>::new(RATE as usize).split();
+        let (bleed_tap, bleed_reference) = HeapRb::<f32>::new(RATE as usize).split();
+
+        let keys = KeyEdgeQueue::new();
+        let latency = LatencyMeter::new();
+        let level = Arc::new(AtomicU32::new(0));
+        let mic_recorder = MicRecorder {
+            producer: mic_recorder,
+            is_recording: Arc::clone(&controls.is_recording),
+            mic_only: Arc::clone(&controls.record_mic_only),
+        };
+        let capture = MicCapture::new(producer, level, mic_recorder, &controls);
+        let input = backend.open_input(None, capture, StreamMonitor::new()).unwrap();
+        let mix = MixPipeline::new(controls.clone(), keys.main_schedule(), latency.probe(), consumer, recorder, bleed_reference);
+        let output = backend.open_output(None, mix, StreamMonitor::new()).unwrap();
+        let pipeline = LocalPipeline::new(
+            keys.local_schedule(),
+            bleed_tap,
+            controls.bleed.clone(),
+            local.clone(),
+            Arc::clone(&controls.sidetone_route),
+            controls.key_timing.clone(),
+        );
+        let local_output = backend.open_local_output(None, pipeline, StreamMonitor::new()).unwrap();
+        Rig {
+            backend,
+            controls,
+            keys,
+            latency,
+            local,
+            mix_recording,
+            mic_recording,
+            _streams: [input, output, local_output],
+        }
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |p, s| p.max(s.abs()))
+    }
+
+    #[test]
+    fn test_mix_levels() {
+        let mut rig = rig(OfflineSource::Sine { frequency: 300.0, amplitude: 0.25 }, SidetoneRoute::OutputOnly);
+        rig.backend.render(4800);
+        // Mic passes at unity once the resampler has primed
+        let mic_peak = peak(&rig.backend.output[2400..]);
+        assert!((mic_peak - 0.25).abs() < 0.02, "mic peak {}", mic_peak);
+
+        rig.key(true);
+        rig.controls.mic_volume.store(0.0_f32.to_bits(), Ordering::Relaxed);
+        rig.backend.render(4800);
+        // Sidetone alone at its volume
+        let tone_peak = peak(&rig.backend.output[4800 + 960..]);
+        assert!((tone_peak - 0.5).abs() < 0.01, "tone peak {}", tone_peak);
+    }
+
+    #[test]
+    fn test_mic_only_recording_bypasses_mix() {
+        let mut rig = rig(OfflineSource::Sine { frequency: 300.0, amplitude: 0.25 }, SidetoneRoute::OutputOnly);
+        // Nothing of the mic reaches the mix, and the sidetone is on
+        rig.controls.mic_volume.store(0.0_f32.to_bits(), Ordering::Relaxed);
+        rig.key(true);
+        rig.controls.is_recording.store(true, Ordering::Relaxed);
+        rig.controls.record_mic_only.store(true, Ordering::Relaxed);
+        rig.backend.render(4800);
+
+        let mic: Vec<f32> = rig.mic_recording.pop_iter().collect();
+        assert_eq!(mic.len(), 4800);
+        assert!((peak(&mic) - 0.25).abs() < 0.01, "mic peak {}", peak(&mic));
+        assert!(rig.mix_recording.is_empty());
+
+        rig.controls.record_mic_only.store(false, Ordering::Relaxed);
+        rig.backend.render(4800);
+        let mix: Vec<f32> = rig.mix_recording.pop_iter().collect();
+        assert_eq!(mix.len(), 4800);
+        assert!((peak(&mix) - 0.5).abs() < 0.01, "mix peak {}", peak(&mix));
+        assert!(rig.mic_recording.is_empty());
+    }
+
+    #[test]
+    fn test_ducking_hold_timing() {
+        let mut rig = rig(OfflineSource::Sine { frequency: 300.0, amplitude: 0.25 }, SidetoneRoute::LocalOnly);
+        let settings = DuckingSettings { enabled: true, hold_ms: 250.0, depth: 1.0, attack_ms: 5.0, release_ms: 5.0 };
+        rig.controls.ducking.set(&settings);
+        rig.backend.render(4800);
+
+        rig.key(true);
+        rig.backend.render(2400);
+        rig.key(false);
+        rig.backend.render(24000);
+
+        // Keys are heard one buffer period (the default budget) after they're pressed
+        let key_down = 4800 + 256;
+        let key_up = key_down + 2400;
+        let ms = |ms: f32| (ms * RATE as f32 / 1000.0) as usize;
+        let hold = ms(settings.hold_ms);
+        let ramp = ms(5.0);
+        // Mic silent from the end of the fade-down through the hold, back after the fade-up
+        let output = &rig.backend.output;
+        assert!(peak(&output[key_down..key_down + ramp]) > 0.05);
+        assert_eq!(peak(&output[key_down + ramp + 1..key_up + hold]), 0.0);
+        assert!(peak(&output[key_up + hold + ramp..]) > 0.24);
+        // No clicks: nothing moves faster than the 300 Hz sine itself
+        let steepest = output.windows(2).fold(0.0_f32, |m, w| m.max((w[1] - w[0]).abs()));
+        assert!(steepest < 0.011, "step {}", steepest);
+
+        // Partial depth keeps the mic audible underneath
+        rig.controls.ducking.set(&DuckingSettings { depth: 0.5, ..settings });
+        rig.key(true);
+        rig.backend.render(4800);
+        let ducked = peak(&rig.backend.output[rig.backend.output.len() - 2400..]);
+        assert!((ducked - 0.125).abs() < 0.005, "ducked peak {}", ducked);
+    }
+
+    #[test]
+    fn test_cw_mutes_mic_ignores_depth() {
+        let mut rig = rig(OfflineSource::Sine { frequency: 300.0, amplitude: 0.25 }, SidetoneRoute::LocalOnly);
+        rig.controls.mix_mode.store(MixMode::CwMutesMic as u32, Ordering::Relaxed);
+        rig.controls.cw_mute_tail_ms.store(250.0_f32.to_bits(), Ordering::Relaxed);
+        // A partial ducking depth must not leak into CwMutesMic
+        let settings = DuckingSettings { enabled: false, hold_ms: 0.0, depth: 0.5, attack_ms: 5.0, release_ms: 5.0 };
+        rig.controls.ducking.set(&settings);
+        rig.backend.render(4800);
+
+        rig.key(true);
+        rig.backend.render(2400);
+        rig.key(false);
+        rig.backend.render(24000);
+
+        let key_down = 4800 + 256;
+        let key_up = key_down + 2400;
+        let ms = |ms: f32| (ms * RATE as f32 / 1000.0) as usize;
+        let tail = ms(250.0);
+        let ramp = ms(5.0);
+        // Silent through the key-down and the whole tail, then back at full level
+        let output = &rig.backend.output;
+        assert_eq!(peak(&output[key_down + ramp + 1..key_up + tail]), 0.0);
+        assert!(peak(&output[key_up + tail + ramp..]) > 0.24);
+    }
+
+    #[test]
+    fn test_cw_mutes_mic_from_first_key_down_sample() {
+        let mut rig = rig(OfflineSource::Sine { frequency: 300.0, amplitude: 0.25 }, SidetoneRoute::LocalOnly);
+        rig.controls.mix_mode.store(MixMode::CwMutesMic as u32, Ordering::Relaxed);
+        // A slow ducking attack must not let the mic into the start of the transmission
+        let settings = DuckingSettings { attack_ms: 50.0, ..DuckingSettings::default() };
+        rig.controls.ducking.set(&settings);
+        rig.backend.render(4800);
+
+        rig.key(true);
+        rig.backend.render(2400);
+
+        let key_down = 4800 + 256;
+        let output = &rig.backend.output;
+        assert!(peak(&output[key_down - 256..key_down]) > 0.24);
+        assert_eq!(peak(&output[key_down..]), 0.0);
+    }
+
+    #[test]
+    fn test_route_selects_outputs() {
+        let mut rig = rig(OfflineSource::Silence, SidetoneRoute::LocalOnly);
+        rig.key(true);
+        rig.backend.render(4800);
+        assert_eq!(peak(&rig.backend.output), 0.0);
+        assert!(peak(&rig.backend.local_output) > 0.4);
+
+        rig.controls.sidetone_route.store(SidetoneRoute::OutputOnly as u32, Ordering::Relaxed);
+        rig.backend.render(4800);
+        assert!(peak(&rig.backend.output[4800..]) > 0.4);
+        assert_eq!(peak(&rig.backend.local_output[4800..]), 0.0);
+    }
+
+    #[test]
+    fn test_key_edges_independent_of_block_size() {
+        let mut onsets = Vec::new();
+        for block_size in [64, 256, 1024] {
+            let mut rig = rig_with_block(OfflineSource::Silence, SidetoneRoute::OutputOnly, block_size);
+            rig.controls.key_timing.latency_ms.store(25.0_f32.to_bits(), Ordering::Relaxed);
+            // Edge stamped mid-buffer: 1000 samples in
+            rig.keys.push(KeyEdge { down: true, at: rig.backend.time_of(1000) });
+            rig.backend.render(4800);
+            onsets.push(rig.backend.output.iter().position(|s| *s != 0.0).unwrap());
+            assert_eq!(rig.controls.late_key_edges.load(Ordering::Relaxed), 0);
+        }
+        // 1000 samples + 25 ms budget, within a sample of rounding
+        let expected = 1000 + 1200;
+        for onset in onsets {
+            assert!((onset as i32 - expected).abs() <= 2, "onset at {}", onset);
+        }
+    }
+
+    #[test]
+    fn test_latency_measurement() {
+        let mut rig = rig(OfflineSource::Silence, SidetoneRoute::OutputOnly);
+        rig.controls.key_timing.latency_ms.store(25.0_f32.to_bits(), Ordering::Relaxed);
+        rig.latency.set_enabled(true);
+        let _loopback = rig
+            .backend
+            .open_loopback("monitor", rig.latency.loopback_detector(), StreamMonitor::new())
+            .unwrap();
+
+        let mut key_downs = Vec::new();
+        for pulse in 0..4 {
+            let at = rig.backend.time_of(pulse * 4800 + 100);
+            key_downs.push(at);
+            rig.keys.push(KeyEdge { down: true, at });
+            rig.keys.push(KeyEdge { down: false, at: rig.backend.time_of(pulse * 4800 + 2500) });
+        }
+        rig.backend.render(4 * 4800 + 2400);
+
+        // Budget plus half of the 5 ms attack
+        let report = rig.latency.report();
+        assert_eq!(report.count, 4);
+        assert!((report.min_ms - 27.5).abs() < 0.1, "min {}", report.min_ms);
+        assert!(report.jitter_ms < 0.05, "jitter {}", report.jitter_ms);
+
+        // The loopback hears the tone as soon as it leaves the buffer
+        let delays = match_onsets(&key_downs, &rig.latency.take_onsets(), Duration::from_millis(100));
+        assert_eq!(delays.len(), 4);
+        for delay in delays {
+            assert!((25.0..27.5).contains(&delay), "loopback delay {}", delay);
+        }
+    }
+
+    #[test]
+    fn test_waveform_per_route() {
+        let mut rig = rig(OfflineSource::Silence, SidetoneRoute::Both);
+        rig.local.waveform.set(&Waveform::SoftSquare { drive: 5.0 });
+        rig.key(true);
+        rig.backend.render(4800);
+
+        // Same peak on both routes, but the local monitor carries a squarer tone
+        let power = |s: &[f32]| s.iter().map(|x| x * x).sum::<f32>();
+        let output = &rig.backend.output[960..];
+        let local = &rig.backend.local_output[960..];
+        assert!((peak(output) - peak(local)).abs() < 0.01);
+        assert!(power(local) > power(output) * 1.5);
+    }
+
+    #[test]
+    fn test_independent_local_sidetone() {
+        let mut rig = rig(OfflineSource::Silence, SidetoneRoute::Both);
+        rig.controls.frequency.store(700.0_f32.to_bits(), Ordering::Relaxed);
+        rig.local.frequency.store(550.0_f32.to_bits(), Ordering::Relaxed);
+        rig.local.volume.store(0.25_f32.to_bits(), Ordering::Relaxed);
+        rig.local.envelope.set(EnvelopeShape::Linear, 20.0, 20.0);
+        rig.key(true);
+        rig.backend.render(4800);
+
+        // Each route keeps its own pitch and level
+        let output = &rig.backend.output[1440..];
+        let local = &rig.backend.local_output[1440..];
+        assert!((peak(output) - 0.5).abs() < 0.01);
+        assert!((peak(local) - 0.25).abs() < 0.01);
+        let cycles = |s: &[f32]| s.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        let expected = |frequency: f32| (frequency * output.len() as f32 / RATE as f32) as usize;
+        assert!(cycles(output).abs_diff(expected(700.0)) <= 1);
+        assert!(cycles(local).abs_diff(expected(550.0)) <= 1);
+
+        // The local 20 ms rise is still under way 10 ms after the key is heard;
+        // the output's 5 ms one is done
+        let at_10ms = |s: &[f32], volume: f32| peak(&s[256 + 400..256 + 480]) / volume;
+        assert!(at_10ms(&rig.backend.output, 0.5) > 0.99);
+        assert!(at_10ms(&rig.backend.local_output, 0.25) < 0.6);
+    }
+
+    #[test]
+    fn test_bleed_cancellation() {
+        let residual = |enabled: bool| {
+            let mut rig = rig(OfflineSource::Silence, SidetoneRoute::LocalOnly);
+            // Speakers 10 ms away at half level
+            rig.backend.bleed = Some((0.5, 480));
+            rig.controls.bleed.set_enabled(enabled);
+            rig.key(true);
+            rig.backend.render(RATE as usize);
+            (peak(&rig.backend.output[RATE as usize / 2..]), rig.controls.bleed.status())
+        };
+
+        let (leaked, status) = residual(false);
+        assert!((leaked - 0.25).abs() < 0.02, "leak {}", leaked);
+        assert!(!status.active);
+
+        let (cancelled, status) = residual(true);
+        assert!(cancelled < leaked / 100.0, "residual {}", cancelled);
+        assert!(status.active && status.erle_db > 30.0 && status.convergence == 1.0, "{:?}", status);
+    }
+
+    #[test]
+    fn test_bleed_cancellation_off_when_local_rate_differs() {
+        // Headset at 44.1 kHz, main output at 48 kHz
+        let mut backend = OfflineBackend::new(RATE, 256, OfflineSource::Silence);
+        backend.local_rate = 44100;
+        let mut rig = rig_on(backend, SidetoneRoute::LocalOnly);
+        rig.backend.bleed = Some((0.5, 480));
+        rig.controls.bleed.set_enabled(true);
+        rig.key(true);
+        rig.backend.render(RATE as usize);
+
+        // The leak passes untouched rather than being cancelled against a drifting reference
+        let leaked = peak(&rig.backend.output[RATE as usize / 2..]);
+        assert!((leaked - 0.25).abs() < 0.02, "leak {}", leaked);
+        let status = rig.controls.bleed.status();
+        assert!(status.enabled && !status.active && status.rate_mismatch, "{:?}", status);
+    }
+
+    #[test]
+    fn test_voice_ducks_sidetone() {
+        // Half a second of quiet room, then the operator talks over the tone
+        let rate = RATE as f32;
+        let talk = |i: usize| -> f32 {
+            (1..=20).map(|h| (2.0 * PI * 140.0 * h as f32 * i as f32 / rate).sin() * 0.05 / h as f32).sum()
+        };
+        let samples = (0..RATE as usize).map(|i| if i < RATE as usize / 2 { 0.0 } else { talk(i) }).collect();
+        let mut rig = rig(OfflineSource::Samples(samples), SidetoneRoute::OutputOnly);
+        rig.controls.mic_volume.store(0.0_f32.to_bits(), Ordering::Relaxed);
+        rig.controls.vad.set(&VadSettings {
+            enabled: true,
+            duck_db: 12.0,
+            ..VadSettings::default()
+        });
+        rig.key(true);
+        rig.backend.render(RATE as usize);
+
+        let output = &rig.backend.output;
+        assert!(peak(&output[RATE as usize / 4..RATE as usize / 2]) > 0.45);
+        // 12 dB down once the detector has caught on
+        let ducked = peak(&output[RATE as usize * 3 / 4..]);
+        assert!((ducked - 0.125).abs() < 0.01, "ducked tone {}", ducked);
+        assert!(rig.controls.vad.is_active());
+    }
+
+    #[test]
+    fn test_engine_routes_records_and_plays_back() {
+        let backend = OfflineBackend::new(RATE, 256, OfflineSource::Silence);
+        let controls = controls(SidetoneRoute::OutputOnly);
+        let shared = EngineShared::new();
+        let (recovery_tx, recovery_rx) = crossbeam_channel::bounded(8);
+        let mut engine = AudioEngine::new(backend, controls.clone(), shared.clone(), recovery_tx);
+        let key = |engine: &AudioEngine<OfflineBackend>, down: bool| {
+            shared.key_edges.push(KeyEdge { down, at: engine.backend.now() });
+        };
+
+        assert!(engine.handle(AudioCommand::Start {
+            output_device: None,
+            input_device: None,
+            local_device: None,
+            sidetone_route: SidetoneRoute::OutputOnly,
+        }));
+        assert!(engine.local_stream.is_none());
+        key(&engine, true);
+        engine.backend.render(4800);
+        assert!(peak(&engine.backend.output[960..]) > 0.4);
+        assert!(engine.backend.local_output.is_empty());
+
+        // Adding the local route opens the monitor without touching the main output
+        engine.handle(AudioCommand::SetSidetoneRoute(SidetoneRoute::Both));
+        assert!(engine.local_stream.is_some());
+        // A newly opened monitor only hears edges from then on
+        key(&engine, true);
+        engine.backend.render(4800);
+        assert_eq!(engine.backend.output.len(), 9600);
+        assert!(peak(&engine.backend.local_output[960..]) > 0.4);
+
+        // Dropping it again stops the monitor stream
+        engine.handle(AudioCommand::SetSidetoneRoute(SidetoneRoute::OutputOnly));
+        assert!(engine.local_stream.is_none());
+        engine.backend.render(4800);
+        assert_eq!(engine.backend.local_output.len(), 4800);
+
+        // Record half a second of the mix, then play it back
+        key(&engine, false);
+        controls.is_recording.store(true, Ordering::Relaxed);
+        engine.backend.render(4800);
+        key(&engine, true);
+        engine.backend.render(19200);
+        controls.is_recording.store(false, Ordering::Relaxed);
+        engine.drain_recording();
+        let recording = shared.recording_buffer.lock().clone();
+        assert_eq!(recording.len(), 24000);
+        assert_eq!(peak(&recording[960..4800]), 0.0);
+        assert!(peak(&recording[19200..]) > 0.4);
+
+        shared.is_playing.store(true, Ordering::Relaxed);
+        engine.handle(AudioCommand::StartPlayback { device: None, recording_rate: RATE });
+        engine.backend.render(RATE as usize);
+        // At the recording's own rate the interpolator passes samples through, two late
+        let played = &engine.backend.playback_output;
+        assert_eq!(played[2..recording.len()], recording[..recording.len() - 2]);
+        assert_eq!(peak(&played[recording.len()..]), 0.0);
+        assert!(!shared.is_playing.load(Ordering::Relaxed));
+
+        // Every stream kept calling back, so the watchdog has nothing to rebuild
+        engine.check_streams(Instant::now());
+        assert!(recovery_rx.is_empty());
+        assert!(!engine.handle(AudioCommand::Shutdown));
+    }
+
+    #[test]
+    fn test_engine_rebuilds_stalled_streams() {
+        let backend = OfflineBackend::new(RATE, 256, OfflineSource::Silence);
+        let shared = EngineShared::new();
+        let (recovery_tx, recovery_rx) = crossbeam_channel::bounded(8);
+        let mut engine = AudioEngine::new(backend, controls(SidetoneRoute::OutputOnly), shared.clone(), recovery_tx);
+        engine.handle(AudioCommand::Start {
+            output_device: None,
+            input_device: None,
+            local_device: None,
+            sidetone_route: SidetoneRoute::OutputOnly,
+        });
+        engine.backend.render(4800);
+        let checked = Instant::now();
+        engine.check_streams(checked);
+        assert!(recovery_rx.is_empty());
+
+        // Nothing renders for two seconds: both streams are rebuilt
+        let stalled = checked + Duration::from_secs(2);
+        engine.check_streams(stalled);
+        let events: Vec<RecoveryEvent> = recovery_rx.try_iter().collect();
+        let kinds: Vec<StreamKind> = events.iter().map(|event| event.stream).collect();
+        assert_eq!(kinds, vec![StreamKind::Input, StreamKind::Output]);
+        for event in &events {
+            assert!(event.reason.contains("no callbacks"), "{}", event.reason);
+            assert_eq!(event.attempt, 1);
+            assert!(event.recovered);
+        }
+
+        // The rebuilt streams run, so the watchdog leaves them alone
+        shared.key_edges.push(KeyEdge { down: true, at: engine.backend.now() });
+        engine.backend.render(4800);
+        assert!(peak(&engine.backend.output[4800 + 960..]) > 0.4);
+        engine.check_streams(stalled + Duration::from_millis(500));
+        assert!(recovery_rx.is_empty());
+    }
+
+    #[test]
+    fn test_capture_open_errors_reach_the_caller() {
+        let mut backend = OfflineBackend::new(RATE, 256, OfflineSource::Silence);
+        backend.unavailable.push("unplugged".to_string());
+        let (recovery_tx, _recovery_rx) = crossbeam_channel::bounded(8);
+        let mut engine = AudioEngine::new(backend, controls(SidetoneRoute::OutputOnly), EngineShared::new(), recovery_tx);
+
+        let (reply, result) = crossbeam_channel::bounded(1);
+        engine.handle(AudioCommand::StartRxDecoder { device: "unplugged".to_string(), reply: reply.clone() });
+        assert!(result.try_recv().unwrap().unwrap_err().contains("unplugged"));
+        assert!(engine.rx_stream.is_none());
+
+        engine.handle(AudioCommand::StartRxDecoder { device: "monitor".to_string(), reply: reply.clone() });
+        assert!(result.try_recv().unwrap().is_ok());
+        assert!(engine.rx_stream.is_some());
+
+        engine.handle(AudioCommand::StartToneKeying { device: "unplugged".to_string(), reply: reply.clone() });
+        assert!(result.try_recv().unwrap().unwrap_err().contains("unplugged"));
+        assert!(engine.tone_key_stream.is_none());
+
+        engine.handle(AudioCommand::StartLoopback { device: "unplugged".to_string(), reply });
+        assert!(result.try_recv().unwrap().unwrap_err().contains("unplugged"));
+        assert!(engine.loopback_stream.is_none());
+    }
+
+    #[test]
+    fn test_playback_converts_recording_rate() {
+        let mut backend = OfflineBackend::new(RATE, 256, OfflineSource::Silence);
+        backend.playback_rate = 44100;
+        let shared = EngineShared::new();
+        let (recovery_tx, _recovery_rx) = crossbeam_channel::bounded(8);
+        let mut engine = AudioEngine::new(backend, controls(SidetoneRoute::OutputOnly), shared.clone(), recovery_tx);
+
+        // A quarter second of 441 Hz recorded at 48 kHz
+        let tone = |i: usize| (2.0 * std::f32::consts::PI * 441.0 * i as f32 / RATE as f32).sin() * 0.5;
+        *shared.recording_buffer.lock() = (0..RATE as usize / 4).map(tone).collect();
+        shared.is_playing.store(true, Ordering::Relaxed);
+        engine.handle(AudioCommand::StartPlayback { device: None, recording_rate: RATE });
+        engine.backend.render(RATE as usize / 2);
+        assert!(!shared.is_playing.load(Ordering::Relaxed));
+
+        // Still a quarter second of 441 Hz on a 44.1 kHz device: 100 samples a cycle
+        let played = &engine.backend.playback_output;
+        let end = played.iter().rposition(|s| s.abs() > 0.01).unwrap();
+        assert!((end as i32 - 11025).abs() < 100, "played {} samples", end);
+        let cycle = 100;
+        for i in (1000..10000).step_by(997) {
+            assert!((played[i] - played[i + cycle]).abs() < 0.01, "sample {}", i);
+        }
+    }
+
+    #[test]
+    fn test_wav_source() {
+        let path = std::env::temp_dir().join("vail_offline_source_test.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for _ in 0..100 {
+            writer.write_sample(16384_i16).unwrap();
+            writer.write_sample(0_i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let source = OfflineSource::from_wav(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!((source.sample(10, RATE as f32) - 0.25).abs() < 1e-4);
+        assert_eq!(source.sample(100, RATE as f32), 0.0);
+    }
+}
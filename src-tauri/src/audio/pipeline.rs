@@ -0,0 +1,838 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use cpal::FromSample;
+use ringbuf::traits::{Consumer, Observer, Producer};
+use ringbuf::{HeapCons, HeapProd};
+
+use super::analyzer::SpectrumControls;
+use super::bleed::{BleedCanceller, BleedControls};
+use super::channels::{ChannelControls, InputChannels, OutputChannel};
+use super::dynamics::{Compressor, CompressorControls, GateControls, Limiter, NoiseGate};
+use super::handoff::{Handoff, Receiver};
+use super::key_timing::{KeySchedule, KeyTimingControls};
+use super::latency::LatencyProbe;
+use super::meters::{Bus, BusMeter, MeterControls};
+use super::resampler::DriftResampler;
+use super::session::SessionControls;
+use super::vad::{VadControls, VoiceDetector, DUCK_ATTACK_MS, DUCK_RELEASE_MS};
+use super::{EnvelopeShape, MicRingStats, MixMode, SidetoneGenerator, SidetoneRoute, Waveform};
+
+/// Local monitor samples the bleed reference may queue before the oldest are skipped
+const BLEED_REFERENCE_BACKLOG_MS: f32 = 50.0;
+
+/// Sidetone envelope settings, read by the callbacks once per buffer
+#[derive(Clone)]
+pub(super) struct EnvelopeControls {
+    pub shape: Arc<AtomicU32>,
+    pub rise_ms: Arc<AtomicU32>,
+    pub fall_ms: Arc<AtomicU32>,
+}
+
+impl EnvelopeControls {
+    pub fn new() -> Self {
+        Self {
+            shape: Arc::new(AtomicU32::new(EnvelopeShape::RaisedCosine as u32)),
+            rise_ms: Arc::new(AtomicU32::new(5.0_f32.to_bits())),
+            fall_ms: Arc::new(AtomicU32::new(5.0_f32.to_bits())),
+        }
+    }
+
+    /// Called from the control side; times are in milliseconds
+    pub fn set(&self, shape: EnvelopeShape, rise_ms: f32, fall_ms: f32) {
+        self.shape.store(shape as u32, Ordering::Relaxed);
+        self.rise_ms.store(rise_ms.clamp(0.5, 50.0).to_bits(), Ordering::Relaxed);
+        self.fall_ms.store(fall_ms.clamp(0.5, 50.0).to_bits(), Ordering::Relaxed);
+    }
+
+    fn apply(&self, sidetone: &mut SidetoneGenerator) {
+        sidetone.set_envelope(
+            EnvelopeShape::from_u32(self.shape.load(Ordering::Relaxed)),
+            f32::from_bits(self.rise_ms.load(Ordering::Relaxed)),
+            f32::from_bits(self.fall_ms.load(Ordering::Relaxed)),
+        );
+    }
+}
+
+/// Mic ducking while sending
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DuckingSettings {
+    pub enabled: bool,
+    /// How long the mic stays ducked after key up
+    pub hold_ms: f32,
+    /// How far the mic is turned down (0.0 = not at all, 1.0 = muted)
+    pub depth: f32,
+    /// Fade-down time when ducking starts
+    pub attack_ms: f32,
+    /// Fade-up time when the hold runs out
+    pub release_ms: f32,
+}
+
+impl Default for DuckingSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            hold_ms: 250.0,
+            depth: 1.0,
+            attack_ms: 5.0,
+            release_ms: 20.0,
+        }
+    }
+}
+
+/// Ducking settings, read by the output callback once per buffer.
+/// CwMutesMic ignores the depth and hold: it mutes fully from the first
+/// key-down sample through its own tail (`cw_mute_tail_ms`), then fades back
+/// in with the release ramp.
+#[derive(Clone)]
+pub(super) struct DuckingControls {
+    pub enabled: Arc<AtomicBool>,
+    pub hold_ms: Arc<AtomicU32>,
+    pub depth: Arc<AtomicU32>,
+    pub attack_ms: Arc<AtomicU32>,
+    pub release_ms: Arc<AtomicU32>,
+}
+
+impl DuckingControls {
+    pub fn new() -> Self {
+        let defaults = DuckingSettings::default();
+        Self {
+            enabled: Arc::new(AtomicBool::new(defaults.enabled)),
+            hold_ms: Arc::new(AtomicU32::new(defaults.hold_ms.to_bits())),
+            depth: Arc::new(AtomicU32::new(defaults.depth.to_bits())),
+            attack_ms: Arc::new(AtomicU32::new(defaults.attack_ms.to_bits())),
+            release_ms: Arc::new(AtomicU32::new(defaults.release_ms.to_bits())),
+        }
+    }
+
+    /// Called from the control side
+    pub fn set(&self, settings: &DuckingSettings) {
+        self.hold_ms.store(settings.hold_ms.clamp(0.0, 5000.0).to_bits(), Ordering::Relaxed);
+        self.depth.store(settings.depth.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+        self.attack_ms.store(settings.attack_ms.clamp(0.0, 500.0).to_bits(), Ordering::Relaxed);
+        self.release_ms.store(settings.release_ms.clamp(0.0, 500.0).to_bits(), Ordering::Relaxed);
+        self.enabled.store(settings.enabled, Ordering::Relaxed);
+    }
+}
+
+/// Sidetone waveform for one route, handed to each callback without locks
+/// so that a replaced wavetable is freed on the control side
+#[derive(Clone)]
+pub(super) struct WaveformControls {
+    waveform: Handoff<Waveform>,
+}
+
+impl WaveformControls {
+    pub fn new() -> Self {
+        Self {
+            waveform: Handoff::new(Waveform::Sine),
+        }
+    }
+
+    /// Called from the control side
+    pub fn set(&self, waveform: &Waveform) {
+        self.waveform.set(waveform.clone());
+    }
+
+    /// Called when a callback is built; starts `sidetone` on the current waveform
+    fn subscribe(&self, sidetone: &mut SidetoneGenerator) -> Receiver<Waveform> {
+        let receiver = self.waveform.subscribe();
+        sidetone.set_waveform(receiver.get().clone());
+        receiver
+    }
+}
+
+/// Called from the callback: switch `sidetone` to a new waveform, if there is one
+fn apply_waveform(waveform: &mut Receiver<Waveform>, sidetone: &mut SidetoneGenerator) {
+    // The generator's copy of the old waveform is dropped here, but the
+    // receiver still holds it, so a wavetable is never freed in the callback
+    waveform.update(|waveform| sidetone.set_waveform(waveform.clone()));
+}
+
+/// Local monitor start-up flags, set by its callback and logged by the audio thread
+#[derive(Clone)]
+pub(super) struct LocalStats {
+    /// A local stream made its first callback, of `buffer_len` samples
+    pub started: Arc<AtomicBool>,
+    pub buffer_len: Arc<AtomicU32>,
+    /// A local stream heard its first key down
+    pub keyed: Arc<AtomicBool>,
+}
+
+impl LocalStats {
+    fn new() -> Self {
+        Self {
+            started: Arc::new(AtomicBool::new(false)),
+            buffer_len: Arc::new(AtomicU32::new(0)),
+            keyed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+/// Sidetone settings for the local monitor, independent of the output's
+#[derive(Clone)]
+pub(super) struct LocalControls {
+    pub frequency: Arc<AtomicU32>,
+    pub volume: Arc<AtomicU32>,
+    pub envelope: EnvelopeControls,
+    pub waveform: WaveformControls,
+    pub stats: LocalStats,
+}
+
+impl LocalControls {
+    pub fn new(frequency: f32, volume: f32) -> Self {
+        Self {
+            frequency: Arc::new(AtomicU32::new(frequency.to_bits())),
+            volume: Arc::new(AtomicU32::new(volume.to_bits())),
+            envelope: EnvelopeControls::new(),
+            waveform: WaveformControls::new(),
+            stats: LocalStats::new(),
+        }
+    }
+}
+
+/// Shared state read and updated by the output mix.
+/// Cloning shares the same atomics with the engine handle. The callbacks
+/// never take a lock: everything they need is either one of these atomics
+/// or owned by the pipeline.
+#[derive(Clone)]
+pub(super) struct MixControls {
+    pub frequency: Arc<AtomicU32>,
+    pub volume: Arc<AtomicU32>,
+    pub envelope: EnvelopeControls,
+    pub waveform: WaveformControls,
+    pub sidetone_route: Arc<AtomicU32>,
+    pub mic_volume: Arc<AtomicU32>,
+    pub mic_gate: GateControls,
+    pub mic_compressor: CompressorControls,
+    /// Local monitor settings
+    pub local: LocalControls,
+    pub bleed: BleedControls,
+    pub output_level: Arc<AtomicU32>,
+    /// RMS, peak and loudness of the mic, sidetone and output buses
+    pub meters: MeterControls,
+    /// Feeds the output and mic to the spectrum analyzer thread
+    pub spectrum: SpectrumControls,
+    /// Feeds the mix, mic and sidetone stems to the session recorder
+    pub session: SessionControls,
+    /// Output channel map (the input side is handed to `MicCapture`)
+    pub channels: ChannelControls,
+    /// Gain reduction of the output limiter, in dB
+    pub limiter_reduction: Arc<AtomicU32>,
+    pub ducking: DuckingControls,
+    /// Voice activity detection on the mic, ducking the sidetone while the operator talks
+    pub vad: VadControls,
+    pub mix_mode: Arc<AtomicU32>,
+    pub cw_mute_tail_ms: Arc<AtomicU32>,
+    pub ptt_active: Arc<AtomicBool>,
+    pub is_recording: Arc<AtomicBool>,
+    /// The test recording takes the raw mic instead of the mix
+    pub record_mic_only: Arc<AtomicBool>,
+    /// Key edge latency budget, shared with the local monitor
+    pub key_timing: KeyTimingControls,
+    /// Key edges that arrived too late for the latency budget
+    pub late_key_edges: Arc<AtomicU32>,
+    pub sample_rate: Arc<AtomicU32>,
+    pub ring_stats: MicRingStats,
+}
+
+/// Something that consumes interleaved capture buffers
+pub(super) trait InputProcessor: Send + 'static {
+    /// Called by the backend once the input device rate is known
+    fn prepare(&mut self, sample_rate: u32);
+
+    /// Process one interleaved input buffer delivered at `now`
+    fn process<T: Copy>(&mut self, data: &[T], channels: usize, now: Instant)
+    where
+        f32: FromSample<T>;
+}
+
+/// Mic-only test recording: the captured mic at the input rate, before
+/// it goes anywhere near the mix
+pub(super) struct MicRecorder {
+    pub producer: HeapProd<f32>,
+    pub is_recording: Arc<AtomicBool>,
+    pub mic_only: Arc<AtomicBool>,
+}
+
+/// Mic capture: downmixes input buffers into the mic ring buffer and
+/// listens for the operator's voice
+pub(super) struct MicCapture {
+    producer: HeapProd<f32>,
+    mic_level: Arc<AtomicU32>,
+    ring_stats: MicRingStats,
+    vad_controls: VadControls,
+    /// Output and local sidetone pitches, kept out of the voice detector
+    sidetone_frequencies: [Arc<AtomicU32>; 2],
+    /// Built in `prepare`, once the input device rate is known
+    vad: Option<VoiceDetector>,
+    recorder: MicRecorder,
+    channels: Receiver<InputChannels>,
+}
+
+impl MicCapture {
+    pub fn new(producer: HeapProd<f32>, mic_level: Arc<AtomicU32>, recorder: MicRecorder, controls: &MixControls) -> Self {
+        Self {
+            producer,
+            mic_level,
+            ring_stats: controls.ring_stats.clone(),
+            vad_controls: controls.vad.clone(),
+            sidetone_frequencies: [Arc::clone(&controls.frequency), Arc::clone(&controls.local.frequency)],
+            vad: None,
+            recorder,
+            channels: controls.channels.input.subscribe(),
+        }
+    }
+}
+
+impl InputProcessor for MicCapture {
+    /// Lets the output side resample from the mic rate
+    fn prepare(&mut self, sample_rate: u32) {
+        self.ring_stats.input_sample_rate.store(sample_rate, Ordering::Relaxed);
+        self.vad = Some(VoiceDetector::new(
+            self.vad_controls.clone(),
+            self.sidetone_frequencies.clone(),
+            sample_rate as f32,
+        ));
+    }
+
+    fn process<T: Copy>(&mut self, data: &[T], channels: usize, _now: Instant)
+    where
+        f32: FromSample<T>,
+    {
+        let producer = &mut self.producer;
+        let mut vad = self.vad.as_mut();
+        if let Some(vad) = &mut vad {
+            vad.begin_block();
+        }
+        let recording = self.recorder.is_recording.load(Ordering::Relaxed) && self.recorder.mic_only.load(Ordering::Relaxed);
+        self.channels.update(|_| ());
+        let downmix = self.channels.get();
+        let mut peak: f32 = 0.0;
+        let mut overrun = false;
+
+        // Convert to mono with the selected channels and push to ring buffer
+        for frame in data.chunks(channels) {
+            let sample = downmix.downmix(frame);
+            if producer.try_push(sample).is_err() {
+                overrun = true;
+            }
+            if let Some(vad) = &mut vad {
+                vad.push(sample);
+            }
+            if recording {
+                let _ = self.recorder.producer.try_push(sample);
+            }
+
+            // Track peak level
+            peak = peak.max(sample.abs());
+        }
+
+        if let Some(vad) = vad {
+            vad.end_block();
+        }
+        if overrun {
+            self.ring_stats.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+
+        // Logged by the audio thread, so the callback never touches stderr
+        self.ring_stats.callbacks.fetch_add(1, Ordering::Relaxed);
+        self.ring_stats.peak.store(peak.to_bits(), Ordering::Relaxed);
+
+        // Update mic level with smoothing (fast attack, slow decay)
+        let current = f32::from_bits(self.mic_level.load(Ordering::Relaxed));
+        let new_level = if peak > current {
+            peak // Fast attack
+        } else {
+            current * 0.95 + peak * 0.05 // Slow decay
+        };
+        self.mic_level.store(new_level.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Main output mix: resampled mic + sidetone, with ducking, mix mode gating and test/session recording
+pub(super) struct MixPipeline {
+    controls: MixControls,
+    keys: KeySchedule,
+    sidetone: SidetoneGenerator,
+    waveform: Receiver<Waveform>,
+    /// Key-to-buffer latency measurement (idle unless enabled)
+    probe: LatencyProbe,
+    consumer: HeapCons<f32>,
+    /// Test recording samples, drained by the audio thread
+    recorder: HeapProd<f32>,
+    sample_rate: f32,
+    /// Samples left in the CwMutesMic tail
+    cw_mute_remaining: u32,
+    /// Samples left in the ducking hold after key up
+    duck_remaining: u32,
+    /// Current mic gain from ducking and the mix mode, ramped towards its target
+    mic_gain: f32,
+    /// Current sidetone gain from voice ducking, ramped the same way
+    tone_gain: f32,
+    /// Converts mic audio to the output rate and absorbs clock drift
+    resampler: DriftResampler,
+    /// What the local monitor played, the reference for the bleed canceller
+    bleed_reference: HeapCons<f32>,
+    bleed: BleedCanceller,
+    gate: NoiseGate,
+    compressor: Compressor,
+    /// Keeps the final bus under full scale; delays the output by its look-ahead
+    limiter: Limiter,
+    mic_meter: BusMeter,
+    sidetone_meter: BusMeter,
+    output_meter: BusMeter,
+    /// Output and captured mic pairs for the spectrum analyzer
+    spectrum_tap: HeapProd<[f32; 2]>,
+    /// Mix, mic and sidetone frames for the session recorder
+    session_tap: HeapProd<[f32; 3]>,
+    /// Role of each output channel
+    channel_map: Receiver<Vec<OutputChannel>>,
+}
+
+impl MixPipeline {
+    pub fn new(
+        controls: MixControls,
+        keys: KeySchedule,
+        probe: LatencyProbe,
+        consumer: HeapCons<f32>,
+        recorder: HeapProd<f32>,
+        bleed_reference: HeapCons<f32>,
+    ) -> Self {
+        let mut sidetone = SidetoneGenerator::new(
+            f32::from_bits(controls.frequency.load(Ordering::Relaxed)),
+            f32::from_bits(controls.volume.load(Ordering::Relaxed)),
+            48000.0,
+        );
+        let waveform = controls.waveform.subscribe(&mut sidetone);
+        let bleed = BleedCanceller::new(controls.bleed.clone(), 48000.0);
+        let gate = NoiseGate::new(controls.mic_gate.clone(), 48000.0);
+        let compressor = Compressor::new(controls.mic_compressor.clone(), 48000.0);
+        let limiter = Limiter::new(Arc::clone(&controls.limiter_reduction), 48000.0);
+        let mic_meter = BusMeter::new(controls.meters.clone(), Bus::Mic, 48000.0);
+        let sidetone_meter = BusMeter::new(controls.meters.clone(), Bus::Sidetone, 48000.0);
+        let output_meter = BusMeter::new(controls.meters.clone(), Bus::Output, 48000.0);
+        let spectrum_tap = HeapProd::new(Arc::clone(&controls.spectrum.ring));
+        let session_tap = HeapProd::new(Arc::clone(&controls.session.ring));
+        let channel_map = controls.channels.output.subscribe();
+        Self {
+            controls,
+            keys,
+            sidetone,
+            waveform,
+            probe,
+            consumer,
+            recorder,
+            sample_rate: 48000.0,
+            cw_mute_remaining: 0,
+            duck_remaining: 0,
+            mic_gain: 1.0,
+            tone_gain: 1.0,
+            resampler: DriftResampler::new(48000.0, 48000.0),
+            bleed_reference,
+            bleed,
+            gate,
+            compressor,
+            limiter,
+            mic_meter,
+            sidetone_meter,
+            output_meter,
+            spectrum_tap,
+            session_tap,
+            channel_map,
+        }
+    }
+
+    /// Called by the backend once the output device rate is known
+    pub fn prepare(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate as f32;
+        self.resampler = DriftResampler::new(self.sample_rate, self.sample_rate);
+        self.bleed.set_sample_rate(self.sample_rate);
+        self.gate.set_sample_rate(self.sample_rate);
+        self.compressor.set_sample_rate(self.sample_rate);
+        self.limiter.set_sample_rate(self.sample_rate);
+        self.mic_meter.set_sample_rate(self.sample_rate);
+        self.sidetone_meter.set_sample_rate(self.sample_rate);
+        self.output_meter.set_sample_rate(self.sample_rate);
+        // Update sidetone sample rate and store it for recording duration calculation
+        self.sidetone.set_sample_rate(self.sample_rate);
+        self.controls.sample_rate.store(sample_rate, Ordering::Relaxed);
+    }
+
+    /// Fill one interleaved output buffer whose first frame is rendered at `now`
+    pub fn process<T>(&mut self, data: &mut [T], channels: usize, now: Instant)
+    where
+        T: Copy + FromSample<f32>,
+    {
+        let controls = &self.controls;
+        let sample_rate = self.sample_rate;
+        let keys = &mut self.keys;
+        // Render key edges early by the limiter's look-ahead so they still leave on time
+        let lookahead = self.limiter.lookahead();
+        let lookahead_time = Duration::from_secs_f64(lookahead as f64 / sample_rate as f64);
+        let delay = controls.key_timing.edge_delay(data.len() / channels, sample_rate, lookahead_time);
+        keys.begin_block(now, delay, sample_rate);
+        let route = SidetoneRoute::from_u32(controls.sidetone_route.load(Ordering::Relaxed));
+        let include_sidetone = route.includes_output();
+        let mic_vol = f32::from_bits(controls.mic_volume.load(Ordering::Relaxed));
+        let ducking = &controls.ducking;
+        let ducking_enabled = ducking.enabled.load(Ordering::Relaxed);
+        let load_ms = |atomic: &AtomicU32| f32::from_bits(atomic.load(Ordering::Relaxed)) * sample_rate / 1000.0;
+        let hold_samples = load_ms(&ducking.hold_ms) as u32;
+        let ducked_gain = 1.0 - f32::from_bits(ducking.depth.load(Ordering::Relaxed));
+        // Gain change per sample for a full-scale fade
+        let attack_step = 1.0 / load_ms(&ducking.attack_ms).max(1.0);
+        let release_step = 1.0 / load_ms(&ducking.release_ms).max(1.0);
+        let tone_target = controls.vad.sidetone_gain();
+        let tone_attack_step = 1.0 / (DUCK_ATTACK_MS * sample_rate / 1000.0);
+        let tone_release_step = 1.0 / (DUCK_RELEASE_MS * sample_rate / 1000.0);
+        let mode = MixMode::from_u32(controls.mix_mode.load(Ordering::Relaxed));
+        let ptt = controls.ptt_active.load(Ordering::Relaxed);
+        let tail_ms = f32::from_bits(controls.cw_mute_tail_ms.load(Ordering::Relaxed));
+        let tail_samples = (tail_ms * sample_rate / 1000.0) as u32;
+        let recording = controls.is_recording.load(Ordering::Relaxed) && !controls.record_mic_only.load(Ordering::Relaxed);
+        let analyzing = controls.spectrum.enabled.load(Ordering::Relaxed);
+        let session_recording = controls.session.recording.load(Ordering::Relaxed);
+        let sidetone = &mut self.sidetone;
+        sidetone.set_frequency(f32::from_bits(controls.frequency.load(Ordering::Relaxed)));
+        sidetone.set_volume(f32::from_bits(controls.volume.load(Ordering::Relaxed)));
+        controls.envelope.apply(sidetone);
+        apply_waveform(&mut self.waveform, sidetone);
+        let probe = &mut self.probe;
+        let consumer = &mut self.consumer;
+        let resampler = &mut self.resampler;
+        // Keep the local monitor's samples from piling up ahead of the mic,
+        // which would push the leak past the canceller's delay range
+        let bleed_reference = &mut self.bleed_reference;
+        let backlog = (BLEED_REFERENCE_BACKLOG_MS * sample_rate / 1000.0) as usize;
+        if bleed_reference.occupied_len() > backlog {
+            bleed_reference.skip(bleed_reference.occupied_len() - backlog / 2);
+        }
+        let bleed = &mut self.bleed;
+        bleed.begin_block(route.includes_local());
+        let gate = &mut self.gate;
+        gate.begin_block();
+        let compressor = &mut self.compressor;
+        compressor.begin_block();
+        let limiter = &mut self.limiter;
+        limiter.begin_block();
+        self.channel_map.update(|_| ());
+        let channel_map = self.channel_map.get();
+        let mut peak: f32 = 0.0;
+
+        // Resample from the mic rate, keeping the ring near its target fill
+        let input_rate = controls.ring_stats.input_sample_rate.load(Ordering::Relaxed);
+        let mic_running = input_rate > 0;
+        let mut underrun = false;
+        if mic_running {
+            resampler.set_rates(input_rate as f32, sample_rate);
+            let skip = resampler.begin_block(consumer.occupied_len(), consumer.capacity().get());
+            if skip > 0 {
+                consumer.skip(skip);
+                controls.ring_stats.overruns.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        for (i, frame) in data.chunks_mut(channels).enumerate() {
+            let key_down = keys.key_at(i);
+            if let Some(at) = keys.take_down_edge() {
+                probe.key_down(at);
+            }
+
+            // Get sidetone sample (only if routing includes it)
+            self.tone_gain = if tone_target < self.tone_gain {
+                (self.tone_gain - tone_attack_step).max(tone_target)
+            } else {
+                (self.tone_gain + tone_release_step).min(tone_target)
+            };
+            let tone_sample = if include_sidetone {
+                sidetone.next_sample(key_down) * self.tone_gain
+            } else {
+                // Still need to advance the generator to keep it in sync
+                let _ = sidetone.next_sample(key_down);
+                0.0
+            };
+            // The tone leaves the buffer once the limiter's delay has passed
+            probe.envelope(sidetone.envelope(), || now + Duration::from_secs_f64((i + lookahead) as f64 / sample_rate as f64));
+
+            // Get resampled mic sample (or silence if the ring ran dry)
+            let raw_mic = if mic_running {
+                resampler.next_sample(|| consumer.try_pop()).unwrap_or_else(|| {
+                    underrun = true;
+                    0.0
+                })
+            } else {
+                0.0
+            };
+            let captured_mic = raw_mic;
+            // Take out sidetone the mic picked up from the local speakers
+            let local_tone = bleed_reference.try_pop().unwrap_or(0.0);
+            let raw_mic = bleed.process(raw_mic, local_tone);
+
+            // Gate out keyboard and paddle noise between words, then even out the voice
+            let raw_mic = compressor.process(gate.process(raw_mic));
+
+            // Apply mic ducking: duck mic while key is down or during hold period
+            if key_down {
+                self.duck_remaining = hold_samples;
+                self.cw_mute_remaining = tail_samples;
+            }
+            let should_duck = ducking_enabled && (key_down || self.duck_remaining > 0);
+
+            // Apply mix mode gate
+            let muted = match mode {
+                MixMode::AlwaysMix => false,
+                MixMode::CwMutesMic => key_down || self.cw_mute_remaining > 0,
+                MixMode::PushToTalkVoice => !ptt,
+            };
+            if !key_down {
+                self.duck_remaining = self.duck_remaining.saturating_sub(1);
+                self.cw_mute_remaining = self.cw_mute_remaining.saturating_sub(1);
+            }
+            let target_gain = if muted {
+                0.0
+            } else if should_duck {
+                ducked_gain
+            } else {
+                1.0
+            };
+
+            // Fade rather than switch, so ducking doesn't click. The mix mode
+            // gate cuts at once: no mic may go out while the operator sends.
+            self.mic_gain = if muted {
+                0.0
+            } else if target_gain < self.mic_gain {
+                (self.mic_gain - attack_step).max(target_gain)
+            } else {
+                (self.mic_gain + release_step).min(target_gain)
+            };
+
+            let mic_sample = raw_mic * mic_vol * self.mic_gain;
+            self.mic_meter.process(mic_sample);
+            self.sidetone_meter.process(tone_sample);
+
+            // Mix: add sidetone and mic together, limiting instead of clipping where they overlap
+            let mixed = limiter.process(tone_sample + mic_sample);
+
+            self.output_meter.process(mixed);
+
+            // Capture sample for test recording if active
+            if recording {
+                let _ = self.recorder.try_push(mixed);
+            }
+            if analyzing {
+                let _ = self.spectrum_tap.try_push([mixed, captured_mic]);
+            }
+            if session_recording {
+                let _ = self.session_tap.try_push([mixed, mic_sample, tone_sample]);
+            }
+
+            // Track output peak level
+            peak = peak.max(mixed.abs());
+
+            if channel_map.is_empty() {
+                let value = T::from_sample_(mixed);
+                for channel in frame.iter_mut() {
+                    *channel = value;
+                }
+            } else {
+                for (index, channel) in frame.iter_mut().enumerate() {
+                    let role = channel_map.get(index).copied().unwrap_or_default();
+                    *channel = T::from_sample_(role.sample(mixed, mic_sample, tone_sample));
+                }
+            }
+        }
+
+        bleed.end_block();
+        gate.end_block();
+        compressor.end_block();
+        limiter.end_block();
+        let frames = data.len() / channels;
+        self.mic_meter.end_block(frames);
+        self.sidetone_meter.end_block(frames);
+        self.output_meter.end_block(frames);
+        if underrun {
+            controls.ring_stats.underruns.fetch_add(1, Ordering::Relaxed);
+        }
+        controls.ring_stats.drift_ppm.store(resampler.correction_ppm().to_bits(), Ordering::Relaxed);
+
+        let late = keys.take_late();
+        if late > 0 {
+            controls.late_key_edges.fetch_add(late, Ordering::Relaxed);
+        }
+
+        // Update output level with smoothing
+        let current = f32::from_bits(controls.output_level.load(Ordering::Relaxed));
+        let new_level = if peak > current {
+            peak
+        } else {
+            current * 0.95 + peak * 0.05
+        };
+        controls.output_level.store(new_level.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Local monitor output: sidetone only, silent while the route excludes local
+pub(super) struct LocalPipeline {
+    sidetone: SidetoneGenerator,
+    keys: KeySchedule,
+    /// Everything played, for the main output's bleed canceller
+    bleed_tap: HeapProd<f32>,
+    /// Tells the canceller what rate `bleed_tap` is filled at
+    bleed: BleedControls,
+    controls: LocalControls,
+    waveform: Receiver<Waveform>,
+    key_timing: KeyTimingControls,
+    sidetone_route: Arc<AtomicU32>,
+    sample_rate: f32,
+    /// Whether this stream has reported its first callback and first key down
+    reported_start: bool,
+    reported_key_down: bool,
+}
+
+impl LocalPipeline {
+    pub fn new(
+        keys: KeySchedule,
+        bleed_tap: HeapProd<f32>,
+        bleed: BleedControls,
+        controls: LocalControls,
+        sidetone_route: Arc<AtomicU32>,
+        key_timing: KeyTimingControls,
+    ) -> Self {
+        // Independent phase from the main output's generator
+        let mut sidetone = SidetoneGenerator::new(
+            f32::from_bits(controls.frequency.load(Ordering::Relaxed)),
+            f32::from_bits(controls.volume.load(Ordering::Relaxed)),
+            48000.0,
+        );
+        let waveform = controls.waveform.subscribe(&mut sidetone);
+        Self {
+            sidetone,
+            keys,
+            bleed_tap,
+            bleed,
+            controls,
+            waveform,
+            key_timing,
+            sidetone_route,
+            sample_rate: 48000.0,
+            reported_start: false,
+            reported_key_down: false,
+        }
+    }
+
+    /// Called by the backend once the local device rate is known
+    pub fn prepare(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate as f32;
+        self.sidetone.set_sample_rate(self.sample_rate);
+        self.bleed.set_reference_rate(sample_rate);
+    }
+
+    /// Fill one interleaved output buffer whose first frame is rendered at `now`
+    pub fn process<T>(&mut self, data: &mut [T], channels: usize, now: Instant)
+    where
+        T: Copy + FromSample<f32>,
+    {
+        let keys = &mut self.keys;
+        // Nothing delays the local output after rendering
+        let delay = self.key_timing.edge_delay(data.len() / channels, self.sample_rate, Duration::ZERO);
+        keys.begin_block(now, delay, self.sample_rate);
+        let key_down = keys.key_at(0);
+        // Stay silent while the route excludes local (stream is about to be closed)
+        let include_sidetone = SidetoneRoute::from_u32(self.sidetone_route.load(Ordering::Relaxed)).includes_local();
+        let controls = &self.controls;
+        let sidetone = &mut self.sidetone;
+        sidetone.set_frequency(f32::from_bits(controls.frequency.load(Ordering::Relaxed)));
+        sidetone.set_volume(f32::from_bits(controls.volume.load(Ordering::Relaxed)));
+        controls.envelope.apply(sidetone);
+        apply_waveform(&mut self.waveform, sidetone);
+
+        // Flag the first callback and first key down for the audio thread to log
+        let stats = &controls.stats;
+        if !self.reported_start {
+            self.reported_start = true;
+            stats.buffer_len.store(data.len() as u32, Ordering::Relaxed);
+            stats.started.store(true, Ordering::Relaxed);
+        }
+        if key_down && !self.reported_key_down {
+            self.reported_key_down = true;
+            stats.keyed.store(true, Ordering::Relaxed);
+        }
+
+        for (i, frame) in data.chunks_mut(channels).enumerate() {
+            // Get sidetone sample (volume is already in the generator)
+            let tone_sample = sidetone.next_sample(keys.key_at(i));
+            let tone_sample = if include_sidetone { tone_sample } else { 0.0 };
+            let _ = self.bleed_tap.try_push(tone_sample);
+
+            let value = T::from_sample_(tone_sample);
+            for channel in frame.iter_mut() {
+                *channel = value;
+            }
+        }
+    }
+}
+
+/// Test recording playback: the recording on every channel until it runs
+/// out, converted from the rate it was recorded at to the device's
+pub(super) struct PlaybackPipeline {
+    recording: Vec<f32>,
+    recording_rate: f32,
+    resampler: DriftResampler,
+    is_playing: Arc<AtomicBool>,
+    position: Arc<AtomicUsize>,
+}
+
+impl PlaybackPipeline {
+    pub fn new(recording: Vec<f32>, recording_rate: u32, is_playing: Arc<AtomicBool>, position: Arc<AtomicUsize>) -> Self {
+        let recording_rate = recording_rate as f32;
+        Self {
+            recording,
+            recording_rate,
+            resampler: DriftResampler::new(recording_rate, recording_rate),
+            is_playing,
+            position,
+        }
+    }
+
+    /// Update for the stream's actual sample rate
+    pub fn prepare(&mut self, sample_rate: u32) {
+        self.resampler = DriftResampler::new(self.recording_rate, sample_rate as f32);
+        // The whole recording is there from the start, so there is no fill
+        // level to track: prime once and read straight through
+        self.resampler.begin_block(usize::MAX, usize::MAX);
+    }
+
+    /// Fill one interleaved output buffer
+    pub fn process<T>(&mut self, data: &mut [T], channels: usize, _now: Instant)
+    where
+        T: Copy + FromSample<f32>,
+    {
+        // Check if playback should continue
+        if !self.is_playing.load(Ordering::Relaxed) {
+            // Fill with silence
+            data.fill(T::from_sample_(0.0));
+            return;
+        }
+
+        let recording = &self.recording;
+        let position = &self.position;
+        for frame in data.chunks_mut(channels) {
+            // Position counts recording samples, whatever rate the device runs at
+            let sample = match self.resampler.next_sample(|| recording.get(position.fetch_add(1, Ordering::Relaxed)).copied()) {
+                Some(sample) => sample,
+                None => {
+                    // Playback finished
+                    self.is_playing.store(false, Ordering::Relaxed);
+                    0.0
+                }
+            };
+
+            let value = T::from_sample_(sample);
+            for channel in frame.iter_mut() {
+                *channel = value;
+            }
+        }
+    }
+}
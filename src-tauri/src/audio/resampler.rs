@@ -0,0 +1,194 @@
+/// Target amount of mic audio to keep queued in the ring buffer
+const TARGET_FILL_MS: f32 = 20.0;
+
+/// Smoothing for the ring fill estimate (per output buffer)
+const FILL_SMOOTHING: f32 = 0.05;
+
+/// Proportional and integral gains of the drift controller
+const DRIFT_KP: f32 = 0.002;
+const DRIFT_KI: f32 = 0.00002;
+
+/// Maximum ratio correction (+/- 1%) so pitch changes stay inaudible
+const MAX_CORRECTION: f32 = 0.01;
+
+/// Converts mic audio from the input device rate to the output device rate.
+///
+/// Sits on the consumer side of the mic ring buffer. Besides the nominal rate
+/// conversion it nudges the ratio so the ring stays near a target fill level,
+/// absorbing clock drift between the two devices instead of under/overrunning.
+pub struct DriftResampler {
+    in_rate: f32,
+    out_rate: f32,
+    target_fill: f32,
+    fill_avg: f32,
+    integral: f32,
+    correction: f32,
+    /// Input samples consumed per output sample (including correction)
+    step: f32,
+    /// Fractional read position between history[1] and history[2]
+    pos: f32,
+    /// Last four input samples for cubic interpolation
+    history: [f32; 4],
+    /// Waiting for the ring to reach target fill (at start or after an underrun)
+    primed: bool,
+}
+
+impl DriftResampler {
+    pub fn new(in_rate: f32, out_rate: f32) -> Self {
+        let mut resampler = Self {
+            in_rate,
+            out_rate,
+            target_fill: 0.0,
+            fill_avg: 0.0,
+            integral: 0.0,
+            correction: 0.0,
+            step: 1.0,
+            pos: 0.0,
+            history: [0.0; 4],
+            primed: false,
+        };
+        resampler.set_rates(in_rate, out_rate);
+        resampler
+    }
+
+    /// Update the input and output sample rates
+    pub fn set_rates(&mut self, in_rate: f32, out_rate: f32) {
+        if in_rate == self.in_rate && out_rate == self.out_rate && self.target_fill > 0.0 {
+            return;
+        }
+        self.in_rate = in_rate;
+        self.out_rate = out_rate;
+        self.target_fill = in_rate * TARGET_FILL_MS / 1000.0;
+        self.integral = 0.0;
+        self.correction = 0.0;
+        self.primed = false;
+        self.update_step();
+    }
+
+    /// Call once per output buffer with the current ring fill level.
+    /// Returns the number of samples to discard from the ring to resync
+    /// after a large backlog has built up.
+    pub fn begin_block(&mut self, fill: usize, capacity: usize) -> usize {
+        let fill_f = fill as f32;
+
+        if !self.primed {
+            if fill_f >= self.target_fill {
+                self.primed = true;
+                self.fill_avg = fill_f;
+                self.pos = 0.0;
+                self.history = [0.0; 4];
+            }
+            return 0;
+        }
+
+        // Backlog too large for the controller to absorb quickly: drop down to target
+        let resync_threshold = (self.target_fill * 4.0).min(capacity as f32 * 0.75);
+        if fill_f > resync_threshold {
+            self.fill_avg = self.target_fill;
+            self.integral = 0.0;
+            return fill - self.target_fill as usize;
+        }
+
+        self.fill_avg += (fill_f - self.fill_avg) * FILL_SMOOTHING;
+        let error = (self.fill_avg - self.target_fill) / self.target_fill.max(1.0);
+        self.integral = (self.integral + error * DRIFT_KI).clamp(-MAX_CORRECTION, MAX_CORRECTION);
+        self.correction = (error * DRIFT_KP + self.integral).clamp(-MAX_CORRECTION, MAX_CORRECTION);
+        self.update_step();
+        0
+    }
+
+    /// Produce the next output sample, pulling input samples with `pop` as needed.
+    /// Returns `None` on underrun (the ring ran dry), after which the resampler
+    /// re-primes before producing audio again.
+    pub fn next_sample(&mut self, mut pop: impl FnMut() -> Option<f32>) -> Option<f32> {
+        if !self.primed {
+            return Some(0.0);
+        }
+
+        self.pos += self.step;
+        while self.pos >= 1.0 {
+            self.pos -= 1.0;
+            match pop() {
+                Some(sample) => {
+                    self.history.rotate_left(1);
+                    self.history[3] = sample;
+                }
+                None => {
+                    self.primed = false;
+                    return None;
+                }
+            }
+        }
+
+        Some(cubic(self.history, self.pos))
+    }
+
+    /// Current drift correction in parts per million
+    pub fn correction_ppm(&self) -> f32 {
+        self.correction * 1_000_000.0
+    }
+
+    fn update_step(&mut self) {
+        self.step = if self.out_rate > 0.0 {
+            self.in_rate / self.out_rate * (1.0 + self.correction)
+        } else {
+            1.0
+        };
+    }
+}
+
+/// 4-point Catmull-Rom interpolation between y[1] and y[2]
+fn cubic(y: [f32; 4], t: f32) -> f32 {
+    let a = -0.5 * y[0] + 1.5 * y[1] - 1.5 * y[2] + 0.5 * y[3];
+    let b = y[0] - 2.5 * y[1] + 2.0 * y[2] - 0.5 * y[3];
+    let c = -0.5 * y[0] + 0.5 * y[2];
+    ((a * t + b) * t + c) * t + y[1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn test_rate_conversion_output_count() {
+        // 44.1 kHz in, 48 kHz out: 1 second of input yields ~1 second of output
+        let mut resampler = DriftResampler::new(44100.0, 48000.0);
+        let mut ring: VecDeque<f32> = (0..44100).map(|i| (i as f32 * 0.01).sin()).collect();
+        resampler.begin_block(ring.len(), 100_000);
+
+        let mut produced = 0_usize;
+        while resampler.next_sample(|| ring.pop_front()).is_some() {
+            produced += 1;
+        }
+        assert!((produced as i32 - 48000).abs() < 10, "produced {}", produced);
+    }
+
+    #[test]
+    fn test_drift_correction_holds_fill_near_target() {
+        // Input clock runs 0.2% fast relative to output
+        let in_rate = 48096.0;
+        let mut resampler = DriftResampler::new(48000.0, 48000.0);
+        let mut ring: VecDeque<f32> = VecDeque::new();
+        let mut input_acc = 0.0;
+        let target = 48000.0 * TARGET_FILL_MS / 1000.0;
+
+        for _ in 0..6000 {
+            // 10 ms per block
+            input_acc += in_rate * 0.01;
+            while input_acc >= 1.0 {
+                ring.push_back(0.0);
+                input_acc -= 1.0;
+            }
+            let skip = resampler.begin_block(ring.len(), 100_000);
+            ring.drain(..skip);
+            for _ in 0..480 {
+                let _ = resampler.next_sample(|| ring.pop_front());
+            }
+        }
+
+        let fill = ring.len() as f32;
+        assert!((fill - target).abs() < target * 0.5, "fill {} target {}", fill, target);
+        assert!(resampler.correction_ppm() > 1000.0);
+    }
+}
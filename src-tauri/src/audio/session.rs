@@ -0,0 +1,316 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ringbuf::traits::Consumer;
+use ringbuf::{HeapCons, HeapRb};
+
+/// Mix, mic and sidetone frames from the output callback (~2.7 s at 48 kHz),
+/// enough to ride out a slow disk between writer passes
+pub(super) type SessionRing = Arc<HeapRb<[f32; 3]>>;
+const SESSION_RING_SIZE: usize = 131072;
+
+/// How often the writer thread moves the tapped frames to disk
+const WRITE_INTERVAL: Duration = Duration::from_millis(50);
+
+type StemWriter = hound::WavWriter<BufWriter<File>>;
+
+/// Stem files of a session, in the order of the tapped frames
+const STEMS: [&str; 3] = ["mix", "mic", "sidetone"];
+
+/// Session tap shared between the output callback and the recorder
+#[derive(Clone)]
+pub(super) struct SessionControls {
+    /// Whether the output callback feeds the tap
+    pub recording: Arc<AtomicBool>,
+    pub ring: SessionRing,
+}
+
+impl SessionControls {
+    pub fn new() -> Self {
+        Self {
+            recording: Arc::new(AtomicBool::new(false)),
+            ring: Arc::new(HeapRb::new(SESSION_RING_SIZE)),
+        }
+    }
+}
+
+/// Progress of the current (or last) session recording
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct SessionStatus {
+    pub recording: bool,
+    pub duration_seconds: f64,
+    /// Size of the stem files on disk so far
+    pub bytes_written: u64,
+    /// Sidecar JSON of the session; the stems sit next to it
+    pub path: Option<String>,
+}
+
+/// Something that happened during the session, timed from its start
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SessionEvent {
+    Key { time: f64, down: bool },
+    /// Decoded from our own keying
+    Decoded { time: f64, text: String, wpm: f32 },
+    /// Decoded from the conference audio
+    RxDecoded { time: f64, text: String, wpm: f32 },
+}
+
+#[derive(serde::Serialize)]
+struct Sidecar<'a> {
+    /// Wall clock start, in seconds since the Unix epoch
+    started_at: f64,
+    sample_rate: u32,
+    duration_seconds: f64,
+    stems: Vec<String>,
+    events: &'a [SessionEvent],
+}
+
+struct ActiveSession {
+    started: Instant,
+    started_at: f64,
+    sample_rate: u32,
+    stems: Vec<PathBuf>,
+    sidecar: PathBuf,
+    events: Vec<SessionEvent>,
+    stop: Arc<AtomicBool>,
+    writer: JoinHandle<Result<(), String>>,
+}
+
+/// Records whole sessions to disk: the mixed output, mic and sidetone stems
+/// as WAV files written off the real-time path, and a JSON sidecar with the
+/// key events and decoded text.
+pub(super) struct SessionRecorder {
+    controls: SessionControls,
+    active: parking_lot::Mutex<Option<ActiveSession>>,
+    /// Frames written by the current session's writer thread
+    frames: Arc<AtomicU64>,
+    /// Status of the last finished session
+    last: parking_lot::Mutex<SessionStatus>,
+}
+
+impl SessionRecorder {
+    pub fn new(controls: SessionControls) -> Self {
+        Self {
+            controls,
+            active: parking_lot::Mutex::new(None),
+            frames: Arc::new(AtomicU64::new(0)),
+            last: parking_lot::Mutex::new(SessionStatus::default()),
+        }
+    }
+
+    /// Start recording into `directory` at the output's `sample_rate`
+    pub fn start(&self, directory: &Path, sample_rate: u32) -> Result<PathBuf, String> {
+        let mut active = self.active.lock();
+        if active.is_some() {
+            return Err("A session is already being recorded".to_string());
+        }
+
+        std::fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let base = format!("session-{}", since_epoch.as_secs());
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let stems: Vec<PathBuf> = STEMS.iter().map(|stem| directory.join(format!("{}-{}.wav", base, stem))).collect();
+        let writers = stems
+            .iter()
+            .map(|path| hound::WavWriter::create(path, spec).map_err(|e| format!("{}: {}", path.display(), e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let sidecar = directory.join(format!("{}.json", base));
+
+        // Anything left in the ring is from before this session
+        let mut consumer = HeapCons::new(Arc::clone(&self.controls.ring));
+        consumer.clear();
+        self.frames.store(0, Ordering::Relaxed);
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let stop = Arc::clone(&stop);
+            let frames = Arc::clone(&self.frames);
+            let recording = Arc::clone(&self.controls.recording);
+            thread::spawn(move || {
+                let result = writer_thread(consumer, writers, &stop, &frames);
+                // Stop feeding the ring if the disk gave up
+                recording.store(false, Ordering::Relaxed);
+                result
+            })
+        };
+        self.controls.recording.store(true, Ordering::Relaxed);
+
+        *active = Some(ActiveSession {
+            started: Instant::now(),
+            started_at: since_epoch.as_secs_f64(),
+            sample_rate,
+            stems,
+            sidecar: sidecar.clone(),
+            events: Vec::new(),
+            stop,
+            writer,
+        });
+        Ok(sidecar)
+    }
+
+    /// Stop recording, finish the stems and write the sidecar
+    pub fn stop(&self) -> Result<SessionStatus, String> {
+        let Some(session) = self.active.lock().take() else {
+            return Err("No session is being recorded".to_string());
+        };
+        self.controls.recording.store(false, Ordering::Relaxed);
+        session.stop.store(true, Ordering::Relaxed);
+        let written = session.writer.join().map_err(|_| "Session writer thread panicked".to_string());
+
+        let status = SessionStatus {
+            recording: false,
+            duration_seconds: self.frames.load(Ordering::Relaxed) as f64 / session.sample_rate as f64,
+            bytes_written: stem_bytes(&session.stems),
+            path: Some(session.sidecar.display().to_string()),
+        };
+        *self.last.lock() = status.clone();
+        written??;
+
+        let sidecar = Sidecar {
+            started_at: session.started_at,
+            sample_rate: session.sample_rate,
+            duration_seconds: status.duration_seconds,
+            stems: session
+                .stems
+                .iter()
+                .filter_map(|path| path.file_name().map(|name| name.to_string_lossy().into_owned()))
+                .collect(),
+            events: &session.events,
+        };
+        let mut file = BufWriter::new(File::create(&session.sidecar).map_err(|e| e.to_string())?);
+        serde_json::to_writer_pretty(&mut file, &sidecar).map_err(|e| e.to_string())?;
+        file.flush().map_err(|e| e.to_string())?;
+        Ok(status)
+    }
+
+    pub fn status(&self) -> SessionStatus {
+        match *self.active.lock() {
+            Some(ref session) => SessionStatus {
+                // Cleared by the writer thread if the disk gave up
+                recording: self.controls.recording.load(Ordering::Relaxed),
+                duration_seconds: self.frames.load(Ordering::Relaxed) as f64 / session.sample_rate as f64,
+                bytes_written: stem_bytes(&session.stems),
+                path: Some(session.sidecar.display().to_string()),
+            },
+            None => self.last.lock().clone(),
+        }
+    }
+
+    /// Note a key edge that happened at `at`
+    pub fn key_edge(&self, down: bool, at: Instant) {
+        if let Some(ref mut session) = *self.active.lock() {
+            let time = at.saturating_duration_since(session.started).as_secs_f64();
+            session.events.push(SessionEvent::Key { time, down });
+        }
+    }
+
+    /// Note decoded text, from our own keying or (`received`) the conference audio
+    pub fn decoded(&self, text: &str, wpm: f32, received: bool) {
+        if let Some(ref mut session) = *self.active.lock() {
+            let time = session.started.elapsed().as_secs_f64();
+            let text = text.to_string();
+            session.events.push(if received {
+                SessionEvent::RxDecoded { time, text, wpm }
+            } else {
+                SessionEvent::Decoded { time, text, wpm }
+            });
+        }
+    }
+}
+
+fn stem_bytes(stems: &[PathBuf]) -> u64 {
+    stems.iter().filter_map(|path| std::fs::metadata(path).ok()).map(|meta| meta.len()).sum()
+}
+
+/// Move tapped frames into the stem files until `stop` is set, then drain
+/// what is left and finish the files
+fn writer_thread(
+    mut consumer: HeapCons<[f32; 3]>,
+    mut writers: Vec<StemWriter>,
+    stop: &AtomicBool,
+    frames: &AtomicU64,
+) -> Result<(), String> {
+    loop {
+        // Read the flag first so the last pass picks up everything pushed before it
+        let stopping = stop.load(Ordering::Relaxed);
+        let mut written = 0;
+        while let Some(frame) = consumer.try_pop() {
+            for (writer, sample) in writers.iter_mut().zip(frame) {
+                writer.write_sample(sample).map_err(|e| e.to_string())?;
+            }
+            written += 1;
+        }
+        frames.fetch_add(written, Ordering::Relaxed);
+        if stopping {
+            break;
+        }
+        thread::sleep(WRITE_INTERVAL);
+    }
+    for writer in writers {
+        writer.finalize().map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ringbuf::traits::Producer;
+    use ringbuf::HeapProd;
+
+    #[test]
+    fn test_writes_stems_and_sidecar() {
+        let directory = std::env::temp_dir().join("vail_session_test");
+        let _ = std::fs::remove_dir_all(&directory);
+        let controls = SessionControls::new();
+        let recorder = SessionRecorder::new(controls.clone());
+        let mut tap = HeapProd::new(Arc::clone(&controls.ring));
+
+        let sidecar = recorder.start(&directory, 8000).unwrap();
+        assert!(recorder.start(&directory, 8000).is_err());
+        assert!(controls.recording.load(Ordering::Relaxed));
+        recorder.key_edge(true, Instant::now());
+        for i in 0..8000 {
+            let tone = if i % 2 == 0 { 0.5 } else { -0.5 };
+            let _ = tap.try_push([tone + 0.25, 0.25, tone]);
+        }
+        recorder.key_edge(false, Instant::now());
+        recorder.decoded("K", 20.0, false);
+
+        let status = recorder.stop().unwrap();
+        assert!(!controls.recording.load(Ordering::Relaxed));
+        assert_eq!(status.duration_seconds, 1.0);
+        assert!(status.bytes_written > 3 * 8000 * 4);
+
+        let read = |stem: &str| -> Vec<f32> {
+            let name = sidecar.file_stem().unwrap().to_string_lossy();
+            let mut reader = hound::WavReader::open(directory.join(format!("{}-{}.wav", name, stem))).unwrap();
+            reader.samples::<f32>().map(|s| s.unwrap()).collect()
+        };
+        assert_eq!(&read("mix")[..2], &[0.75, -0.25]);
+        assert_eq!(&read("mic")[..2], &[0.25, 0.25]);
+        assert_eq!(&read("sidetone")[..2], &[0.5, -0.5]);
+        assert_eq!(read("mix").len(), 8000);
+
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&sidecar).unwrap()).unwrap();
+        let _ = std::fs::remove_dir_all(&directory);
+        assert_eq!(json["sample_rate"], 8000);
+        assert_eq!(json["stems"].as_array().unwrap().len(), 3);
+        let events = json["events"].as_array().unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["type"], "key");
+        assert_eq!(events[0]["down"], true);
+        assert_eq!(events[2]["type"], "decoded");
+        assert_eq!(events[2]["text"], "K");
+    }
+}
@@ -1,4 +1,182 @@
 use std::f32::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Shortest rise/fall time accepted (shorter edges are clicks no matter the shape)
+const MIN_EDGE_MS: f32 = 0.5;
+
+/// Harmonics that can be added to the sine (2nd through 5th)
+pub const HARMONIC_COUNT: usize = 4;
+
+/// Longest single-cycle wavetable accepted
+const MAX_WAVETABLE_LEN: usize = 8192;
+
+/// Harmonics kept when loading a wavetable: the 20th of the highest (1 kHz)
+/// sidetone is still below Nyquist at 44.1 kHz
+const WAVETABLE_HARMONICS: usize = 20;
+
+/// Length a loaded wavetable is rebuilt at (~100 points per cycle of its
+/// highest harmonic, so interpolating between them adds little)
+const WAVETABLE_LEN: usize = 2048;
+
+/// Hardest the soft square is driven; past this its upper harmonics alias
+/// audibly at high sidetone pitches
+pub const MAX_SQUARE_DRIVE: f32 = 5.0;
+
+/// Points per cycle when measuring a harmonic waveform's peak
+const PEAK_SEARCH_POINTS: usize = 4096;
+
+/// Shape of the sidetone's rise and fall
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnvelopeShape {
+    Linear,          // Straight ramp (most click energy)
+    RaisedCosine,    // Half-cosine ramp
+    BlackmanHarris,  // Integral of a 4-term Blackman-Harris window (cleanest spectrum)
+}
+
+impl EnvelopeShape {
+    pub(super) fn from_u32(value: u32) -> Self {
+        match value {
+            0 => EnvelopeShape::Linear,
+            1 => EnvelopeShape::RaisedCosine,
+            _ => EnvelopeShape::BlackmanHarris,
+        }
+    }
+
+    /// Map a linear ramp position (0.0 - 1.0) to the envelope level
+    fn apply(self, ramp: f32) -> f32 {
+        match self {
+            EnvelopeShape::Linear => ramp,
+            EnvelopeShape::RaisedCosine => 0.5 - 0.5 * (PI * ramp).cos(),
+            EnvelopeShape::BlackmanHarris => {
+                // The edge's slope follows the window, normalised to end at 1.0
+                let x = 2.0 * PI * ramp;
+                (0.35875 * x - 0.48829 * x.sin() + 0.14128 / 2.0 * (2.0 * x).sin() - 0.01168 / 3.0 * (3.0 * x).sin())
+                    / (0.35875 * 2.0 * PI)
+            }
+        }
+    }
+}
+
+/// Timbre of the sidetone
+#[derive(Clone, Debug, PartialEq)]
+pub enum Waveform {
+    Sine,
+    /// Sine plus the 2nd..5th harmonics at these relative amplitudes
+    Harmonics([f32; HARMONIC_COUNT]),
+    /// Sine driven into tanh; higher drive (up to `MAX_SQUARE_DRIVE`) is closer to a square
+    SoftSquare { drive: f32 },
+    /// One cycle of a user-supplied waveform, band-limited by `load_wavetable`
+    Wavetable(Arc<[f32]>),
+}
+
+impl Waveform {
+    /// Value at `phase` (radians, 0..2π), peaking at 1.0
+    fn value(&self, phase: f32, norm: f32) -> f32 {
+        match self {
+            Waveform::Sine => phase.sin(),
+            Waveform::Harmonics(amplitudes) => {
+                let mut value = phase.sin();
+                for (n, amplitude) in amplitudes.iter().enumerate() {
+                    value += amplitude * ((n + 2) as f32 * phase).sin();
+                }
+                value * norm
+            }
+            Waveform::SoftSquare { drive } => (drive * phase.sin()).tanh() * norm,
+            Waveform::Wavetable(table) => {
+                let pos = phase / (2.0 * PI) * table.len() as f32;
+                let index = (pos as usize).min(table.len() - 1);
+                let frac = pos - index as f32;
+                let next = table[(index + 1) % table.len()];
+                table[index] + (next - table[index]) * frac
+            }
+        }
+    }
+
+    /// Gain that brings the waveform's peak to 1.0. Harmonics partly cancel,
+    /// so their peak is measured rather than bounded by the amplitudes' sum.
+    fn normalisation(&self) -> f32 {
+        match self {
+            Waveform::Harmonics(_) => {
+                let peak = (0..PEAK_SEARCH_POINTS)
+                    .map(|i| self.value(2.0 * PI * i as f32 / PEAK_SEARCH_POINTS as f32, 1.0).abs())
+                    .fold(0.0_f32, f32::max);
+                1.0 / peak
+            }
+            Waveform::SoftSquare { drive } => 1.0 / drive.tanh(),
+            _ => 1.0,
+        }
+    }
+}
+
+/// Rebuild one cycle from its first `WAVETABLE_HARMONICS` harmonics, so no
+/// partial can alias at any sidetone pitch (DC is dropped too)
+fn band_limit(cycle: &[f32]) -> Vec<f32> {
+    let n = cycle.len();
+    // An even table's Nyquist bin has no phase to rebuild from, so it's left out
+    let harmonics: Vec<(f32, f32)> = (1..=WAVETABLE_HARMONICS.min((n - 1) / 2))
+        .map(|k| {
+            let (mut re, mut im) = (0.0, 0.0);
+            for (i, sample) in cycle.iter().enumerate() {
+                let angle = 2.0 * PI * ((k * i) % n) as f32 / n as f32;
+                re += sample * angle.cos();
+                im += sample * angle.sin();
+            }
+            (re * 2.0 / n as f32, im * 2.0 / n as f32)
+        })
+        .collect();
+    (0..WAVETABLE_LEN)
+        .map(|i| {
+            harmonics
+                .iter()
+                .enumerate()
+                .map(|(k, (re, im))| {
+                    let angle = 2.0 * PI * (((k + 1) * i) % WAVETABLE_LEN) as f32 / WAVETABLE_LEN as f32;
+                    re * angle.cos() + im * angle.sin()
+                })
+                .sum()
+        })
+        .collect()
+}
+
+/// Load a single-cycle wavetable from a WAV file (channels are averaged,
+/// the cycle is band-limited and normalised to a peak of 1.0)
+pub fn load_wavetable(path: &Path) -> Result<Arc<[f32]>, String> {
+    let mut reader = hound::WavReader::open(path).map_err(|e| e.to_string())?;
+    let spec = reader.spec();
+    let channels = spec.channels as usize;
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .collect::<Result<_, _>>()
+            .map_err(|e| e.to_string())?,
+        hound::SampleFormat::Int => {
+            let scale = (1_i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / scale))
+                .collect::<Result<_, _>>()
+                .map_err(|e| e.to_string())?
+        }
+    };
+    let cycle: Vec<f32> = samples
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+
+    if cycle.len() < 3 || cycle.len() > MAX_WAVETABLE_LEN {
+        return Err(format!("Wavetable must be a single cycle of 3 to {} samples", MAX_WAVETABLE_LEN));
+    }
+    let mut table = band_limit(&cycle);
+    let peak = table.iter().fold(0.0_f32, |p, s| p.max(s.abs()));
+    if peak == 0.0 {
+        return Err("Wavetable is silent".to_string());
+    }
+    for sample in table.iter_mut() {
+        *sample /= peak;
+    }
+    Ok(table.into())
+}
 
 /// Sidetone generator that produces a sine wave with attack/decay envelope
 pub struct SidetoneGenerator {
@@ -7,7 +185,15 @@ pub struct SidetoneGenerator {
     sample_rate: f32,
     frequency: f32,
     volume: f32,
+    waveform: Waveform,
+    /// Peak normalisation for the current waveform
+    waveform_norm: f32,
+    /// Position along the rise/fall (0.0 - 1.0), shaped into `envelope`
+    ramp: f32,
     envelope: f32,
+    shape: EnvelopeShape,
+    rise_ms: f32,
+    fall_ms: f32,
     attack_rate: f32,
     decay_rate: f32,
 }
@@ -16,33 +202,39 @@ impl SidetoneGenerator {
     pub fn new(frequency: f32, volume: f32, sample_rate: f32) -> Self {
         let phase_increment = 2.0 * PI * frequency / sample_rate;
 
-        // Attack/decay rates for ~5ms rise/fall at 48kHz
-        let attack_rate = 1.0 / (0.005 * sample_rate);
-        let decay_rate = 1.0 / (0.005 * sample_rate);
-
-        Self {
+        let mut generator = Self {
             phase: 0.0,
             phase_increment,
             sample_rate,
             frequency,
             volume,
+            waveform: Waveform::Sine,
+            waveform_norm: 1.0,
+            ramp: 0.0,
             envelope: 0.0,
-            attack_rate,
-            decay_rate,
-        }
+            // Linear ~5ms rise/fall until configured
+            shape: EnvelopeShape::Linear,
+            rise_ms: 5.0,
+            fall_ms: 5.0,
+            attack_rate: 0.0,
+            decay_rate: 0.0,
+        };
+        generator.update_rates();
+        generator
     }
 
     /// Generate the next audio sample
     pub fn next_sample(&mut self, key_down: bool) -> f32 {
         // Update envelope with attack/decay
         if key_down {
-            self.envelope = (self.envelope + self.attack_rate).min(1.0);
+            self.ramp = (self.ramp + self.attack_rate).min(1.0);
         } else {
-            self.envelope = (self.envelope - self.decay_rate).max(0.0);
+            self.ramp = (self.ramp - self.decay_rate).max(0.0);
         }
+        self.envelope = self.shape.apply(self.ramp);
 
-        // Generate sine wave
-        let sample = self.phase.sin() * self.envelope * self.volume;
+        // Generate the waveform
+        let sample = self.waveform.value(self.phase, self.waveform_norm) * self.envelope * self.volume;
 
         // Advance phase
         self.phase += self.phase_increment;
@@ -53,12 +245,37 @@ impl SidetoneGenerator {
         sample
     }
 
+    /// Current envelope level (0.0 - 1.0), before volume
+    pub fn envelope(&self) -> f32 {
+        self.envelope
+    }
+
     /// Update the sample rate
     pub fn set_sample_rate(&mut self, sample_rate: f32) {
         self.sample_rate = sample_rate;
         self.phase_increment = 2.0 * PI * self.frequency / sample_rate;
-        self.attack_rate = 1.0 / (0.005 * sample_rate);
-        self.decay_rate = 1.0 / (0.005 * sample_rate);
+        self.update_rates();
+    }
+
+    /// Update the envelope shape and rise/fall times
+    pub fn set_envelope(&mut self, shape: EnvelopeShape, rise_ms: f32, fall_ms: f32) {
+        if shape == self.shape && rise_ms == self.rise_ms && fall_ms == self.fall_ms {
+            return;
+        }
+        self.shape = shape;
+        self.rise_ms = rise_ms;
+        self.fall_ms = fall_ms;
+        self.update_rates();
+    }
+
+    /// Update the waveform (phase is kept, so switching mid-tone doesn't click).
+    /// The peak normalisation is worked out here, not per sample.
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.waveform = match waveform {
+            Waveform::SoftSquare { drive } => Waveform::SoftSquare { drive: drive.min(MAX_SQUARE_DRIVE) },
+            waveform => waveform,
+        };
+        self.waveform_norm = self.waveform.normalisation();
     }
 
     /// Update the frequency
@@ -71,4 +288,167 @@ impl SidetoneGenerator {
     pub fn set_volume(&mut self, volume: f32) {
         self.volume = volume.clamp(0.0, 1.0);
     }
+
+    fn update_rates(&mut self) {
+        self.attack_rate = 1.0 / (self.rise_ms.max(MIN_EDGE_MS) / 1000.0 * self.sample_rate);
+        self.decay_rate = 1.0 / (self.fall_ms.max(MIN_EDGE_MS) / 1000.0 * self.sample_rate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = 48000.0;
+    const TONE: f32 = 600.0;
+
+    /// Render 25 WPM dits (48 ms on, 48 ms off) with the given envelope
+    fn keyed_tone(shape: EnvelopeShape, edge_ms: f32, len: usize) -> Vec<f32> {
+        let mut generator = SidetoneGenerator::new(TONE, 1.0, RATE);
+        generator.set_envelope(shape, edge_ms, edge_ms);
+        let dit = (0.048 * RATE) as usize;
+        (0..len).map(|i| generator.next_sample((i / dit).is_multiple_of(2))).collect()
+    }
+
+    /// Share of the signal's energy more than 1 kHz away from the tone (the key clicks)
+    fn click_energy(samples: &[f32]) -> f64 {
+        let n = samples.len();
+        let mut total = 0.0;
+        let mut clicks = 0.0;
+        for bin in 0..n / 2 {
+            let w = 2.0 * std::f64::consts::PI * bin as f64 / n as f64;
+            let (mut re, mut im) = (0.0_f64, 0.0_f64);
+            for (i, s) in samples.iter().enumerate() {
+                let angle = w * i as f64;
+                re += *s as f64 * angle.cos();
+                im -= *s as f64 * angle.sin();
+            }
+            let power = re * re + im * im;
+            total += power;
+            let freq = bin as f32 * RATE / n as f32;
+            if (freq - TONE).abs() > 1000.0 {
+                clicks += power;
+            }
+        }
+        clicks / total
+    }
+
+    #[test]
+    fn test_shapes_reach_full_level_in_rise_time() {
+        for shape in [EnvelopeShape::Linear, EnvelopeShape::RaisedCosine, EnvelopeShape::BlackmanHarris] {
+            let mut generator = SidetoneGenerator::new(TONE, 1.0, RATE);
+            generator.set_envelope(shape, 4.0, 8.0);
+            for _ in 0..(0.004 * RATE) as usize {
+                generator.next_sample(true);
+            }
+            assert!((generator.envelope() - 1.0).abs() < 1e-3, "{:?} rise", shape);
+            for _ in 0..(0.004 * RATE) as usize {
+                generator.next_sample(false);
+            }
+            let halfway = generator.envelope();
+            assert!(halfway > 0.1 && halfway < 0.9, "{:?} halfway {}", shape, halfway);
+            for _ in 0..(0.004 * RATE) as usize {
+                generator.next_sample(false);
+            }
+            assert!(generator.envelope() < 1e-3, "{:?} fall", shape);
+        }
+    }
+
+    /// Magnitude of the DFT bin at `freq`
+    fn magnitude_at(samples: &[f32], freq: f32) -> f32 {
+        let w = 2.0 * PI * freq / RATE;
+        let (re, im) = samples.iter().enumerate().fold((0.0, 0.0), |(re, im), (i, s)| {
+            (re + s * (w * i as f32).cos(), im - s * (w * i as f32).sin())
+        });
+        (re * re + im * im).sqrt() * 2.0 / samples.len() as f32
+    }
+
+    fn steady_tone(waveform: Waveform) -> Vec<f32> {
+        let mut generator = SidetoneGenerator::new(TONE, 1.0, RATE);
+        generator.set_waveform(waveform);
+        // Skip the rise, then one tenth of a second (a whole number of cycles)
+        (0..4800 + 480).map(|_| generator.next_sample(true)).skip(480).collect()
+    }
+
+    #[test]
+    fn test_waveforms() {
+        let harmonics = steady_tone(Waveform::Harmonics([0.5, 0.0, 0.25, 0.0]));
+        let fundamental = magnitude_at(&harmonics, TONE);
+        assert!((magnitude_at(&harmonics, 2.0 * TONE) / fundamental - 0.5).abs() < 0.01);
+        assert!(magnitude_at(&harmonics, 3.0 * TONE) / fundamental < 0.01);
+        assert!((magnitude_at(&harmonics, 4.0 * TONE) / fundamental - 0.25).abs() < 0.01);
+        // The harmonics partly cancel, but the tone still peaks at full scale
+        let peak = |s: &[f32]| s.iter().fold(0.0_f32, |p, x| p.max(x.abs()));
+        assert!((peak(&harmonics) - 1.0).abs() < 0.01, "harmonics peak {}", peak(&harmonics));
+
+        // Soft square: same peak as the sine, but much more power
+        let square = steady_tone(Waveform::SoftSquare { drive: 4.0 });
+        let sine = steady_tone(Waveform::Sine);
+        let power = |s: &[f32]| s.iter().map(|x| x * x).sum::<f32>();
+        assert!((peak(&square) - 1.0).abs() < 0.01);
+        assert!(power(&square) > power(&sine) * 1.5);
+        // Drive past the cap plays as the cap
+        let hard = steady_tone(Waveform::SoftSquare { drive: 20.0 });
+        assert_eq!(hard, steady_tone(Waveform::SoftSquare { drive: MAX_SQUARE_DRIVE }));
+
+        // A sine wavetable plays back as a sine
+        let table: Vec<f32> = (0..256).map(|i| (2.0 * PI * i as f32 / 256.0).sin()).collect();
+        let from_table = steady_tone(Waveform::Wavetable(table.into()));
+        for (a, b) in from_table.iter().zip(sine.iter()) {
+            assert!((a - b).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_load_wavetable() {
+        let path = std::env::temp_dir().join("vail_wavetable_test.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for sample in [0.0_f32, 0.25, 0.0, -0.5] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        // Four samples only hold a fundamental: DC and Nyquist are dropped
+        let table = load_wavetable(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(table.len(), WAVETABLE_LEN);
+        for (i, sample) in table.iter().enumerate() {
+            assert!((sample - (2.0 * PI * i as f32 / WAVETABLE_LEN as f32).sin()).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_wavetable_band_limited() {
+        let square: Vec<f32> = (0..1024).map(|i| if i < 512 { 1.0 } else { -1.0 }).collect();
+        let table = band_limit(&square);
+        let harmonic = |k: usize| {
+            let (re, im) = table.iter().enumerate().fold((0.0, 0.0), |(re, im), (i, s)| {
+                let angle = 2.0 * PI * ((k * i) % WAVETABLE_LEN) as f32 / WAVETABLE_LEN as f32;
+                (re + s * angle.cos(), im + s * angle.sin())
+            });
+            (re * re + im * im).sqrt() * 2.0 / WAVETABLE_LEN as f32
+        };
+        // Odd harmonics fall off as 1/k up to the limit, nothing is left above it
+        let fundamental = harmonic(1);
+        assert!((harmonic(3) / fundamental - 1.0 / 3.0).abs() < 0.01);
+        assert!((harmonic(19) / fundamental - 1.0 / 19.0).abs() < 0.01);
+        for k in WAVETABLE_HARMONICS + 1..WAVETABLE_HARMONICS * 3 {
+            assert!(harmonic(k) / fundamental < 1e-3, "harmonic {}", k);
+        }
+    }
+
+    #[test]
+    fn test_click_energy_drops_with_smoother_shapes() {
+        let linear = click_energy(&keyed_tone(EnvelopeShape::Linear, 5.0, 4096));
+        let cosine = click_energy(&keyed_tone(EnvelopeShape::RaisedCosine, 5.0, 4096));
+        let blackman = click_energy(&keyed_tone(EnvelopeShape::BlackmanHarris, 5.0, 4096));
+        assert!(cosine < linear * 0.5, "linear {:e}, raised cosine {:e}", linear, cosine);
+        assert!(blackman < cosine, "raised cosine {:e}, Blackman-Harris {:e}", cosine, blackman);
+    }
 }
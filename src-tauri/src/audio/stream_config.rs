@@ -0,0 +1,242 @@
+use cpal::{BufferSize, SampleFormat, StreamConfig, SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange};
+
+use super::health::StreamKind;
+
+/// Sample format the user would like the streams to use
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SampleFormatPreference {
+    #[default]
+    Auto,  // Whatever the device reports as its default
+    F32,
+    I16,
+}
+
+impl SampleFormatPreference {
+    fn format(self) -> Option<SampleFormat> {
+        match self {
+            SampleFormatPreference::Auto => None,
+            SampleFormatPreference::F32 => Some(SampleFormat::F32),
+            SampleFormatPreference::I16 => Some(SampleFormat::I16),
+        }
+    }
+}
+
+/// Requested stream configuration; `None` fields use the device default
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StreamPreferences {
+    /// Target buffer size in frames (smaller = lower latency)
+    pub buffer_frames: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub sample_format: SampleFormatPreference,
+}
+
+/// Configuration a stream was actually opened with
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct NegotiatedConfig {
+    pub stream: StreamKind,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub sample_format: String,
+    /// Buffer size in frames, `None` if the host picked it
+    pub buffer_frames: Option<u32>,
+    /// Latency of one buffer, when the buffer size is known
+    pub buffer_latency_ms: Option<f32>,
+}
+
+/// Negotiated configuration of every running stream, for the UI
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct StreamConfigs {
+    pub input: Option<NegotiatedConfig>,
+    pub output: Option<NegotiatedConfig>,
+    pub local: Option<NegotiatedConfig>,
+}
+
+impl StreamConfigs {
+    pub(super) fn set(&mut self, negotiated: NegotiatedConfig) {
+        let slot = match negotiated.stream {
+            StreamKind::Input => &mut self.input,
+            StreamKind::Output => &mut self.output,
+            StreamKind::Local => &mut self.local,
+        };
+        *slot = Some(negotiated);
+    }
+}
+
+/// Result of matching the preferences against what a device supports
+pub(super) struct ChosenConfig {
+    pub config: StreamConfig,
+    pub sample_format: SampleFormat,
+}
+
+impl ChosenConfig {
+    pub fn describe(&self, stream: StreamKind) -> NegotiatedConfig {
+        let buffer_frames = match self.config.buffer_size {
+            BufferSize::Fixed(frames) => Some(frames),
+            BufferSize::Default => None,
+        };
+        NegotiatedConfig {
+            stream,
+            sample_rate: self.config.sample_rate.0,
+            channels: self.config.channels,
+            sample_format: self.sample_format.to_string(),
+            buffer_frames,
+            buffer_latency_ms: buffer_frames.map(|frames| frames as f32 * 1000.0 / self.config.sample_rate.0 as f32),
+        }
+    }
+}
+
+/// Formats the backend can build streams for
+fn is_buildable(format: SampleFormat) -> bool {
+    matches!(format, SampleFormat::F32 | SampleFormat::I16 | SampleFormat::U16)
+}
+
+/// Pick the supported configuration closest to the preferences.
+///
+/// The sample rate matters most (a mismatch means resampling), then the
+/// channel count: `channels` if given, otherwise the device's default,
+/// since the channel selection and map are laid out for it. The sample
+/// format is converted anyway, so it only breaks ties. The buffer size is clamped to the range the device reports; when a device
+/// can't report its range the host default is kept, since an unsupported
+/// fixed size would fail to open the stream.
+pub(super) fn choose_config(
+    supported: &[SupportedStreamConfigRange],
+    default: &SupportedStreamConfig,
+    prefs: &StreamPreferences,
+    channels: Option<u16>,
+) -> ChosenConfig {
+    let target_rate = prefs.sample_rate.unwrap_or(default.sample_rate().0);
+    let target_format = prefs.sample_format.format().unwrap_or(default.sample_format());
+    let target_channels = channels.unwrap_or(default.channels());
+
+    let best = supported
+        .iter()
+        .filter(|range| is_buildable(range.sample_format()))
+        .map(|range| {
+            let rate = target_rate.clamp(range.min_sample_rate().0, range.max_sample_rate().0);
+            let score = (
+                rate.abs_diff(target_rate),
+                range.channels() != target_channels,
+                range.sample_format() != target_format,
+            );
+            (score, rate, range)
+        })
+        .min_by_key(|(score, _, _)| *score);
+
+    let (channels, sample_rate, sample_format, buffer_range) = match best {
+        Some((_, rate, range)) => (range.channels(), rate, range.sample_format(), range.buffer_size()),
+        None => (default.channels(), default.sample_rate().0, default.sample_format(), default.buffer_size()),
+    };
+
+    let buffer_size = match (prefs.buffer_frames, buffer_range) {
+        (Some(frames), SupportedBufferSize::Range { min, max }) => BufferSize::Fixed(frames.clamp(*min, *max)),
+        _ => BufferSize::Default,
+    };
+
+    ChosenConfig {
+        config: StreamConfig {
+            channels,
+            sample_rate: cpal::SampleRate(sample_rate),
+            buffer_size,
+        },
+        sample_format,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::SampleRate;
+
+    fn range(channels: u16, min: u32, max: u32, format: SampleFormat) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(
+            channels,
+            SampleRate(min),
+            SampleRate(max),
+            SupportedBufferSize::Range { min: 64, max: 4096 },
+            format,
+        )
+    }
+
+    fn device() -> (Vec<SupportedStreamConfigRange>, SupportedStreamConfig) {
+        let supported = vec![
+            range(2, 44100, 48000, SampleFormat::I16),
+            range(2, 44100, 96000, SampleFormat::F32),
+            range(1, 8000, 48000, SampleFormat::F32),
+        ];
+        let default = supported[1].with_sample_rate(SampleRate(48000));
+        (supported, default)
+    }
+
+    #[test]
+    fn test_defaults_keep_device_config() {
+        let (supported, default) = device();
+        let chosen = choose_config(&supported, &default, &StreamPreferences::default(), None);
+        assert_eq!(chosen.config.sample_rate.0, 48000);
+        assert_eq!(chosen.config.channels, 2);
+        assert_eq!(chosen.sample_format, SampleFormat::F32);
+        assert_eq!(chosen.config.buffer_size, BufferSize::Default);
+
+        // A requested channel count wins over the default one
+        let chosen = choose_config(&supported, &default, &StreamPreferences::default(), Some(1));
+        assert_eq!(chosen.config.channels, 1);
+        assert_eq!(chosen.config.sample_rate.0, 48000);
+    }
+
+    #[test]
+    fn test_closest_rate_and_format() {
+        let (supported, default) = device();
+        let prefs = StreamPreferences {
+            buffer_frames: Some(128),
+            sample_rate: Some(44100),
+            sample_format: SampleFormatPreference::I16,
+        };
+        let chosen = choose_config(&supported, &default, &prefs, None);
+        assert_eq!(chosen.config.sample_rate.0, 44100);
+        assert_eq!(chosen.sample_format, SampleFormat::I16);
+        assert_eq!(chosen.config.buffer_size, BufferSize::Fixed(128));
+
+        let negotiated = chosen.describe(StreamKind::Local);
+        assert!((negotiated.buffer_latency_ms.unwrap() - 2.902).abs() < 0.01);
+
+        // Unsupported rate: nearest supported one wins over the format
+        let prefs = StreamPreferences { sample_rate: Some(192000), ..prefs };
+        let chosen = choose_config(&supported, &default, &prefs, None);
+        assert_eq!(chosen.config.sample_rate.0, 96000);
+        assert_eq!(chosen.sample_format, SampleFormat::F32);
+    }
+
+    #[test]
+    fn test_channel_count_wins_over_format() {
+        let (supported, default) = device();
+        let prefs = StreamPreferences {
+            sample_format: SampleFormatPreference::I16,
+            ..Default::default()
+        };
+        // Only the float range has a single channel: keep the channel map intact
+        let chosen = choose_config(&supported, &default, &prefs, Some(1));
+        assert_eq!(chosen.config.channels, 1);
+        assert_eq!(chosen.sample_format, SampleFormat::F32);
+
+        let chosen = choose_config(&supported, &default, &prefs, Some(2));
+        assert_eq!(chosen.config.channels, 2);
+        assert_eq!(chosen.sample_format, SampleFormat::I16);
+    }
+
+    #[test]
+    fn test_buffer_size_clamped_or_left_to_host() {
+        let (supported, default) = device();
+        let prefs = StreamPreferences { buffer_frames: Some(16), ..Default::default() };
+        let chosen = choose_config(&supported, &default, &prefs, None);
+        assert_eq!(chosen.config.buffer_size, BufferSize::Fixed(64));
+
+        let unknown = vec![SupportedStreamConfigRange::new(
+            2,
+            SampleRate(48000),
+            SampleRate(48000),
+            SupportedBufferSize::Unknown,
+            SampleFormat::F32,
+        )];
+        let chosen = choose_config(&unknown, &default, &prefs, None);
+        assert_eq!(chosen.config.buffer_size, BufferSize::Default);
+    }
+}
@@ -0,0 +1,445 @@
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use cpal::FromSample;
+use ringbuf::traits::{Consumer, Producer};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
+
+use super::fft::Spectrum;
+use super::key_timing::KeyEdge;
+use super::pipeline::InputProcessor;
+
+/// Band searched for the received tone
+const PITCH_RANGE_HZ: (f32, f32) = (300.0, 1200.0);
+
+/// Window of the pitch search (~40 ms)
+const PITCH_FRAME_MS: f32 = 40.0;
+
+/// How far the strongest bin must stand above the band's median to count as a tone
+const PITCH_PROMINENCE_DB: f32 = 15.0;
+
+/// Pitch changes larger than this are a new station, not drift, and are taken at once
+const PITCH_JUMP_HZ: f32 = 50.0;
+
+/// Length of each Goertzel block, and so the timing resolution
+const BLOCK_MS: f32 = 4.0;
+
+/// How long a new key state must last before it is believed, unless set otherwise
+const DEFAULT_DEBOUNCE_MS: f32 = 8.0;
+
+/// Least difference between the signal and noise levels worth keying on
+const MIN_SNR_DB: f32 = 20.0;
+
+/// Key down above this fraction of the way from noise to signal (in dB), key up below the second
+const THRESHOLD_ON: f32 = 0.6;
+const THRESHOLD_OFF: f32 = 0.4;
+
+/// How quickly the signal level sinks between tones, and the noise level rises during them
+const SIGNAL_DECAY_DB_PER_S: f32 = 6.0;
+const NOISE_RISE_DB_PER_S: f32 = 3.0;
+
+/// Silence after which the pending gap is reported, so the last character is decoded
+const IDLE_MS: f32 = 1500.0;
+
+/// Timings or edges that can be queued before the control side collects them
+const QUEUE_SIZE: usize = 1024;
+
+/// Control-side end of a tone detector: the receive decoder reads timings
+/// from it, the tone keying input reads key edges.
+/// Each new capture stream gets a fresh detector; the previous stream must
+/// be dropped first.
+pub(super) struct ToneMonitor {
+    timings: Arc<HeapRb<f32>>,
+    timings_consumer: parking_lot::Mutex<HeapCons<f32>>,
+    edges: Arc<HeapRb<KeyEdge>>,
+    edges_consumer: parking_lot::Mutex<HeapCons<KeyEdge>>,
+    pitch_hz: Arc<AtomicU32>,
+    debounce_ms: AtomicU32,
+}
+
+impl ToneMonitor {
+    pub fn new() -> Self {
+        let timings = Arc::new(HeapRb::new(QUEUE_SIZE));
+        let timings_consumer = parking_lot::Mutex::new(HeapCons::new(Arc::clone(&timings)));
+        let edges = Arc::new(HeapRb::new(QUEUE_SIZE));
+        let edges_consumer = parking_lot::Mutex::new(HeapCons::new(Arc::clone(&edges)));
+        Self {
+            timings,
+            timings_consumer,
+            edges,
+            edges_consumer,
+            pitch_hz: Arc::new(AtomicU32::new(0.0_f32.to_bits())),
+            debounce_ms: AtomicU32::new(DEFAULT_DEBOUNCE_MS.to_bits()),
+        }
+    }
+
+    /// How long a new key state must last before it is believed.
+    /// Takes effect with the next detector.
+    pub fn set_debounce_ms(&self, debounce_ms: f32) {
+        self.debounce_ms.store(debounce_ms.clamp(0.0, 100.0).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Detector reporting key timings, for a newly opened capture stream
+    pub fn detector(&self) -> ToneDetector {
+        self.timings_consumer.lock().clear();
+        self.new_detector(ToneOutput::Timings(HeapProd::new(Arc::clone(&self.timings))))
+    }
+
+    /// Detector reporting key edges as they happen, for a newly opened capture stream
+    pub fn key_detector(&self) -> ToneDetector {
+        self.edges_consumer.lock().clear();
+        self.new_detector(ToneOutput::Edges(HeapProd::new(Arc::clone(&self.edges))))
+    }
+
+    fn new_detector(&self, output: ToneOutput) -> ToneDetector {
+        let debounce_ms = f32::from_bits(self.debounce_ms.load(Ordering::Relaxed));
+        ToneDetector::new(output, Arc::clone(&self.pitch_hz), debounce_ms)
+    }
+
+    /// Key timings heard since the last call, in `CwDecoder::add_timing` form:
+    /// positive for tones, negative for gaps, in milliseconds
+    pub fn take_timings(&self) -> Vec<f32> {
+        self.timings_consumer.lock().pop_iter().collect()
+    }
+
+    /// Key edges heard since the last call, stamped with when the tone
+    /// started or stopped in the captured audio
+    pub fn take_edges(&self) -> Vec<KeyEdge> {
+        self.edges_consumer.lock().pop_iter().collect()
+    }
+
+    /// Pitch the detector is currently tracking
+    pub fn pitch_hz(&self) -> f32 {
+        f32::from_bits(self.pitch_hz.load(Ordering::Relaxed))
+    }
+}
+
+/// Where a detector reports what it hears
+enum ToneOutput {
+    /// Tone and gap lengths, for decoding
+    Timings(HeapProd<f32>),
+    /// Key edges as soon as they are believed, for keying the sidetone
+    Edges(HeapProd<KeyEdge>),
+}
+
+/// Finds the key-down/up timing of CW tones in captured audio.
+/// An FFT over ~40 ms windows tracks the pitch; a Goertzel filter at that
+/// pitch measures the tone level every few milliseconds, and the key state
+/// follows it with a threshold that sits between the tracked signal and
+/// noise levels.
+pub(super) struct ToneDetector {
+    output: ToneOutput,
+    pitch_published: Arc<AtomicU32>,
+    sample_rate: f32,
+    spectrum: Spectrum,
+    frame: Vec<f32>,
+    filled: usize,
+    power: Vec<f32>,
+    /// Scratch copy of the pitch band for finding its median
+    band: Vec<f32>,
+    pitch_hz: f32,
+    block_length: usize,
+    block_ms: f32,
+    /// Goertzel state for the current block
+    s1: f32,
+    s2: f32,
+    coefficient: f32,
+    block_samples: usize,
+    signal_db: f32,
+    noise_db: f32,
+    /// Levels have been seeded from a first block
+    levels_seeded: bool,
+    key_down: bool,
+    debounce_ms: f32,
+    debounce_blocks: u32,
+    /// Blocks the opposite key state has been seen for
+    pending_blocks: u32,
+    /// Blocks since the last edge
+    run_blocks: u32,
+    /// The current gap has already been reported
+    idle: bool,
+}
+
+impl ToneDetector {
+    fn new(output: ToneOutput, pitch_published: Arc<AtomicU32>, debounce_ms: f32) -> Self {
+        let mut detector = Self {
+            output,
+            pitch_published,
+            sample_rate: 48000.0,
+            spectrum: Spectrum::new(2),
+            frame: Vec::new(),
+            filled: 0,
+            power: Vec::new(),
+            band: Vec::new(),
+            pitch_hz: 600.0,
+            block_length: 1,
+            block_ms: BLOCK_MS,
+            s1: 0.0,
+            s2: 0.0,
+            coefficient: 0.0,
+            block_samples: 0,
+            signal_db: -120.0,
+            noise_db: -120.0,
+            levels_seeded: false,
+            key_down: false,
+            debounce_ms,
+            debounce_blocks: 1,
+            pending_blocks: 0,
+            run_blocks: 0,
+            idle: true,
+        };
+        detector.prepare(48000);
+        detector
+    }
+
+    fn set_pitch(&mut self, pitch_hz: f32) {
+        self.pitch_hz = pitch_hz;
+        self.coefficient = 2.0 * (2.0 * PI * pitch_hz / self.sample_rate).cos();
+        self.pitch_published.store(pitch_hz.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Look for the strongest tone in the pitch range of the latest window
+    fn track_pitch(&mut self) {
+        self.spectrum.power_spectrum(&self.frame, &mut self.power);
+        let bin_hz = self.sample_rate / self.frame.len() as f32;
+        let first = (PITCH_RANGE_HZ.0 / bin_hz) as usize;
+        let last = ((PITCH_RANGE_HZ.1 / bin_hz) as usize).min(self.power.len() - 2);
+        let band = &self.power[first..=last];
+        let Some((offset, &peak)) = band.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)) else {
+            return;
+        };
+
+        self.band.clear();
+        self.band.extend_from_slice(band);
+        let middle = self.band.len() / 2;
+        let (_, &mut median, _) = self.band.select_nth_unstable_by(middle, |a, b| a.total_cmp(b));
+        if peak <= 0.0 || 10.0 * (peak / median.max(1e-20)).log10() < PITCH_PROMINENCE_DB {
+            return;
+        }
+
+        // Parabolic interpolation between the neighbouring bins (in dB)
+        let bin = first + offset;
+        let db = |k: usize| 10.0 * self.power[k].max(1e-20).log10();
+        let (left, centre, right) = (db(bin - 1), db(bin), db(bin + 1));
+        let curvature = left - 2.0 * centre + right;
+        let shift = if curvature < 0.0 { 0.5 * (left - right) / curvature } else { 0.0 };
+        let found = (bin as f32 + shift.clamp(-0.5, 0.5)) * bin_hz;
+
+        let pitch = if (found - self.pitch_hz).abs() > PITCH_JUMP_HZ {
+            found
+        } else {
+            self.pitch_hz + (found - self.pitch_hz) * 0.3
+        };
+        self.set_pitch(pitch);
+    }
+
+    /// Level of the tone in the block just finished (which ended at
+    /// `block_end`), then decide the key state
+    fn end_block(&mut self, block_end: Instant) {
+        let n = self.block_samples as f32;
+        let power = (self.s1 * self.s1 + self.s2 * self.s2 - self.coefficient * self.s1 * self.s2).max(0.0);
+        let amplitude = 2.0 * power.sqrt() / n;
+        let level_db = 20.0 * amplitude.max(1e-6).log10();
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+        self.block_samples = 0;
+
+        // Signal level jumps up and sinks slowly; noise level averages the
+        // blocks while the key is up and creeps up slowly while it is down
+        let seconds = self.block_ms / 1000.0;
+        if !self.levels_seeded {
+            self.signal_db = level_db;
+            self.noise_db = level_db;
+            self.levels_seeded = true;
+        }
+        self.signal_db = level_db.max(self.signal_db - SIGNAL_DECAY_DB_PER_S * seconds);
+        if !self.key_down {
+            self.noise_db += (level_db - self.noise_db) * 0.05;
+        } else {
+            self.noise_db += NOISE_RISE_DB_PER_S * seconds;
+        }
+        self.noise_db = self.noise_db.min(self.signal_db);
+
+        let span = self.signal_db - self.noise_db;
+        let fraction = if self.key_down { THRESHOLD_OFF } else { THRESHOLD_ON };
+        let heard = span >= MIN_SNR_DB && level_db > self.noise_db + fraction * span;
+
+        self.run_blocks += 1;
+        if heard != self.key_down {
+            self.pending_blocks += 1;
+            if self.pending_blocks >= self.debounce_blocks {
+                // The edge happened when the new state was first seen
+                let duration_ms = (self.run_blocks - self.pending_blocks) as f32 * self.block_ms;
+                match &mut self.output {
+                    ToneOutput::Timings(timings) => {
+                        if self.key_down {
+                            let _ = timings.try_push(duration_ms);
+                        } else if !self.idle {
+                            let _ = timings.try_push(-duration_ms);
+                        }
+                    }
+                    ToneOutput::Edges(edges) => {
+                        let age = Duration::from_secs_f32(self.pending_blocks as f32 * self.block_ms / 1000.0);
+                        let at = block_end.checked_sub(age).unwrap_or(block_end);
+                        let _ = edges.try_push(KeyEdge { down: heard, at });
+                    }
+                }
+                self.key_down = heard;
+                self.idle = false;
+                self.run_blocks = self.pending_blocks;
+                self.pending_blocks = 0;
+            }
+        } else {
+            self.pending_blocks = 0;
+        }
+
+        if !self.key_down && !self.idle && self.run_blocks as f32 * self.block_ms >= IDLE_MS {
+            if let ToneOutput::Timings(timings) = &mut self.output {
+                let _ = timings.try_push(-(self.run_blocks as f32 * self.block_ms));
+            }
+            self.idle = true;
+        }
+    }
+}
+
+impl InputProcessor for ToneDetector {
+    fn prepare(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate as f32;
+        let size = ((PITCH_FRAME_MS / 1000.0 * self.sample_rate) as usize).next_power_of_two();
+        self.spectrum = Spectrum::new(size);
+        self.frame = vec![0.0; size];
+        self.filled = 0;
+        self.power = vec![0.0; self.spectrum.bins()];
+        self.band = Vec::with_capacity(self.power.len());
+        self.block_length = ((BLOCK_MS / 1000.0 * self.sample_rate) as usize).max(1);
+        self.block_ms = self.block_length as f32 * 1000.0 / self.sample_rate;
+        self.debounce_blocks = ((self.debounce_ms / self.block_ms).ceil() as u32).max(1);
+        self.set_pitch(self.pitch_hz);
+    }
+
+    fn process<T: Copy>(&mut self, data: &[T], channels: usize, now: Instant)
+    where
+        f32: FromSample<T>,
+    {
+        // `now` is when the buffer was delivered, i.e. just after its last frame
+        let frames = data.len() / channels.max(1);
+        for (i, frame) in data.chunks(channels).enumerate() {
+            let sample = frame
+                .iter()
+                .map(|s| <f32 as FromSample<T>>::from_sample_(*s))
+                .sum::<f32>()
+                / channels as f32;
+
+            self.frame[self.filled] = sample;
+            self.filled += 1;
+            if self.filled == self.frame.len() {
+                self.track_pitch();
+                self.filled = 0;
+            }
+
+            let s0 = sample + self.coefficient * self.s1 - self.s2;
+            self.s2 = self.s1;
+            self.s1 = s0;
+            self.block_samples += 1;
+            if self.block_samples == self.block_length {
+                let age = Duration::from_secs_f64((frames - i - 1) as f64 / self.sample_rate as f64);
+                self.end_block(now.checked_sub(age).unwrap_or(now));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cw::CwDecoder;
+
+    const RATE: f32 = 48000.0;
+
+    /// Key `text` at 20 WPM as a keyed tone with 5 ms edges, plus a little hiss
+    fn keyed(text: &[&str], pitch: f32, amplitude: f32) -> Vec<f32> {
+        let dit = (RATE * 0.060) as usize;
+        let mut keying = vec![false; RATE as usize / 2];
+        for (i, pattern) in text.iter().enumerate() {
+            if i > 0 {
+                keying.extend(std::iter::repeat_n(false, 2 * dit)); // 3 dits with the element gap
+            }
+            for element in pattern.chars() {
+                let length = if element == '.' { dit } else { 3 * dit };
+                keying.extend(std::iter::repeat_n(true, length));
+                keying.extend(std::iter::repeat_n(false, dit));
+            }
+        }
+        keying.extend(std::iter::repeat_n(false, 2 * RATE as usize));
+
+        let ramp = RATE * 0.005;
+        let mut envelope: f32 = 0.0;
+        let mut seed = 3u32;
+        keying
+            .iter()
+            .enumerate()
+            .map(|(i, &down)| {
+                envelope = if down { (envelope + 1.0 / ramp).min(1.0) } else { (envelope - 1.0 / ramp).max(0.0) };
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                let hiss = ((seed >> 8) as f32 / (1 << 24) as f32 - 0.5) * 0.01;
+                (2.0 * PI * pitch * i as f32 / RATE).sin() * amplitude * envelope + hiss
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_decodes_received_tone() {
+        // PARIS, at an unusual pitch and a low level
+        let audio = keyed(&[".--.", ".-", ".-.", "..", "..."], 742.0, 0.05);
+        let monitor = ToneMonitor::new();
+        let mut detector = monitor.detector();
+        detector.prepare(RATE as u32);
+        for buffer in audio.chunks(512) {
+            detector.process(buffer, 1, Instant::now());
+        }
+
+        assert!((monitor.pitch_hz() - 742.0).abs() < 5.0, "pitch {}", monitor.pitch_hz());
+        let timings = monitor.take_timings();
+        // Every element is within a block or two of its keyed length
+        for &timing in timings.iter().filter(|t| **t > 0.0) {
+            let off = (timing - 60.0).abs().min((timing - 180.0).abs());
+            assert!(off <= 10.0, "element {} ms", timing);
+        }
+        assert!(*timings.last().unwrap() <= -IDLE_MS, "last gap not reported");
+
+        let mut decoder = CwDecoder::new();
+        let text: String = timings.iter().filter_map(|&t| decoder.add_timing(t)).collect();
+        assert_eq!(text.trim(), "PARIS");
+    }
+
+    #[test]
+    fn test_key_edges_follow_tone() {
+        // K from a practice oscillator, with a longer debounce
+        let audio = keyed(&["-.-"], 700.0, 0.3);
+        let monitor = ToneMonitor::new();
+        monitor.set_debounce_ms(12.0);
+        let mut detector = monitor.key_detector();
+        detector.prepare(RATE as u32);
+        let start = Instant::now();
+        for (i, buffer) in audio.chunks(512).enumerate() {
+            let delivered = start + Duration::from_secs_f32((i * 512 + buffer.len()) as f32 / RATE);
+            detector.process(buffer, 1, delivered);
+        }
+
+        let edges = monitor.take_edges();
+        assert_eq!(edges.len(), 6);
+        let ms = |at: Instant| (at - start).as_secs_f32() * 1000.0;
+        for (i, edge) in edges.iter().enumerate() {
+            assert_eq!(edge.down, i % 2 == 0);
+        }
+        // Stamped with when the tone started and stopped, not when the debounce let go
+        assert!((ms(edges[0].at) - 500.0).abs() <= 8.0, "first edge at {} ms", ms(edges[0].at));
+        for (pair, expected) in edges.chunks(2).zip([180.0, 60.0, 180.0]) {
+            let length = ms(pair[1].at) - ms(pair[0].at);
+            assert!((length - expected).abs() <= 8.0, "element {} ms", length);
+        }
+        assert!(monitor.take_timings().is_empty());
+    }
+}
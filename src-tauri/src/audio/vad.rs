@@ -0,0 +1,369 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+use super::fft::Spectrum;
+
+/// Analysis window length; long enough to resolve the harmonics of a low voice
+const FRAME_MS: f32 = 40.0;
+
+/// Band the detector listens to, where voiced speech carries its energy
+const VOICE_BAND_HZ: (f32, f32) = (100.0, 4000.0);
+
+/// Bins this close to a sidetone harmonic are ignored, so the tone leaking
+/// into the mic doesn't count as speech. Capped at a quarter of the pitch,
+/// so a low tone's harmonics can't cover the whole band.
+const SIDETONE_GUARD_HZ: f32 = 150.0;
+
+/// Frames flatter than this (1.0 = white noise) don't count as voiced
+const MAX_FLATNESS: f32 = 0.35;
+
+/// How far above the noise floor a frame has to be to count as speech
+const FLOOR_MARGIN_DB: f32 = 10.0;
+
+/// Noise floor rise per second; it falls straight to any quieter frame
+const FLOOR_RISE_DB_PER_S: f32 = 5.0;
+
+/// Consecutive voiced frames needed before the detector fires
+const ONSET_FRAMES: u32 = 2;
+
+/// How quickly the sidetone fades down once speech is detected, and back up
+pub(super) const DUCK_ATTACK_MS: f32 = 20.0;
+pub(super) const DUCK_RELEASE_MS: f32 = 100.0;
+
+/// Voice-activity ducking of the sidetone
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VadSettings {
+    pub enabled: bool,
+    /// How far the sidetone is turned down while the operator is speaking
+    pub duck_db: f32,
+    /// Quietest voice band level counted as speech
+    pub threshold_db: f32,
+    /// How long the detector stays active after speech stops
+    pub hang_ms: f32,
+}
+
+impl Default for VadSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            duck_db: 12.0,
+            threshold_db: -45.0,
+            hang_ms: 300.0,
+        }
+    }
+}
+
+/// VAD settings shared with the input and output callbacks, plus whether
+/// the operator is currently speaking
+#[derive(Clone)]
+pub(super) struct VadControls {
+    shared: Arc<VadShared>,
+}
+
+struct VadShared {
+    enabled: AtomicBool,
+    duck_db: AtomicU32,
+    threshold_db: AtomicU32,
+    hang_ms: AtomicU32,
+    active: AtomicBool,
+}
+
+impl VadControls {
+    pub fn new() -> Self {
+        let controls = Self {
+            shared: Arc::new(VadShared {
+                enabled: AtomicBool::new(false),
+                duck_db: AtomicU32::new(0),
+                threshold_db: AtomicU32::new(0),
+                hang_ms: AtomicU32::new(0),
+                active: AtomicBool::new(false),
+            }),
+        };
+        controls.set(&VadSettings::default());
+        controls
+    }
+
+    /// Called from the control side
+    pub fn set(&self, settings: &VadSettings) {
+        let shared = &self.shared;
+        let store = |atomic: &AtomicU32, value: f32| atomic.store(value.to_bits(), Ordering::Relaxed);
+        store(&shared.duck_db, settings.duck_db.clamp(0.0, 60.0));
+        store(&shared.threshold_db, settings.threshold_db.clamp(-90.0, 0.0));
+        store(&shared.hang_ms, settings.hang_ms.clamp(0.0, 5000.0));
+        shared.enabled.store(settings.enabled, Ordering::Relaxed);
+    }
+
+    /// Whether the operator is speaking (always false while disabled)
+    pub fn is_active(&self) -> bool {
+        self.shared.active.load(Ordering::Relaxed)
+    }
+
+    /// Sidetone gain to apply right now (1.0 = not ducked)
+    pub fn sidetone_gain(&self) -> f32 {
+        if self.is_active() {
+            10.0_f32.powf(-f32::from_bits(self.shared.duck_db.load(Ordering::Relaxed)) / 20.0)
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Voice activity detector on the raw mic signal.
+/// Each frame is voiced when its voice band energy clears both the
+/// threshold and the tracked noise floor, and its spectrum is peaky
+/// (low spectral flatness) rather than noise-like.
+pub(super) struct VoiceDetector {
+    controls: VadControls,
+    enabled: bool,
+    /// Output and local sidetone pitches; their harmonic series are
+    /// excluded from the analysis
+    sidetone_frequencies: [Arc<AtomicU32>; 2],
+    spectrum: Spectrum,
+    frame: Vec<f32>,
+    filled: usize,
+    /// Samples between analyses (half a frame)
+    hop: usize,
+    power: Vec<f32>,
+    bin_hz: f32,
+    frame_rate: f32,
+    noise_floor_db: f32,
+    voiced_frames: u32,
+    /// Frames left before the detector releases
+    hang_remaining: u32,
+    active: bool,
+}
+
+impl VoiceDetector {
+    pub fn new(controls: VadControls, sidetone_frequencies: [Arc<AtomicU32>; 2], sample_rate: f32) -> Self {
+        let mut detector = Self {
+            controls,
+            enabled: false,
+            sidetone_frequencies,
+            spectrum: Spectrum::new(2),
+            frame: Vec::new(),
+            filled: 0,
+            hop: 1,
+            power: Vec::new(),
+            bin_hz: 0.0,
+            frame_rate: 0.0,
+            noise_floor_db: 0.0,
+            voiced_frames: 0,
+            hang_remaining: 0,
+            active: false,
+        };
+        detector.set_sample_rate(sample_rate);
+        detector
+    }
+
+    /// Allocates the analysis buffers; not for the audio callback
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        let size = ((FRAME_MS / 1000.0 * sample_rate) as usize).next_power_of_two();
+        self.spectrum = Spectrum::new(size);
+        self.frame = vec![0.0; size];
+        self.filled = 0;
+        self.hop = size / 2;
+        self.power = vec![0.0; self.spectrum.bins()];
+        self.bin_hz = sample_rate / size as f32;
+        self.frame_rate = sample_rate / self.hop as f32;
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.filled = 0;
+        self.noise_floor_db = -90.0;
+        self.voiced_frames = 0;
+        self.hang_remaining = 0;
+        self.active = false;
+    }
+
+    /// Called once per input buffer, before its samples are pushed
+    pub fn begin_block(&mut self) {
+        let enabled = self.controls.shared.enabled.load(Ordering::Relaxed);
+        if !enabled && self.enabled {
+            self.reset();
+        }
+        self.enabled = enabled;
+    }
+
+    /// Feed one mono mic sample
+    pub fn push(&mut self, sample: f32) {
+        if !self.enabled {
+            return;
+        }
+        self.frame[self.filled] = sample;
+        self.filled += 1;
+        if self.filled == self.frame.len() {
+            self.analyze();
+            self.frame.copy_within(self.hop.., 0);
+            self.filled -= self.hop;
+        }
+    }
+
+    /// Publish whether the operator is speaking; called once per buffer
+    pub fn end_block(&self) {
+        self.controls.shared.active.store(self.active, Ordering::Relaxed);
+    }
+
+    fn analyze(&mut self) {
+        let shared = &self.controls.shared;
+        let threshold_db = f32::from_bits(shared.threshold_db.load(Ordering::Relaxed));
+        let hang_frames = (f32::from_bits(shared.hang_ms.load(Ordering::Relaxed)) / 1000.0 * self.frame_rate) as u32;
+        let sidetones = self
+            .sidetone_frequencies
+            .each_ref()
+            .map(|frequency| f32::from_bits(frequency.load(Ordering::Relaxed)));
+
+        self.spectrum.power_spectrum(&self.frame, &mut self.power);
+        let first = (VOICE_BAND_HZ.0 / self.bin_hz).ceil() as usize;
+        let last = ((VOICE_BAND_HZ.1 / self.bin_hz) as usize).min(self.power.len() - 1);
+        let mut energy = 0.0;
+        let mut log_sum = 0.0;
+        let mut count = 0;
+        for (k, power) in self.power.iter().enumerate().take(last + 1).skip(first) {
+            if sidetones.iter().any(|&pitch| near_harmonic(k as f32 * self.bin_hz, pitch)) {
+                continue;
+            }
+            let power = power + 1e-12;
+            energy += power;
+            log_sum += power.ln();
+            count += 1;
+        }
+        if count == 0 {
+            return;
+        }
+        let energy_db = 10.0 * energy.log10();
+        let flatness = (log_sum / count as f32).exp() / (energy / count as f32);
+
+        let voiced = energy_db > threshold_db
+            && energy_db > self.noise_floor_db + FLOOR_MARGIN_DB
+            && flatness < MAX_FLATNESS;
+        if !voiced {
+            // Only unvoiced frames move the floor, so a long sentence can't raise it
+            self.noise_floor_db = energy_db.min(self.noise_floor_db + FLOOR_RISE_DB_PER_S / self.frame_rate);
+        }
+
+        self.voiced_frames = if voiced { self.voiced_frames + 1 } else { 0 };
+        if self.voiced_frames >= ONSET_FRAMES {
+            self.active = true;
+            self.hang_remaining = hang_frames;
+        } else if self.active && !voiced {
+            if self.hang_remaining == 0 {
+                self.active = false;
+            } else {
+                self.hang_remaining -= 1;
+            }
+        }
+    }
+}
+
+/// Whether `hz` is within the guard of a harmonic of `pitch`
+fn near_harmonic(hz: f32, pitch: f32) -> bool {
+    if pitch <= 0.0 {
+        return false;
+    }
+    let harmonic = (hz / pitch).round().max(1.0);
+    (hz - harmonic * pitch).abs() < SIDETONE_GUARD_HZ.min(pitch / 4.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const RATE: f32 = 48000.0;
+
+    fn detector() -> (VadControls, VoiceDetector) {
+        let controls = VadControls::new();
+        controls.set(&VadSettings {
+            enabled: true,
+            ..VadSettings::default()
+        });
+        let sidetones = [600.0_f32, 700.0].map(|frequency| Arc::new(AtomicU32::new(frequency.to_bits())));
+        let detector = VoiceDetector::new(controls.clone(), sidetones, RATE);
+        (controls, detector)
+    }
+
+    fn feed(detector: &mut VoiceDetector, controls: &VadControls, buffer: &[f32]) -> bool {
+        detector.begin_block();
+        for &sample in buffer {
+            detector.push(sample);
+        }
+        detector.end_block();
+        controls.is_active()
+    }
+
+    /// Feed `seconds` of `signal` in 10 ms buffers, returning whether the
+    /// detector was active after each buffer
+    fn run(detector: &mut VoiceDetector, controls: &VadControls, seconds: f32, signal: impl Fn(usize) -> f32) -> Vec<bool> {
+        let samples: Vec<f32> = (0..(seconds * RATE) as usize).map(signal).collect();
+        samples
+            .chunks(480)
+            .map(|buffer| feed(detector, controls, buffer))
+            .collect()
+    }
+
+    /// Crude voiced vowel: 140 Hz pitch with falling harmonics
+    fn voice(i: usize) -> f32 {
+        let t = i as f32 / RATE;
+        (1..=20).map(|h| (2.0 * PI * 140.0 * h as f32 * t).sin() * 0.05 / h as f32).sum()
+    }
+
+    fn noise(seed: &mut u32) -> f32 {
+        *seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+        (*seed >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+    }
+
+    #[test]
+    fn test_voice_triggers_and_hangs() {
+        let (controls, mut detector) = detector();
+        let seed = std::cell::Cell::new(1u32);
+        let hiss = |_| {
+            let mut s = seed.get();
+            let n = noise(&mut s) * 0.002;
+            seed.set(s);
+            n
+        };
+
+        // Room noise alone: the floor settles under it and nothing fires
+        assert!(run(&mut detector, &controls, 1.0, hiss).iter().all(|active| !active));
+
+        // Talking: fires within a few frames
+        let talking = run(&mut detector, &controls, 0.5, |i| voice(i) + hiss(i));
+        let onset = talking.iter().position(|active| *active).expect("voice not detected");
+        assert!(onset < 10, "onset after {} buffers", onset);
+        assert!(talking[onset..].iter().all(|active| *active));
+
+        // Silence: holds for the hang time (300 ms), then releases
+        let after = run(&mut detector, &controls, 1.0, hiss);
+        let release = after.iter().position(|active| !active).expect("never released");
+        assert!((28..=40).contains(&release), "released after {} buffers", release);
+        assert!(after[release..].iter().all(|active| !active));
+        assert_eq!(controls.sidetone_gain(), 1.0);
+    }
+
+    #[test]
+    fn test_ignores_noise_and_sidetone() {
+        let (controls, mut detector) = detector();
+
+        // Loud broadband noise is too flat to be speech
+        let mut seed = 7u32;
+        let samples: Vec<f32> = (0..RATE as usize).map(|_| noise(&mut seed) * 0.3).collect();
+        for buffer in samples.chunks(480) {
+            assert!(!feed(&mut detector, &controls, buffer));
+        }
+
+        // The local sidetone leaking into the mic is ignored
+        let tone = run(&mut detector, &controls, 1.0, |i| (2.0 * PI * 700.0 * i as f32 / RATE).sin() * 0.3);
+        assert!(tone.iter().all(|active| !active));
+
+        // So are the harmonics of a soft square sidetone, on either tone
+        for frequency in [700.0, 600.0] {
+            let soft_square = |i: usize| (4.0 * (2.0 * PI * frequency * i as f32 / RATE).sin()).tanh() * 0.3;
+            assert!(run(&mut detector, &controls, 1.0, soft_square).iter().all(|active| !active));
+        }
+
+        // Disabled: never active
+        controls.set(&VadSettings::default());
+        assert!(run(&mut detector, &controls, 0.5, voice).iter().all(|active| !active));
+    }
+}
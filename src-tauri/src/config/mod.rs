@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 
@@ -35,6 +36,211 @@ pub enum SidetoneRoute {
     Both,            // Both local speakers and output
 }
 
+/// Shape of the sidetone rise and fall
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum EnvelopeShape {
+    Linear,
+    #[default]
+    RaisedCosine,
+    BlackmanHarris,
+}
+
+/// Sidetone timbre
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum Waveform {
+    #[default]
+    Sine,
+    Harmonics,   // Sine plus the harmonics below
+    SoftSquare,  // Soft-clipped square
+    Wavetable,   // Single cycle loaded from a WAV file
+}
+
+/// Waveform settings for one sidetone route
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WaveformSettings {
+    pub waveform: Waveform,
+    #[serde(default)]
+    pub harmonics: Vec<f32>,  // Relative amplitudes of the 2nd, 3rd, 4th and 5th harmonics
+    #[serde(default = "default_square_drive")]
+    pub square_drive: f32,  // How hard the sine is driven into the soft clipper
+    #[serde(default)]
+    pub wavetable_path: Option<String>,  // Single-cycle WAV file
+}
+
+impl Default for WaveformSettings {
+    fn default() -> Self {
+        Self {
+            waveform: Waveform::default(),
+            harmonics: Vec::new(),
+            square_drive: default_square_drive(),
+            wavetable_path: None,
+        }
+    }
+}
+
+/// Mic noise gate / downward expander settings
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NoiseGateSettings {
+    pub enabled: bool,
+    pub threshold_db: f32,  // Mic level below which the gate starts closing
+    pub ratio: f32,         // Expansion below the threshold (1 = off, 10+ = hard gate)
+    pub range_db: f32,      // Most attenuation when fully closed
+    pub attack_ms: f32,
+    pub hold_ms: f32,       // Time the gate stays open after the level drops
+    pub release_ms: f32,
+}
+
+impl Default for NoiseGateSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold_db: -45.0,
+            ratio: 10.0,
+            range_db: 40.0,
+            attack_ms: 1.0,
+            hold_ms: 100.0,
+            release_ms: 150.0,
+        }
+    }
+}
+
+/// Sidetone ducking while the operator is speaking (voice activity detection)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct VoiceDuckingSettings {
+    pub enabled: bool,
+    pub duck_db: f32,       // How far the sidetone is turned down while talking
+    pub threshold_db: f32,  // Quietest voice level counted as speech
+    pub hang_ms: f32,       // Time the sidetone stays ducked after speech stops
+}
+
+impl Default for VoiceDuckingSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            duck_db: 12.0,
+            threshold_db: -45.0,
+            hang_ms: 300.0,
+        }
+    }
+}
+
+/// Spectrum feed for the frontend's spectrum/waterfall display
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SpectrumFeedSettings {
+    pub enabled: bool,
+    pub rate_hz: f32,     // Frames per second (5 - 60)
+    pub fft_size: usize,  // Samples per FFT; larger = finer frequency resolution
+}
+
+impl Default for SpectrumFeedSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rate_hz: 20.0,
+            fft_size: 2048,
+        }
+    }
+}
+
+/// Mic compressor settings
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CompressorSettings {
+    pub enabled: bool,
+    pub threshold_db: f32,  // Mic level above which the voice is compressed
+    pub ratio: f32,
+    pub makeup_db: f32,     // Gain added back after compression
+    pub attack_ms: f32,
+    pub release_ms: f32,
+}
+
+impl Default for CompressorSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold_db: -24.0,
+            ratio: 3.0,
+            makeup_db: 6.0,
+            attack_ms: 5.0,
+            release_ms: 100.0,
+        }
+    }
+}
+
+/// How a multichannel input device is turned into the mono mic signal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum InputChannelMode {
+    #[default]
+    Average,  // Mean of every channel
+    Single,   // One channel only
+    Sum,      // Every channel added together
+    Custom,   // Gain per channel from `gains`
+}
+
+/// Channel selection for one input device
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct InputChannelSettings {
+    pub mode: InputChannelMode,
+    #[serde(default)]
+    pub channel: usize,   // 0-based channel for Single
+    #[serde(default)]
+    pub gains: Vec<f32>,  // Gain per channel for Custom (missing channels are dropped)
+}
+
+/// What one channel of the output device carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum OutputChannelRole {
+    #[default]
+    Mix,       // Mic and sidetone mixed, as on every channel by default
+    Sidetone,  // Sidetone alone
+    Mic,       // Processed mic alone
+    Silent,
+}
+
+/// Preferred sample format for the audio streams
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum SampleFormatPreference {
+    #[default]
+    Auto,  // Device default
+    F32,
+    I16,
+}
+
+fn default_cw_mute_tail_ms() -> f32 {
+    250.0
+}
+
+fn default_ducking_hold_ms() -> f32 {
+    250.0
+}
+
+fn default_ducking_depth() -> f32 {
+    1.0
+}
+
+fn default_ducking_attack_ms() -> f32 {
+    5.0
+}
+
+fn default_ducking_release_ms() -> f32 {
+    20.0
+}
+
+fn default_tone_key_debounce_ms() -> f32 {
+    8.0
+}
+
+fn default_test_recording_max_seconds() -> f32 {
+    5.0
+}
+
+fn default_envelope_ms() -> f32 {
+    5.0
+}
+
+fn default_square_drive() -> f32 {
+    3.0
+}
+
 /// Application settings
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Settings {
@@ -44,18 +250,84 @@ pub struct Settings {
     pub dit_dah_ratio: f32,
     pub weighting: f32,
     pub swap_paddles: bool,
+    #[serde(default)]
+    pub software_keyer: bool,  // Run the keyer in the app (adapter in passthrough mode)
 
     // Sidetone settings
-    pub sidetone_frequency: f32,
+    pub sidetone_frequency: f32,    // Pitch of the sidetone going to Zoom/output
     pub sidetone_volume: f32,       // Volume for sidetone going to Zoom/output
+    #[serde(default)]
+    pub local_sidetone_frequency: Option<f32>,  // Pitch for local monitoring (None = same as output)
     pub local_sidetone_volume: f32, // Volume for local monitoring (headphones/speakers)
     pub sidetone_route: SidetoneRoute,
+    #[serde(default)]
+    pub envelope_shape: EnvelopeShape,
+    #[serde(default = "default_envelope_ms")]
+    pub envelope_rise_ms: f32,
+    #[serde(default = "default_envelope_ms")]
+    pub envelope_fall_ms: f32,
+    #[serde(default)]
+    pub envelope_scale_with_wpm: bool,  // Rise/fall times are for 20 WPM and scale with speed
+    #[serde(default)]
+    pub local_envelope_shape: Option<EnvelopeShape>,  // Local monitor envelope (None = same as output)
+    #[serde(default)]
+    pub local_envelope_rise_ms: Option<f32>,
+    #[serde(default)]
+    pub local_envelope_fall_ms: Option<f32>,
+    #[serde(default)]
+    pub output_waveform: WaveformSettings,  // Timbre of the sidetone going to Zoom/output
+    #[serde(default)]
+    pub local_waveform: WaveformSettings,   // Timbre of the sidetone on the local monitor
 
     // Audio settings
     pub mic_volume: f32,
     pub mix_mode: MixMode,
     #[serde(default)]
-    pub mic_ducking: bool,  // Mute mic while sending CW (with 250ms hold after key up)
+    pub mic_ducking: bool,  // Duck mic while sending CW (with a hold after key up)
+    #[serde(default = "default_ducking_hold_ms")]
+    pub ducking_hold_ms: f32,
+    #[serde(default)]
+    pub ducking_hold_follows_wpm: bool,  // Hold lasts one word gap at the current WPM
+    #[serde(default = "default_ducking_depth")]
+    pub ducking_depth: f32,  // 0.0 = no attenuation, 1.0 = mute (CwMutesMic always mutes fully)
+    #[serde(default = "default_ducking_attack_ms")]
+    pub ducking_attack_ms: f32,   // Fade-down time
+    #[serde(default = "default_ducking_release_ms")]
+    pub ducking_release_ms: f32,  // Fade-up time
+    #[serde(default)]
+    pub mic_gate: NoiseGateSettings,  // Keeps keyboard and paddle clatter out of the meeting
+    #[serde(default)]
+    pub mic_compressor: CompressorSettings,
+    #[serde(default)]
+    pub cancel_sidetone_bleed: bool,  // Remove local sidetone the mic picks up from speakers
+    #[serde(default)]
+    pub voice_ducking: VoiceDuckingSettings,  // Turn the sidetone down while the operator talks
+    #[serde(default)]
+    pub spectrum: SpectrumFeedSettings,
+    #[serde(default = "default_cw_mute_tail_ms")]
+    pub cw_mute_tail_ms: f32,  // How long the mic stays muted after key up (CwMutesMic)
+    #[serde(default)]
+    pub cw_mute_tail_follows_wpm: bool,  // CwMutesMic tail lasts one word gap at the current WPM
+    #[serde(default)]
+    pub ptt_midi_note: Option<u8>,  // MIDI note that acts as push-to-talk (PushToTalkVoice)
+    #[serde(default)]
+    pub ptt_hotkey: Option<String>,  // Global shortcut for push-to-talk, e.g. "F9"
+    #[serde(default)]
+    pub key_latency_ms: f32,  // Delay that lets key edges land on their exact sample; adds directly to key-to-ear latency, local monitor included (0 = one buffer period)
+    #[serde(default = "default_tone_key_debounce_ms")]
+    pub tone_key_debounce_ms: f32,  // How long a keying tone must stay on or off before it counts
+    #[serde(default = "default_test_recording_max_seconds")]
+    pub test_recording_max_seconds: f32,  // Longest wizard test recording kept
+    #[serde(default)]
+    pub buffer_size_frames: Option<u32>,  // Target stream buffer size (None = host default)
+    #[serde(default)]
+    pub preferred_sample_rate: Option<u32>,  // None = device default
+    #[serde(default)]
+    pub preferred_sample_format: SampleFormatPreference,
+    #[serde(default)]
+    pub input_channels: HashMap<String, InputChannelSettings>,  // Per input device ("" = system default)
+    #[serde(default)]
+    pub output_channels: HashMap<String, Vec<OutputChannelRole>>,  // Per output device, one role per channel
     pub local_output_device: Option<String>,  // For local sidetone monitoring
 
     // Device settings
@@ -76,13 +348,46 @@ impl Default for Settings {
             dit_dah_ratio: 3.0,
             weighting: 0.0,
             swap_paddles: false,
+            software_keyer: false,
             sidetone_frequency: 600.0,
             sidetone_volume: 0.5,
+            local_sidetone_frequency: None,
             local_sidetone_volume: 0.3,
             sidetone_route: SidetoneRoute::default(),
+            envelope_shape: EnvelopeShape::default(),
+            envelope_rise_ms: default_envelope_ms(),
+            envelope_fall_ms: default_envelope_ms(),
+            envelope_scale_with_wpm: false,
+            local_envelope_shape: None,
+            local_envelope_rise_ms: None,
+            local_envelope_fall_ms: None,
+            output_waveform: WaveformSettings::default(),
+            local_waveform: WaveformSettings::default(),
             mic_volume: 1.0,
             mix_mode: MixMode::default(),
             mic_ducking: false,
+            ducking_hold_ms: default_ducking_hold_ms(),
+            ducking_hold_follows_wpm: false,
+            ducking_depth: default_ducking_depth(),
+            ducking_attack_ms: default_ducking_attack_ms(),
+            ducking_release_ms: default_ducking_release_ms(),
+            mic_gate: NoiseGateSettings::default(),
+            mic_compressor: CompressorSettings::default(),
+            cancel_sidetone_bleed: false,
+            voice_ducking: VoiceDuckingSettings::default(),
+            spectrum: SpectrumFeedSettings::default(),
+            cw_mute_tail_ms: default_cw_mute_tail_ms(),
+            cw_mute_tail_follows_wpm: false,
+            ptt_midi_note: None,
+            ptt_hotkey: None,
+            key_latency_ms: 0.0,
+            tone_key_debounce_ms: default_tone_key_debounce_ms(),
+            test_recording_max_seconds: default_test_recording_max_seconds(),
+            buffer_size_frames: None,
+            preferred_sample_rate: None,
+            preferred_sample_format: SampleFormatPreference::default(),
+            input_channels: HashMap::new(),
+            output_channels: HashMap::new(),
             local_output_device: None,
             midi_device: None,
             input_device: None,
@@ -152,10 +152,8 @@ impl CwDecoder {
             }
 
             // Word boundary - add space
-            if duration_ms >= word_threshold {
-                if !self.output_buffer.is_empty() && !self.output_buffer.ends_with(' ') {
-                    self.output_buffer.push(' ');
-                }
+            if duration_ms >= word_threshold && !self.output_buffer.is_empty() && !self.output_buffer.ends_with(' ') {
+                self.output_buffer.push(' ');
             }
 
             // Update dit estimate from inter-character gap (divide by 3)
@@ -169,7 +167,7 @@ impl CwDecoder {
     /// Add a dit length sample to the adaptive buffer
     fn add_dit_sample(&mut self, dit_ms: f32) {
         // Sanity check - ignore extreme values
-        if dit_ms < 10.0 || dit_ms > 500.0 {
+        if !(10.0..=500.0).contains(&dit_ms) {
             return;
         }
 
@@ -231,23 +229,6 @@ impl CwDecoder {
             20.0
         }
     }
-
-    /// Reset the decoder state
-    pub fn reset(&mut self) {
-        self.current_pattern.clear();
-        self.output_buffer.clear();
-        // Keep dit_buffer for speed continuity
-    }
-
-    /// Get the current pattern being built (for debugging)
-    pub fn current_pattern(&self) -> &str {
-        &self.current_pattern
-    }
-
-    /// Get current dit length estimate
-    pub fn dit_length_ms(&self) -> f32 {
-        self.dit_length_ms
-    }
 }
 
 impl Default for CwDecoder {
@@ -273,30 +254,32 @@ mod tests {
     #[test]
     fn test_decode_sos() {
         let mut decoder = CwDecoder::new();
+        // Each character comes back from the timing that completes it
+        let mut decoded = String::new();
         // S: ... (3 dits)
-        decoder.add_timing(60.0);  // dit
-        decoder.add_timing(-60.0); // intra-char gap
-        decoder.add_timing(60.0);  // dit
-        decoder.add_timing(-60.0); // intra-char gap
-        decoder.add_timing(60.0);  // dit
-        decoder.add_timing(-180.0); // char gap (3x dit)
+        decoded.extend(decoder.add_timing(60.0));  // dit
+        decoded.extend(decoder.add_timing(-60.0)); // intra-char gap
+        decoded.extend(decoder.add_timing(60.0));  // dit
+        decoded.extend(decoder.add_timing(-60.0)); // intra-char gap
+        decoded.extend(decoder.add_timing(60.0));  // dit
+        decoded.extend(decoder.add_timing(-180.0)); // char gap (3x dit)
 
         // O: --- (3 dahs)
-        decoder.add_timing(180.0); // dah
-        decoder.add_timing(-60.0); // intra-char gap
-        decoder.add_timing(180.0); // dah
-        decoder.add_timing(-60.0); // intra-char gap
-        decoder.add_timing(180.0); // dah
-        decoder.add_timing(-180.0); // char gap
+        decoded.extend(decoder.add_timing(180.0)); // dah
+        decoded.extend(decoder.add_timing(-60.0)); // intra-char gap
+        decoded.extend(decoder.add_timing(180.0)); // dah
+        decoded.extend(decoder.add_timing(-60.0)); // intra-char gap
+        decoded.extend(decoder.add_timing(180.0)); // dah
+        decoded.extend(decoder.add_timing(-180.0)); // char gap
 
         // S: ...
-        decoder.add_timing(60.0);
-        decoder.add_timing(-60.0);
-        decoder.add_timing(60.0);
-        decoder.add_timing(-60.0);
-        decoder.add_timing(60.0);
-
-        let result = decoder.flush();
-        assert_eq!(result, Some("SOS".to_string()));
+        decoded.extend(decoder.add_timing(60.0));
+        decoded.extend(decoder.add_timing(-60.0));
+        decoded.extend(decoder.add_timing(60.0));
+        decoded.extend(decoder.add_timing(-60.0));
+        decoded.extend(decoder.add_timing(60.0));
+
+        decoded.extend(decoder.flush());
+        assert_eq!(decoded, "SOS");
     }
 }
@@ -0,0 +1,527 @@
+use std::collections::VecDeque;
+
+use crate::config::KeyerType;
+use super::timing::calculate_dit_duration;
+
+/// A key state change produced by the keyer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyerEvent {
+    pub key_down: bool,
+    /// Sample offset of the edge, relative to the start of the `advance` call
+    pub offset: u32,
+}
+
+/// A single Morse element
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Element {
+    Dit,
+    Dah,
+}
+
+impl Element {
+    fn opposite(self) -> Self {
+        match self {
+            Element::Dit => Element::Dah,
+            Element::Dah => Element::Dit,
+        }
+    }
+}
+
+/// Where the keyer is within the element currently being sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Idle,
+    Mark,
+    Space,
+}
+
+/// Software keyer that turns raw paddle states into timed key-down/key-up edges.
+///
+/// Time is counted in samples so edges land on exact sample boundaries.
+/// Mirrors the keyer modes of the Vail adapter firmware, so passthrough-mode
+/// adapters (and any other raw paddle source) behave the same as keyer mode.
+pub struct Keyer {
+    keyer_type: KeyerType,
+    sample_rate: f32,
+    wpm: f32,
+    dit_dah_ratio: f32,
+    /// Weighting in percent of a dit (-50 to 50), added to marks and removed from spaces
+    weighting: f32,
+    swap_paddles: bool,
+
+    // Element lengths in samples (weighting applied)
+    dit_mark: u32,
+    dah_mark: u32,
+    element_space: u32,
+
+    // Paddle state after swap
+    dit_paddle: bool,
+    dah_paddle: bool,
+    /// Paddle pressed most recently (for Ultimatic)
+    last_pressed: Option<Element>,
+
+    // Element sequencer state
+    phase: Phase,
+    current: Option<Element>,
+    remaining: u32,
+    /// Paddle memory (Iambic A/B, Single Dot)
+    dit_memory: bool,
+    dah_memory: bool,
+    /// Both paddles were held during the current element (Iambic B)
+    squeeze: bool,
+    /// Queued elements (Keyahead)
+    queue: VecDeque<Element>,
+
+    key_down: bool,
+}
+
+impl Keyer {
+    pub fn new(keyer_type: KeyerType, wpm: f32, sample_rate: f32) -> Self {
+        let mut keyer = Self {
+            keyer_type,
+            sample_rate,
+            wpm,
+            dit_dah_ratio: 3.0,
+            weighting: 0.0,
+            swap_paddles: false,
+            dit_mark: 0,
+            dah_mark: 0,
+            element_space: 0,
+            dit_paddle: false,
+            dah_paddle: false,
+            last_pressed: None,
+            phase: Phase::Idle,
+            current: None,
+            remaining: 0,
+            dit_memory: false,
+            dah_memory: false,
+            squeeze: false,
+            queue: VecDeque::new(),
+            key_down: false,
+        };
+        keyer.update_timing();
+        keyer
+    }
+
+    /// Set keyer type (resets any element in progress)
+    pub fn set_keyer_type(&mut self, keyer_type: KeyerType) {
+        if self.keyer_type != keyer_type {
+            self.keyer_type = keyer_type;
+            self.reset();
+        }
+    }
+
+    /// Set WPM, dit/dah ratio and weighting
+    pub fn set_timing(&mut self, wpm: f32, dit_dah_ratio: f32, weighting: f32) {
+        self.wpm = wpm;
+        self.dit_dah_ratio = dit_dah_ratio;
+        self.weighting = weighting;
+        self.update_timing();
+    }
+
+    /// Update the sample rate used to count element lengths
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        if self.sample_rate == sample_rate {
+            return;
+        }
+        self.sample_rate = sample_rate;
+        self.update_timing();
+    }
+
+    /// Swap the dit and dah paddles (for left-handed operators)
+    pub fn set_swap_paddles(&mut self, swap: bool) {
+        self.swap_paddles = swap;
+    }
+
+    /// Recalculate element lengths in samples
+    fn update_timing(&mut self) {
+        let wpm = self.wpm.max(1.0);
+        let ratio = self.dit_dah_ratio.max(1.0);
+        let dit = calculate_dit_duration(wpm) / 1000.0 * self.sample_rate;
+        let adjust = dit * self.weighting.clamp(-50.0, 50.0) / 100.0;
+
+        self.dit_mark = (dit + adjust).round().max(1.0) as u32;
+        self.dah_mark = (dit * ratio + adjust).round().max(1.0) as u32;
+        self.element_space = (dit - adjust).round().max(1.0) as u32;
+    }
+
+    /// Report a paddle press or release
+    pub fn set_paddle(&mut self, is_dit: bool, pressed: bool) {
+        let element = if is_dit != self.swap_paddles { Element::Dit } else { Element::Dah };
+
+        match element {
+            Element::Dit => {
+                if self.dit_paddle == pressed {
+                    return;
+                }
+                self.dit_paddle = pressed;
+            }
+            Element::Dah => {
+                if self.dah_paddle == pressed {
+                    return;
+                }
+                self.dah_paddle = pressed;
+            }
+        }
+
+        if !pressed {
+            return;
+        }
+
+        self.last_pressed = Some(element);
+        match self.keyer_type {
+            // Presses while idle are picked up from the paddle state on the next tick
+            KeyerType::IambicA | KeyerType::IambicB if self.phase != Phase::Idle => {
+                self.set_memory(element)
+            }
+            KeyerType::SingleDot if element == Element::Dit => self.dit_memory = true,
+            KeyerType::Keyahead => self.queue.push_back(element),
+            _ => {}
+        }
+    }
+
+    /// Release everything and return to idle
+    pub fn reset(&mut self) {
+        self.phase = Phase::Idle;
+        self.current = None;
+        self.remaining = 0;
+        self.dit_memory = false;
+        self.dah_memory = false;
+        self.squeeze = false;
+        self.queue.clear();
+        self.key_down = false;
+    }
+
+    /// Advance the keyer by `samples` samples, appending any key edges to `events`
+    pub fn advance(&mut self, samples: u32, events: &mut Vec<KeyerEvent>) {
+        for offset in 0..samples {
+            // Nothing changes until a paddle moves
+            if self.is_resting() {
+                return;
+            }
+            let was_down = self.key_down;
+            self.tick();
+            if self.key_down != was_down {
+                events.push(KeyerEvent { key_down: self.key_down, offset });
+            }
+        }
+    }
+
+    /// Idle with the key up, no paddle held and nothing remembered or queued
+    fn is_resting(&self) -> bool {
+        self.phase == Phase::Idle
+            && !self.key_down
+            && !self.dit_paddle
+            && !self.dah_paddle
+            && !self.dit_memory
+            && !self.dah_memory
+            && self.queue.is_empty()
+    }
+
+    /// Advance by one sample and update the key state
+    fn tick(&mut self) {
+        match self.keyer_type {
+            KeyerType::Straight => {
+                self.key_down = self.dit_paddle || self.dah_paddle;
+            }
+            KeyerType::Bug => {
+                // Automatic dits, dah paddle is a manual straight key
+                self.step_sequencer();
+                self.key_down = self.phase == Phase::Mark || self.dah_paddle;
+            }
+            _ => {
+                self.step_sequencer();
+                self.key_down = self.phase == Phase::Mark;
+            }
+        }
+    }
+
+    /// Run the mark/space element sequencer for one sample
+    fn step_sequencer(&mut self) {
+        if self.dit_paddle && self.dah_paddle {
+            self.squeeze = true;
+        }
+
+        if self.phase != Phase::Idle {
+            self.remaining = self.remaining.saturating_sub(1);
+            if self.remaining > 0 {
+                return;
+            }
+
+            if self.phase == Phase::Mark {
+                self.phase = Phase::Space;
+                self.remaining = self.element_space;
+                return;
+            }
+
+            // Element complete (mark + space)
+            if self.keyer_type == KeyerType::IambicB && self.squeeze {
+                if let Some(current) = self.current {
+                    self.set_memory(current.opposite());
+                }
+            }
+            self.phase = Phase::Idle;
+        }
+
+        if let Some(element) = self.next_element() {
+            self.start_element(element);
+        }
+    }
+
+    /// Pick the next element to send based on keyer type and paddle state
+    fn next_element(&mut self) -> Option<Element> {
+        let dit = self.dit_paddle;
+        let dah = self.dah_paddle;
+
+        match self.keyer_type {
+            KeyerType::Straight => None,
+            KeyerType::Bug => dit.then_some(Element::Dit),
+            KeyerType::ElBug => {
+                if dit {
+                    Some(Element::Dit)
+                } else if dah {
+                    Some(Element::Dah)
+                } else {
+                    None
+                }
+            }
+            KeyerType::SingleDot => {
+                // One dit per press of the dit paddle, repeating dahs
+                if self.dit_memory {
+                    Some(Element::Dit)
+                } else if dah {
+                    Some(Element::Dah)
+                } else {
+                    None
+                }
+            }
+            KeyerType::Ultimatic => {
+                if dit && dah {
+                    self.last_pressed
+                } else if dit {
+                    Some(Element::Dit)
+                } else if dah {
+                    Some(Element::Dah)
+                } else {
+                    None
+                }
+            }
+            KeyerType::PlainIambic | KeyerType::IambicA | KeyerType::IambicB => {
+                let want_dit = dit || self.dit_memory;
+                let want_dah = dah || self.dah_memory;
+                if want_dit && want_dah {
+                    // Alternate while squeezed
+                    Some(self.current.map(Element::opposite).unwrap_or(Element::Dit))
+                } else if want_dit {
+                    Some(Element::Dit)
+                } else if want_dah {
+                    Some(Element::Dah)
+                } else {
+                    None
+                }
+            }
+            KeyerType::Keyahead => {
+                if let Some(element) = self.queue.pop_front() {
+                    Some(element)
+                } else if dit && dah {
+                    Some(self.current.map(Element::opposite).unwrap_or(Element::Dit))
+                } else if dit {
+                    Some(Element::Dit)
+                } else if dah {
+                    Some(Element::Dah)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    fn start_element(&mut self, element: Element) {
+        match element {
+            Element::Dit => self.dit_memory = false,
+            Element::Dah => self.dah_memory = false,
+        }
+        self.squeeze = self.dit_paddle && self.dah_paddle;
+        self.current = Some(element);
+        self.phase = Phase::Mark;
+        self.remaining = match element {
+            Element::Dit => self.dit_mark,
+            Element::Dah => self.dah_mark,
+        };
+    }
+
+    fn set_memory(&mut self, element: Element) {
+        match element {
+            Element::Dit => self.dit_memory = true,
+            Element::Dah => self.dah_memory = true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 12 WPM at 1 kHz gives a 100-sample dit
+    const RATE: f32 = 1000.0;
+
+    /// Advance the keyer, recording edges with offsets relative to `clock`
+    fn run(keyer: &mut Keyer, samples: u32, clock: &mut u32, events: &mut Vec<KeyerEvent>) {
+        let mut block = Vec::new();
+        keyer.advance(samples, &mut block);
+        events.extend(block.into_iter().map(|e| KeyerEvent { offset: e.offset + *clock, ..e }));
+        *clock += samples;
+    }
+
+    /// Lengths of each key-down period
+    fn mark_lengths(events: &[KeyerEvent]) -> Vec<u32> {
+        events
+            .chunks(2)
+            .filter(|pair| pair.len() == 2)
+            .map(|pair| pair[1].offset - pair[0].offset)
+            .collect()
+    }
+
+    fn marks(keyer: &mut Keyer, samples: u32) -> Vec<u32> {
+        let mut events = Vec::new();
+        run(keyer, samples, &mut 0, &mut events);
+        mark_lengths(&events)
+    }
+
+    /// Mark lengths for paddle changes `(sample, is_dit, pressed)` given in time order
+    fn play(keyer_type: KeyerType, changes: &[(u32, bool, bool)], samples: u32) -> Vec<u32> {
+        let mut keyer = Keyer::new(keyer_type, 12.0, RATE);
+        let mut clock = 0;
+        let mut events = Vec::new();
+        for &(at, is_dit, pressed) in changes {
+            run(&mut keyer, at - clock, &mut clock, &mut events);
+            keyer.set_paddle(is_dit, pressed);
+        }
+        run(&mut keyer, samples - clock, &mut clock, &mut events);
+        mark_lengths(&events)
+    }
+
+    #[test]
+    fn test_straight_key_follows_paddle() {
+        let mut keyer = Keyer::new(KeyerType::Straight, 12.0, RATE);
+        let mut events = Vec::new();
+        keyer.set_paddle(true, true);
+        keyer.advance(10, &mut events);
+        keyer.set_paddle(true, false);
+        keyer.advance(10, &mut events);
+        assert_eq!(events, vec![
+            KeyerEvent { key_down: true, offset: 0 },
+            KeyerEvent { key_down: false, offset: 0 },
+        ]);
+    }
+
+    #[test]
+    fn test_press_after_rest_starts_on_press() {
+        let mut keyer = Keyer::new(KeyerType::IambicB, 12.0, RATE);
+        let mut clock = 0;
+        let mut events = Vec::new();
+        run(&mut keyer, 10_000, &mut clock, &mut events);
+        assert!(events.is_empty());
+
+        keyer.set_paddle(true, true);
+        run(&mut keyer, 150, &mut clock, &mut events);
+        keyer.set_paddle(true, false);
+        run(&mut keyer, 500, &mut clock, &mut events);
+        assert_eq!(events, vec![
+            KeyerEvent { key_down: true, offset: 10_000 },
+            KeyerEvent { key_down: false, offset: 10_100 },
+        ]);
+    }
+
+    #[test]
+    fn test_iambic_squeeze_alternates() {
+        let mut keyer = Keyer::new(KeyerType::IambicA, 12.0, RATE);
+        keyer.set_paddle(true, true);
+        keyer.set_paddle(false, true);
+        // dit (100) + space (100) + dah (300) + space (100) + dit (100)
+        assert_eq!(marks(&mut keyer, 701), vec![100, 300, 100]);
+    }
+
+    #[test]
+    fn test_iambic_b_sends_extra_element_after_squeeze_release() {
+        for (keyer_type, expected) in [
+            (KeyerType::IambicA, vec![100]),
+            (KeyerType::IambicB, vec![100, 300]),
+        ] {
+            let mut keyer = Keyer::new(keyer_type, 12.0, RATE);
+            keyer.set_paddle(true, true);
+            keyer.set_paddle(false, true);
+            let mut clock = 0;
+            let mut events = Vec::new();
+            run(&mut keyer, 50, &mut clock, &mut events);
+            // Release both paddles during the first dit
+            keyer.set_paddle(true, false);
+            keyer.set_paddle(false, false);
+            run(&mut keyer, 1000, &mut clock, &mut events);
+            let lengths = mark_lengths(&events);
+            assert_eq!(lengths, expected, "{:?}", keyer_type);
+        }
+    }
+
+    #[test]
+    fn test_bug_repeats_dits_with_manual_dah() {
+        // Three dits while the dit paddle is held, then a dah as long as the paddle
+        let changes = [(0, true, true), (550, true, false), (600, false, true), (850, false, false)];
+        assert_eq!(play(KeyerType::Bug, &changes, 1000), vec![100, 100, 100, 250]);
+    }
+
+    #[test]
+    fn test_ultimatic_last_paddle_wins() {
+        // Dah pressed over a held dit repeats dahs; releasing it goes back to dits
+        let changes = [(0, true, true), (150, false, true), (950, false, false)];
+        assert_eq!(play(KeyerType::Ultimatic, &changes, 1150), vec![100, 300, 300, 100]);
+    }
+
+    #[test]
+    fn test_single_dot_sends_one_dit_per_press() {
+        // Dit pressed and held during a dah is sent once, then the dahs carry on
+        let changes = [(0, false, true), (150, true, true)];
+        assert_eq!(play(KeyerType::SingleDot, &changes, 950), vec![300, 100, 300]);
+    }
+
+    #[test]
+    fn test_plain_iambic_has_no_memory() {
+        // A dit tapped and released during a dah is only remembered by Iambic A
+        let changes = [(0, false, true), (100, true, true), (150, true, false), (250, false, false)];
+        assert_eq!(play(KeyerType::PlainIambic, &changes, 700), vec![300]);
+        assert_eq!(play(KeyerType::IambicA, &changes, 700), vec![300, 100]);
+    }
+
+    #[test]
+    fn test_keyahead_queues_every_press() {
+        // Taps during a dah are all sent afterwards, in order
+        let changes = [
+            (0, false, true),
+            (50, false, false),
+            (100, true, true),
+            (120, true, false),
+            (150, true, true),
+            (170, true, false),
+            (200, false, true),
+            (220, false, false),
+        ];
+        assert_eq!(play(KeyerType::Keyahead, &changes, 1250), vec![300, 100, 100, 300]);
+    }
+
+    #[test]
+    fn test_dah_ratio_and_weighting() {
+        let mut keyer = Keyer::new(KeyerType::ElBug, 12.0, RATE);
+        keyer.set_timing(12.0, 4.0, 20.0);
+        keyer.set_paddle(false, true);
+        // Weighting adds 20 samples to each mark and removes them from each space
+        assert_eq!(marks(&mut keyer, 1000), vec![420, 420]);
+    }
+
+    #[test]
+    fn test_swap_paddles() {
+        let mut keyer = Keyer::new(KeyerType::ElBug, 12.0, RATE);
+        keyer.set_swap_paddles(true);
+        keyer.set_paddle(true, true);
+        assert_eq!(marks(&mut keyer, 450), vec![300]);
+    }
+}
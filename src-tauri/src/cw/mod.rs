@@ -1,16 +1,29 @@
 mod decoder;
+mod keyer;
 mod timing;
 
-use std::time::Instant;
+use std::time::{Duration, Instant};
+use crate::audio::KeyEdge;
 use crate::config::KeyerType;
 
 pub use decoder::CwDecoder;
+pub use keyer::Keyer;
 pub use timing::calculate_dit_duration;
 
+/// Longest stretch the software keyer catches up on in one call; time beyond
+/// it (a suspend, a stalled thread) is skipped rather than replayed
+const KEYER_MAX_CATCHUP: Duration = Duration::from_millis(100);
+
 /// CW Engine that handles keying logic and decoding
 pub struct CwEngine {
     decoder: CwDecoder,
     keyer_type: KeyerType,
+    /// Software keyer for raw paddle input (passthrough adapters, other paddle sources)
+    keyer: Keyer,
+    /// Whether raw paddle input goes through the software keyer
+    software_keyer: bool,
+    /// Time up to which the software keyer has been advanced
+    keyer_clock: Instant,
     wpm: f32,
     dit_duration_ms: f32,
     /// When the key went down
@@ -28,6 +41,9 @@ impl CwEngine {
         Self {
             decoder: CwDecoder::new(),
             keyer_type: KeyerType::Straight,
+            keyer: Keyer::new(KeyerType::Straight, wpm, 48000.0),
+            software_keyer: false,
+            keyer_clock: Instant::now(),
             wpm,
             dit_duration_ms,
             key_down_time: None,
@@ -45,6 +61,62 @@ impl CwEngine {
     /// Set keyer type
     pub fn set_keyer_type(&mut self, keyer_type: KeyerType) {
         self.keyer_type = keyer_type;
+        self.keyer.set_keyer_type(keyer_type);
+    }
+
+    /// Update software keyer timing and paddle settings
+    pub fn configure_keyer(&mut self, wpm: f32, dit_dah_ratio: f32, weighting: f32, swap_paddles: bool) {
+        self.keyer.set_timing(wpm, dit_dah_ratio, weighting);
+        self.keyer.set_swap_paddles(swap_paddles);
+    }
+
+    /// Enable or disable the software keyer
+    pub fn set_software_keyer(&mut self, enabled: bool) {
+        if !enabled {
+            self.keyer.reset();
+        }
+        self.software_keyer = enabled;
+    }
+
+    /// Whether raw paddle input should go through the software keyer
+    pub fn software_keyer_enabled(&self) -> bool {
+        self.software_keyer
+    }
+
+    /// Report a raw paddle press or release to the software keyer
+    pub fn paddle(&mut self, is_dit: bool, pressed: bool) {
+        self.keyer.set_paddle(is_dit, pressed);
+    }
+
+    /// Advance the software keyer up to `until`, counting samples at `sample_rate`.
+    /// Call before each paddle change so it takes effect at its own time.
+    /// Returns the key edges produced, each at the time it is due.
+    pub fn advance_keyer_to(&mut self, until: Instant, sample_rate: f32) -> Vec<KeyEdge> {
+        if !self.software_keyer {
+            self.keyer_clock = self.keyer_clock.max(until);
+            return Vec::new();
+        }
+        if until.saturating_duration_since(self.keyer_clock) > KEYER_MAX_CATCHUP {
+            self.keyer_clock = until - KEYER_MAX_CATCHUP;
+        }
+        let sample_rate = sample_rate as f64;
+        let samples = (until.saturating_duration_since(self.keyer_clock).as_secs_f64() * sample_rate) as u32;
+        if samples == 0 {
+            return Vec::new();
+        }
+
+        let block_start = self.keyer_clock;
+        self.keyer_clock += Duration::from_secs_f64(samples as f64 / sample_rate);
+        self.keyer.set_sample_rate(sample_rate as f32);
+        let mut events = Vec::new();
+        self.keyer.advance(samples, &mut events);
+        events
+            .into_iter()
+            .map(|event| KeyEdge {
+                down: event.key_down,
+                at: block_start + Duration::from_secs_f64(event.offset as f64 / sample_rate),
+            })
+            .collect()
     }
 
     /// Handle key down event
@@ -139,3 +211,34 @@ pub struct DecodedElement {
     pub character: String,
     pub wpm: f32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyer_edges_start_at_paddle_time() {
+        // 12 WPM gives a 100 ms dit
+        let mut engine = CwEngine::new(12.0);
+        engine.set_keyer_type(KeyerType::IambicA);
+        engine.configure_keyer(12.0, 3.0, 0.0, false);
+        engine.set_software_keyer(true);
+        let start = engine.keyer_clock;
+
+        let press = start + Duration::from_millis(30);
+        assert!(engine.advance_keyer_to(press, 1000.0).is_empty());
+        engine.paddle(true, true);
+        let release = press + Duration::from_millis(40);
+        let mut edges = engine.advance_keyer_to(release, 1000.0);
+        engine.paddle(true, false);
+        // Stepped like the MIDI loop, well inside the catch-up limit
+        for ms in (50..=500).step_by(10) {
+            edges.extend(engine.advance_keyer_to(press + Duration::from_millis(ms), 1000.0));
+        }
+
+        let times: Vec<(bool, Duration)> = edges.iter().map(|e| (e.down, e.at.saturating_duration_since(press))).collect();
+        assert_eq!(times.len(), 2, "{:?}", times);
+        assert!(times[0].0 && times[0].1 < Duration::from_millis(1), "{:?}", times);
+        assert!(!times[1].0 && times[1].1.abs_diff(Duration::from_millis(100)) < Duration::from_millis(1), "{:?}", times);
+    }
+}
@@ -1,5 +1,6 @@
 use midir::{MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
 use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Instant;
 
 /// MIDI event types
 #[derive(Debug, Clone)]
@@ -10,7 +11,7 @@ pub enum MidiEvent {
 }
 
 /// Vail adapter MIDI constants (from MIDI_INTEGRATION_SPEC.md)
-mod vail {
+pub mod vail {
     /// Control Change 0: Mode Control
     /// Values 0-63: MIDI mode (sends Note events), 64-127: Keyboard mode (sends HID)
     /// Note: The spec example says 0x7F but the values say 0-63 for MIDI mode
@@ -41,8 +42,8 @@ mod vail {
 pub struct MidiHandler {
     input_connection: Option<MidiInputConnection<()>>,
     output_connection: Option<MidiOutputConnection>,
-    event_rx: Receiver<MidiEvent>,
-    event_tx: Sender<MidiEvent>,
+    event_rx: Receiver<(MidiEvent, Instant)>,
+    event_tx: Sender<(MidiEvent, Instant)>,
     /// Persistent MidiInput client used only for listing available devices.
     /// Keeping this alive avoids creating/disposing CoreMIDI clients on every
     /// poll cycle, which can cause macOS to stop reporting hot-plugged devices.
@@ -120,8 +121,10 @@ impl MidiHandler {
                 &in_port,
                 "vail-zoomer-input",
                 move |_timestamp, message, _| {
+                    // Stamp on arrival so key edges keep their spacing through later polling
+                    let received = Instant::now();
                     if let Some(event) = parse_midi_message(message) {
-                        let _ = tx.send(event);
+                        let _ = tx.send((event, received));
                     }
                 },
                 (),
@@ -163,7 +166,7 @@ impl MidiHandler {
     /// Send keyer type to Vail adapter (Program Change)
     pub fn send_keyer_type(&mut self, keyer_type: u8) -> Result<(), String> {
         if let Some(ref mut conn) = self.output_connection {
-            let message = [0xC0, keyer_type.min(9)];  // Program Change, clamp to valid range
+            let message = [0xC0, keyer_type.min(vail::KEYER_KEYAHEAD)];  // Program Change, clamp to valid range
             conn.send(&message).map_err(|e| e.to_string())
         } else {
             Err("MIDI output not connected".to_string())
@@ -196,8 +199,8 @@ impl MidiHandler {
         }
     }
 
-    /// Try to receive a pending MIDI event (non-blocking)
-    pub fn try_recv(&self) -> Option<MidiEvent> {
+    /// Try to receive a pending MIDI event and the time it arrived (non-blocking)
+    pub fn try_recv(&self) -> Option<(MidiEvent, Instant)> {
         self.event_rx.try_recv().ok()
     }
 
@@ -7,24 +7,55 @@ mod input;
 mod config;
 mod linux_audio_setup;
 
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use parking_lot::Mutex;
 use tauri::{AppHandle, Emitter, Manager};
 
-use audio::{AudioEngineHandle, DeviceInfo};
-use input::{MidiHandler, MidiEvent};
-use cw::CwEngine;
+use audio::{AudioEngineHandle, AudioStats, DeviceInfo, LatencyStats, StreamConfigs};
+use input::{vail, MidiHandler, MidiEvent};
+use cw::{CwDecoder, CwEngine};
 use config::Settings;
 use serde::Serialize;
 
+/// Speed at which the configured envelope rise/fall times apply when scaling with WPM
+const ENVELOPE_REFERENCE_WPM: f32 = 20.0;
+
+/// Limits on the WPM envelope scale, so very slow or fast speeds can't
+/// stretch the edges into the dit or squeeze them into clicks
+const ENVELOPE_SCALE_RANGE: (f32, f32) = (0.25, 2.0);
+
+/// Key pulses sent during the loopback latency test
+const LOOPBACK_PULSES: usize = 10;
+
+/// How long each loopback test tone is keyed
+const LOOPBACK_TONE: Duration = Duration::from_millis(60);
+
+/// Time between loopback test key downs (also the window for matching tones)
+const LOOPBACK_SPACING: Duration = Duration::from_millis(300);
+
 /// Event payload for key state changes
 #[derive(Clone, Serialize)]
 struct KeyEvent {
     down: bool,
 }
 
+/// Event payload for CW decoded from the conference audio
+#[derive(Clone, Serialize)]
+struct RxDecodedEvent {
+    character: String,
+    wpm: f32,
+    pitch_hz: f32,
+}
+
+/// Event payload for voice activity changes
+#[derive(Clone, Serialize)]
+struct VoiceEvent {
+    active: bool,
+}
+
 /// Event payload for decoded CW characters
 #[derive(Clone, Serialize)]
 struct DecodedEvent {
@@ -49,47 +80,237 @@ fn get_settings(state: tauri::State<AppState>) -> Settings {
     state.settings.lock().clone()
 }
 
+/// Convert config waveform settings to an audio waveform, falling back to a
+/// sine if the wavetable can't be loaded
+fn audio_waveform(settings: &config::WaveformSettings) -> audio::Waveform {
+    match settings.waveform {
+        config::Waveform::Sine => audio::Waveform::Sine,
+        config::Waveform::Harmonics => {
+            let mut amplitudes = [0.0; audio::HARMONIC_COUNT];
+            for (amplitude, value) in amplitudes.iter_mut().zip(&settings.harmonics) {
+                *amplitude = value.clamp(-1.0, 1.0);
+            }
+            audio::Waveform::Harmonics(amplitudes)
+        }
+        config::Waveform::SoftSquare => audio::Waveform::SoftSquare {
+            drive: settings.square_drive.clamp(0.1, audio::MAX_SQUARE_DRIVE),
+        },
+        config::Waveform::Wavetable => {
+            let loaded = settings
+                .wavetable_path
+                .as_deref()
+                .ok_or_else(|| "No wavetable file selected".to_string())
+                .and_then(|path| audio::load_wavetable(std::path::Path::new(path)));
+            match loaded {
+                Ok(table) => audio::Waveform::Wavetable(table),
+                Err(e) => {
+                    eprintln!("[audio] Failed to load wavetable {:?}: {}", settings.wavetable_path, e);
+                    audio::Waveform::Sine
+                }
+            }
+        }
+    }
+}
+
+/// Push audio-related settings into the audio engine
+fn apply_audio_settings(engine: &AudioEngineHandle, settings: &Settings) {
+    engine.set_sidetone_frequency(settings.sidetone_frequency);
+    engine.set_sidetone_volume(settings.sidetone_volume);
+    engine.set_local_sidetone_frequency(settings.local_sidetone_frequency.unwrap_or(settings.sidetone_frequency));
+    engine.set_local_sidetone_volume(settings.local_sidetone_volume);
+
+    // Convert config envelope shape to audio envelope shape
+    let audio_shape = |shape: config::EnvelopeShape| match shape {
+        config::EnvelopeShape::Linear => audio::EnvelopeShape::Linear,
+        config::EnvelopeShape::RaisedCosine => audio::EnvelopeShape::RaisedCosine,
+        config::EnvelopeShape::BlackmanHarris => audio::EnvelopeShape::BlackmanHarris,
+    };
+    // Shorter edges at higher speeds so the rise/fall stays in proportion to the dit
+    let envelope_scale = if settings.envelope_scale_with_wpm && settings.wpm > 0.0 {
+        (ENVELOPE_REFERENCE_WPM / settings.wpm).clamp(ENVELOPE_SCALE_RANGE.0, ENVELOPE_SCALE_RANGE.1)
+    } else {
+        1.0
+    };
+    engine.set_envelope(
+        audio_shape(settings.envelope_shape),
+        settings.envelope_rise_ms * envelope_scale,
+        settings.envelope_fall_ms * envelope_scale,
+    );
+    // Local envelope settings that aren't set follow the output's
+    engine.set_local_envelope(
+        audio_shape(settings.local_envelope_shape.unwrap_or(settings.envelope_shape)),
+        settings.local_envelope_rise_ms.unwrap_or(settings.envelope_rise_ms) * envelope_scale,
+        settings.local_envelope_fall_ms.unwrap_or(settings.envelope_fall_ms) * envelope_scale,
+    );
+    engine.set_output_waveform(&audio_waveform(&settings.output_waveform));
+    engine.set_local_waveform(&audio_waveform(&settings.local_waveform));
+    engine.set_mic_volume(settings.mic_volume);
+    let gate = &settings.mic_gate;
+    engine.set_mic_gate(&audio::GateSettings {
+        enabled: gate.enabled,
+        threshold_db: gate.threshold_db,
+        ratio: gate.ratio,
+        range_db: gate.range_db,
+        attack_ms: gate.attack_ms,
+        hold_ms: gate.hold_ms,
+        release_ms: gate.release_ms,
+    });
+    let compressor = &settings.mic_compressor;
+    engine.set_mic_compressor(&audio::CompressorSettings {
+        enabled: compressor.enabled,
+        threshold_db: compressor.threshold_db,
+        ratio: compressor.ratio,
+        makeup_db: compressor.makeup_db,
+        attack_ms: compressor.attack_ms,
+        release_ms: compressor.release_ms,
+    });
+    engine.set_bleed_cancellation(settings.cancel_sidetone_bleed);
+    let voice = &settings.voice_ducking;
+    engine.set_voice_ducking(&audio::VadSettings {
+        enabled: voice.enabled,
+        duck_db: voice.duck_db,
+        threshold_db: voice.threshold_db,
+        hang_ms: voice.hang_ms,
+    });
+    engine.set_spectrum(&audio::SpectrumSettings {
+        enabled: settings.spectrum.enabled,
+        rate_hz: settings.spectrum.rate_hz,
+        fft_size: settings.spectrum.fft_size,
+    });
+    // A word gap is 7 dits; a dit is 1200 / WPM ms
+    let word_gap_ms = 7.0 * 1200.0 / settings.wpm.max(1.0);
+    engine.set_mic_ducking(&audio::DuckingSettings {
+        enabled: settings.mic_ducking,
+        hold_ms: if settings.ducking_hold_follows_wpm { word_gap_ms } else { settings.ducking_hold_ms },
+        depth: settings.ducking_depth,
+        attack_ms: settings.ducking_attack_ms,
+        release_ms: settings.ducking_release_ms,
+    });
+
+    // Convert config sidetone route to audio sidetone route
+    let audio_route = match settings.sidetone_route {
+        config::SidetoneRoute::OutputOnly => audio::SidetoneRoute::OutputOnly,
+        config::SidetoneRoute::LocalOnly => audio::SidetoneRoute::LocalOnly,
+        config::SidetoneRoute::Both => audio::SidetoneRoute::Both,
+    };
+    engine.set_sidetone_route(audio_route);
+
+    // Convert config mix mode to audio mix mode
+    let mix_mode = match settings.mix_mode {
+        config::MixMode::AlwaysMix => audio::MixMode::AlwaysMix,
+        config::MixMode::CwMutesMic => audio::MixMode::CwMutesMic,
+        config::MixMode::PushToTalkVoice => audio::MixMode::PushToTalkVoice,
+    };
+    engine.set_mix_mode(mix_mode);
+    engine.set_cw_mute_tail_ms(if settings.cw_mute_tail_follows_wpm { word_gap_ms } else { settings.cw_mute_tail_ms });
+    engine.set_key_latency_ms(settings.key_latency_ms);
+    engine.set_software_keyer(settings.software_keyer);
+    engine.set_tone_key_debounce_ms(settings.tone_key_debounce_ms);
+    engine.set_test_recording_max_seconds(settings.test_recording_max_seconds);
+
+    // Convert config sample format to audio sample format preference
+    let sample_format = match settings.preferred_sample_format {
+        config::SampleFormatPreference::Auto => audio::SampleFormatPreference::Auto,
+        config::SampleFormatPreference::F32 => audio::SampleFormatPreference::F32,
+        config::SampleFormatPreference::I16 => audio::SampleFormatPreference::I16,
+    };
+    engine.set_stream_preferences(audio::StreamPreferences {
+        buffer_frames: settings.buffer_size_frames,
+        sample_rate: settings.preferred_sample_rate,
+        sample_format,
+    });
+
+    apply_channel_settings(engine, settings, settings.input_device.as_deref(), settings.output_device.as_deref());
+}
+
+/// Apply the channel selection saved for these devices (None = system default)
+fn apply_channel_settings(engine: &AudioEngineHandle, settings: &Settings, input_device: Option<&str>, output_device: Option<&str>) {
+    let input = settings.input_channels.get(input_device.unwrap_or("")).cloned().unwrap_or_default();
+    engine.set_input_channels(&match input.mode {
+        config::InputChannelMode::Average => audio::InputChannels::Average,
+        config::InputChannelMode::Single => audio::InputChannels::Single(input.channel),
+        config::InputChannelMode::Sum => audio::InputChannels::Sum,
+        config::InputChannelMode::Custom => audio::InputChannels::Gains(input.gains),
+    });
+
+    let output: Vec<audio::OutputChannel> = settings
+        .output_channels
+        .get(output_device.unwrap_or(""))
+        .map(|roles| {
+            roles
+                .iter()
+                .map(|role| match role {
+                    config::OutputChannelRole::Mix => audio::OutputChannel::Mix,
+                    config::OutputChannelRole::Sidetone => audio::OutputChannel::Sidetone,
+                    config::OutputChannelRole::Mic => audio::OutputChannel::Mic,
+                    config::OutputChannelRole::Silent => audio::OutputChannel::Silent,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    engine.set_output_channels(&output);
+}
+
+/// Register the global push-to-talk shortcut, replacing any previous one
+fn register_ptt_hotkey(app: &AppHandle, hotkey: Option<&str>) {
+    use tauri_plugin_global_shortcut::{GlobalShortcutExt, ShortcutState};
+
+    let shortcuts = app.global_shortcut();
+    let _ = shortcuts.unregister_all();
+
+    if let Some(hotkey) = hotkey {
+        let result = shortcuts.on_shortcut(hotkey, |app, _shortcut, event| {
+            let pressed = matches!(event.state, ShortcutState::Pressed);
+            let state = app.state::<AppState>();
+            let audio_engine = state.audio_engine.lock();
+            if let Some(ref engine) = *audio_engine {
+                engine.set_ptt(pressed);
+            }
+        });
+        match result {
+            Ok(()) => eprintln!("[ptt] Registered push-to-talk hotkey: {}", hotkey),
+            Err(e) => eprintln!("[ptt] Failed to register hotkey '{}': {}", hotkey, e),
+        }
+    }
+}
+
 #[tauri::command]
-fn update_settings(state: tauri::State<AppState>, settings: Settings) -> Result<(), String> {
+fn update_settings(app: AppHandle, state: tauri::State<AppState>, settings: Settings) -> Result<(), String> {
     let mut current = state.settings.lock();
+    let hotkey_changed = current.ptt_hotkey != settings.ptt_hotkey;
     *current = settings.clone();
 
     // Update audio engine with new settings
     if let Some(ref engine) = *state.audio_engine.lock() {
-        engine.set_sidetone_frequency(settings.sidetone_frequency);
-        engine.set_sidetone_volume(settings.sidetone_volume);
-        engine.set_local_sidetone_volume(settings.local_sidetone_volume);
-        engine.set_mic_volume(settings.mic_volume);
-        engine.set_mic_ducking(settings.mic_ducking);
+        apply_audio_settings(engine, &settings);
+    }
 
-        // Convert config sidetone route to audio sidetone route
-        let audio_route = match settings.sidetone_route {
-            config::SidetoneRoute::OutputOnly => audio::SidetoneRoute::OutputOnly,
-            config::SidetoneRoute::LocalOnly => audio::SidetoneRoute::LocalOnly,
-            config::SidetoneRoute::Both => audio::SidetoneRoute::Both,
-        };
-        engine.set_sidetone_route(audio_route);
+    if hotkey_changed {
+        register_ptt_hotkey(&app, settings.ptt_hotkey.as_deref());
     }
 
     // Update CW engine with new settings
     let mut cw = state.cw_engine.lock();
     cw.set_wpm(settings.wpm);
     cw.set_keyer_type(settings.keyer_type);
+    cw.configure_keyer(settings.wpm, settings.dit_dah_ratio, settings.weighting, settings.swap_paddles);
+    cw.set_software_keyer(settings.software_keyer);
 
     // Sync settings to Vail adapter via MIDI
     if let Some(ref mut handler) = *state.midi_handler.lock() {
         // Send keyer type (convert enum to MIDI program number)
-        let keyer_num = match settings.keyer_type {
-            config::KeyerType::Straight => 1,
-            config::KeyerType::Bug => 2,
-            config::KeyerType::ElBug => 3,
-            config::KeyerType::SingleDot => 4,
-            config::KeyerType::Ultimatic => 5,
-            config::KeyerType::PlainIambic => 6,
-            config::KeyerType::IambicA => 7,
-            config::KeyerType::IambicB => 8,
-            config::KeyerType::Keyahead => 9,
-        };
+        // With the software keyer the adapter only passes raw paddle states through
+        let keyer_num = if settings.software_keyer { vail::KEYER_PASSTHROUGH } else { match settings.keyer_type {
+            config::KeyerType::Straight => vail::KEYER_STRAIGHT,
+            config::KeyerType::Bug => vail::KEYER_BUG,
+            config::KeyerType::ElBug => vail::KEYER_ELECTRIC_BUG,
+            config::KeyerType::SingleDot => vail::KEYER_SINGLE_DOT,
+            config::KeyerType::Ultimatic => vail::KEYER_ULTIMATIC,
+            config::KeyerType::PlainIambic => vail::KEYER_PLAIN_IAMBIC,
+            config::KeyerType::IambicA => vail::KEYER_IAMBIC_A,
+            config::KeyerType::IambicB => vail::KEYER_IAMBIC_B,
+            config::KeyerType::Keyahead => vail::KEYER_KEYAHEAD,
+        } };
         let _ = handler.send_keyer_type(keyer_num);
 
         // Send WPM
@@ -132,6 +353,33 @@ fn get_mic_level(state: tauri::State<AppState>) -> f32 {
     }
 }
 
+#[tauri::command]
+fn get_mic_gate_level(state: tauri::State<AppState>) -> f32 {
+    if let Some(ref engine) = *state.audio_engine.lock() {
+        engine.get_mic_gate_level()
+    } else {
+        1.0
+    }
+}
+
+#[tauri::command]
+fn get_gain_reduction(state: tauri::State<AppState>) -> audio::GainReduction {
+    if let Some(ref engine) = *state.audio_engine.lock() {
+        engine.get_gain_reduction()
+    } else {
+        audio::GainReduction::default()
+    }
+}
+
+#[tauri::command]
+fn get_bleed_status(state: tauri::State<AppState>) -> audio::BleedStatus {
+    if let Some(ref engine) = *state.audio_engine.lock() {
+        engine.get_bleed_status()
+    } else {
+        audio::BleedStatus::default()
+    }
+}
+
 #[tauri::command]
 fn get_output_level(state: tauri::State<AppState>) -> f32 {
     if let Some(ref engine) = *state.audio_engine.lock() {
@@ -141,6 +389,140 @@ fn get_output_level(state: tauri::State<AppState>) -> f32 {
     }
 }
 
+#[tauri::command]
+fn get_meters(state: tauri::State<AppState>) -> audio::Meters {
+    if let Some(ref engine) = *state.audio_engine.lock() {
+        engine.get_meters()
+    } else {
+        audio::Meters::default()
+    }
+}
+
+#[tauri::command]
+fn get_audio_stats(state: tauri::State<AppState>) -> AudioStats {
+    if let Some(ref engine) = *state.audio_engine.lock() {
+        engine.get_audio_stats()
+    } else {
+        AudioStats::default()
+    }
+}
+
+/// Stream configurations actually negotiated with the devices
+#[tauri::command]
+fn get_stream_configs(state: tauri::State<AppState>) -> StreamConfigs {
+    if let Some(ref engine) = *state.audio_engine.lock() {
+        engine.get_stream_configs()
+    } else {
+        StreamConfigs::default()
+    }
+}
+
+/// Start (clearing earlier results) or stop measuring key-to-audio latency
+#[tauri::command]
+fn set_latency_measurement(state: tauri::State<AppState>, enabled: bool) {
+    if let Some(ref engine) = *state.audio_engine.lock() {
+        engine.set_latency_measurement(enabled);
+    }
+}
+
+/// Key-to-buffer latency measured since measuring was started
+#[tauri::command]
+fn get_latency_report(state: tauri::State<AppState>) -> LatencyStats {
+    if let Some(ref engine) = *state.audio_engine.lock() {
+        engine.get_latency_report()
+    } else {
+        LatencyStats::default()
+    }
+}
+
+/// Key the sidetone a few times and time it on a loopback of the output.
+/// On Linux this defaults to the monitor of the selected output sink; other
+/// platforms need the recording side of the virtual cable passed in.
+/// Runs off the main thread since it takes a few seconds.
+#[tauri::command(async)]
+fn run_loopback_latency_test(state: tauri::State<AppState>, monitor_device: Option<String>) -> Result<LatencyStats, String> {
+    #[cfg(target_os = "linux")]
+    let device = monitor_device.unwrap_or_else(|| {
+        match state.settings.lock().output_device.as_deref() {
+            Some(sink) => format!("{}.monitor", sink),
+            None => "@DEFAULT_MONITOR@".to_string(),
+        }
+    });
+    #[cfg(not(target_os = "linux"))]
+    let device = monitor_device.ok_or_else(|| "Select a loopback input device for the latency test".to_string())?;
+
+    let with_engine = |f: &dyn Fn(&AudioEngineHandle) -> Result<(), String>| -> Result<(), String> {
+        match *state.audio_engine.lock() {
+            Some(ref engine) => f(engine),
+            None => Err("Audio is not running".to_string()),
+        }
+    };
+
+    with_engine(&|engine| engine.start_loopback_test(device.clone()))?;
+    // Let the capture stream start and get routed
+    thread::sleep(Duration::from_millis(500));
+
+    let mut key_downs = Vec::with_capacity(LOOPBACK_PULSES);
+    for _ in 0..LOOPBACK_PULSES {
+        let at = Instant::now();
+        with_engine(&|engine| {
+            engine.key_down(at);
+            Ok(())
+        })?;
+        key_downs.push(at);
+        thread::sleep(LOOPBACK_TONE);
+        with_engine(&|engine| {
+            engine.key_up(Instant::now());
+            Ok(())
+        })?;
+        thread::sleep(LOOPBACK_SPACING - LOOPBACK_TONE);
+    }
+
+    match *state.audio_engine.lock() {
+        Some(ref engine) => engine.finish_loopback_test(&key_downs, LOOPBACK_SPACING),
+        None => Err("Audio is not running".to_string()),
+    }
+}
+
+/// Start decoding the CW other participants send, captured from
+/// `monitor_device` (the speakers' monitor on Linux by default)
+#[tauri::command]
+fn start_rx_decoder(state: tauri::State<AppState>, monitor_device: Option<String>) -> Result<(), String> {
+    #[cfg(target_os = "linux")]
+    let device = monitor_device.unwrap_or_else(|| "@DEFAULT_MONITOR@".to_string());
+    #[cfg(not(target_os = "linux"))]
+    let device = monitor_device.ok_or_else(|| "Select a loopback input device to decode from".to_string())?;
+
+    match *state.audio_engine.lock() {
+        Some(ref engine) => engine.start_rx_decoder(device),
+        None => Err("Audio is not running".to_string()),
+    }
+}
+
+#[tauri::command]
+fn stop_rx_decoder(state: tauri::State<AppState>) {
+    if let Some(ref engine) = *state.audio_engine.lock() {
+        engine.stop_rx_decoder();
+    }
+}
+
+/// Key the sidetone and decoder from a CW tone on an audio input, e.g. a
+/// receiver's audio or a practice oscillator
+#[tauri::command]
+fn start_tone_keying(state: tauri::State<AppState>, device_name: String) -> Result<(), String> {
+    match *state.audio_engine.lock() {
+        Some(ref engine) => engine.start_tone_keying(device_name),
+        None => Err("Audio is not running".to_string()),
+    }
+}
+
+#[tauri::command]
+fn stop_tone_keying(state: tauri::State<AppState>) {
+    if let Some(ref engine) = *state.audio_engine.lock() {
+        engine.stop_tone_keying();
+    }
+}
+
 #[tauri::command]
 fn list_input_devices() -> Vec<DeviceInfo> {
     AudioEngineHandle::list_input_devices()
@@ -153,6 +535,7 @@ fn start_audio(state: tauri::State<AppState>, device_name: Option<String>) -> Re
     if engine_lock.is_none() {
         let settings = state.settings.lock().clone();
         let engine = AudioEngineHandle::new(settings.sidetone_frequency, settings.sidetone_volume)?;
+        apply_audio_settings(&engine, &settings);
         *engine_lock = Some(engine);
     }
 
@@ -174,10 +557,13 @@ fn start_audio_with_devices(
     if engine_lock.is_none() {
         let settings = state.settings.lock().clone();
         let engine = AudioEngineHandle::new(settings.sidetone_frequency, settings.sidetone_volume)?;
+        apply_audio_settings(&engine, &settings);
         *engine_lock = Some(engine);
     }
 
     if let Some(ref engine) = *engine_lock {
+        let settings = state.settings.lock().clone();
+        apply_channel_settings(engine, &settings, input_device.as_deref(), output_device.as_deref());
         engine.start_with_devices(output_device, input_device)?;
     }
 
@@ -196,11 +582,13 @@ fn start_audio_with_all_devices(
     if engine_lock.is_none() {
         let settings = state.settings.lock().clone();
         let engine = AudioEngineHandle::new(settings.sidetone_frequency, settings.sidetone_volume)?;
+        apply_audio_settings(&engine, &settings);
         *engine_lock = Some(engine);
     }
 
     if let Some(ref engine) = *engine_lock {
         let settings = state.settings.lock().clone();
+        apply_channel_settings(engine, &settings, input_device.as_deref(), output_device.as_deref());
         let audio_route = match settings.sidetone_route {
             config::SidetoneRoute::OutputOnly => audio::SidetoneRoute::OutputOnly,
             config::SidetoneRoute::LocalOnly => audio::SidetoneRoute::LocalOnly,
@@ -228,12 +616,20 @@ fn set_mic_volume(state: tauri::State<AppState>, volume: f32) {
     }
 }
 
+/// Press or release push-to-talk (PushToTalkVoice mix mode)
+#[tauri::command]
+fn set_ptt(state: tauri::State<AppState>, active: bool) {
+    if let Some(ref engine) = *state.audio_engine.lock() {
+        engine.set_ptt(active);
+    }
+}
+
 #[tauri::command]
 fn key_down(state: tauri::State<AppState>, is_dit: bool) {
     eprintln!("[cmd] key_down called (is_dit={})", is_dit);
     // Trigger sidetone
     if let Some(ref engine) = *state.audio_engine.lock() {
-        engine.key_down();
+        engine.key_down(Instant::now());
     } else {
         eprintln!("[cmd] WARNING: No audio engine in key_down!");
     }
@@ -248,7 +644,7 @@ fn key_up(state: tauri::State<AppState>) {
     eprintln!("[cmd] key_up called");
     // Stop sidetone
     if let Some(ref engine) = *state.audio_engine.lock() {
-        engine.key_up();
+        engine.key_up(Instant::now());
     }
 
     // Feed to CW engine for decoding
@@ -256,23 +652,88 @@ fn key_up(state: tauri::State<AppState>) {
     cw.key_up();
 }
 
-/// Helper to emit decoded characters to frontend
-fn emit_decoded(app_handle: &AppHandle, decoded: cw::DecodedElement) {
+/// Report a raw paddle press/release to the software keyer
+#[tauri::command]
+fn paddle(app: AppHandle, state: tauri::State<AppState>, is_dit: bool, pressed: bool) {
+    // Run the keyer up to now so the change lands on the current element
+    let sample_rate = state.audio_engine.lock().as_ref().map_or(48000, |e| e.get_sample_rate()) as f32;
+    advance_software_keyer(&app, &state.audio_engine, &state.cw_engine, Instant::now(), sample_rate);
+    state.cw_engine.lock().paddle(is_dit, pressed);
+}
+
+/// Helper to emit decoded characters to frontend (and note them in a session recording)
+fn emit_decoded(app_handle: &AppHandle, audio_engine: &Mutex<Option<AudioEngineHandle>>, decoded: cw::DecodedElement) {
+    if let Some(ref engine) = *audio_engine.lock() {
+        engine.record_decoded(&decoded.character, decoded.wpm, false);
+    }
     let _ = app_handle.emit("cw:decoded", DecodedEvent {
         character: decoded.character,
         wpm: decoded.wpm,
     });
 }
 
+/// Drive the sidetone and decoder from a software keyer edge
+fn apply_keyer_edge(
+    app_handle: &AppHandle,
+    audio_engine: &Mutex<Option<AudioEngineHandle>>,
+    cw_engine: &Mutex<CwEngine>,
+    down: bool,
+    at: Instant,
+) {
+    if let Some(ref engine) = *audio_engine.lock() {
+        if down {
+            engine.key_down(at);
+        } else {
+            engine.key_up(at);
+        }
+    }
+
+    let decoded = {
+        let mut cw = cw_engine.lock();
+        if down {
+            cw.key_down(true)
+        } else {
+            cw.key_up()
+        }
+    };
+    if let Some(decoded) = decoded {
+        emit_decoded(app_handle, audio_engine, decoded);
+    }
+
+    let _ = app_handle.emit("cw:key", KeyEvent { down });
+}
+
+/// Advance the software keyer up to `until`, driving the sidetone and
+/// decoder from the edges it produces
+fn advance_software_keyer(
+    app_handle: &AppHandle,
+    audio_engine: &Mutex<Option<AudioEngineHandle>>,
+    cw_engine: &Mutex<CwEngine>,
+    until: Instant,
+    sample_rate: f32,
+) {
+    let edges = cw_engine.lock().advance_keyer_to(until, sample_rate);
+    for edge in edges {
+        apply_keyer_edge(app_handle, audio_engine, cw_engine, edge.down, edge.at);
+    }
+}
+
 /// Spawn a background thread to process MIDI events
 fn start_midi_event_loop(
     app_handle: AppHandle,
+    settings: Arc<Mutex<Settings>>,
     midi_handler: Arc<Mutex<Option<MidiHandler>>>,
     audio_engine: Arc<Mutex<Option<AudioEngineHandle>>>,
     cw_engine: Arc<Mutex<CwEngine>>,
 ) {
     thread::spawn(move || {
         let mut loop_counter: u32 = 0;
+        // Output rate the keyer counts samples at, refreshed every ~50ms
+        let mut keyer_rate = 48000.0;
+        // Last voice activity state sent to the frontend
+        let mut voice_active = false;
+        // Decodes the CW heard in the conference audio, separately from our own keying
+        let mut rx_decoder = CwDecoder::new();
 
         loop {
             // Poll for MIDI events
@@ -284,27 +745,43 @@ fn start_midi_event_loop(
                 }
             };
 
-            if let Some(event) = event {
+            if let Some((event, received)) = event {
                 match event {
                     MidiEvent::NoteOn { note, velocity } => {
                         eprintln!("[midi] *** NOTE ON: note={}, velocity={} ***", note, velocity);
 
+                        // Push-to-talk note
+                        if settings.lock().ptt_midi_note == Some(note) {
+                            if let Some(ref engine) = *audio_engine.lock() {
+                                engine.set_ptt(true);
+                            }
+                            continue;
+                        }
+
                         // Determine if this is a dit or dah based on note
                         // Vail adapter sends note 1 for dit, note 2 for dah (in keyer modes)
                         // In Passthrough mode it sends C# (61) for dit, D (62) for dah
                         let is_dit = note == 1 || note == 61;
 
+                        // Passthrough paddle notes go to the software keyer when it is enabled
+                        if (note == 61 || note == 62) && cw_engine.lock().software_keyer_enabled() {
+                            // Run the keyer up to the press so the element starts on it
+                            advance_software_keyer(&app_handle, &audio_engine, &cw_engine, received, keyer_rate);
+                            cw_engine.lock().paddle(is_dit, true);
+                            continue;
+                        }
+
                         // Trigger sidetone
                         if let Some(ref engine) = *audio_engine.lock() {
-                            engine.key_down();
+                            engine.key_down(received);
                         } else {
                             eprintln!("[midi] WARNING: No audio engine available!");
                         }
 
                         // Feed to CW engine - key_down may return decoded chars (from gap)
-                        let mut cw = cw_engine.lock();
-                        if let Some(decoded) = cw.key_down(is_dit) {
-                            emit_decoded(&app_handle, decoded);
+                        let decoded = cw_engine.lock().key_down(is_dit);
+                        if let Some(decoded) = decoded {
+                            emit_decoded(&app_handle, &audio_engine, decoded);
                         }
 
                         // Emit event to frontend
@@ -313,15 +790,28 @@ fn start_midi_event_loop(
                     MidiEvent::NoteOff { note } => {
                         eprintln!("[midi] Note Off: note={}", note);
 
+                        if settings.lock().ptt_midi_note == Some(note) {
+                            if let Some(ref engine) = *audio_engine.lock() {
+                                engine.set_ptt(false);
+                            }
+                            continue;
+                        }
+
+                        if (note == 61 || note == 62) && cw_engine.lock().software_keyer_enabled() {
+                            advance_software_keyer(&app_handle, &audio_engine, &cw_engine, received, keyer_rate);
+                            cw_engine.lock().paddle(note == 61, false);
+                            continue;
+                        }
+
                         // Stop sidetone
                         if let Some(ref engine) = *audio_engine.lock() {
-                            engine.key_up();
+                            engine.key_up(received);
                         }
 
                         // Feed to CW engine
-                        let mut cw = cw_engine.lock();
-                        if let Some(decoded) = cw.key_up() {
-                            emit_decoded(&app_handle, decoded);
+                        let decoded = cw_engine.lock().key_up();
+                        if let Some(decoded) = decoded {
+                            emit_decoded(&app_handle, &audio_engine, decoded);
                         }
 
                         // Emit key up event
@@ -333,12 +823,80 @@ fn start_midi_event_loop(
                 }
             }
 
-            // Check for timeout every ~50ms (50 loop iterations at 1ms sleep)
+            // Key edges heard on the tone keying input, like a straight key
+            let tone_edges = match *audio_engine.lock() {
+                Some(ref engine) => engine.drain_tone_key_edges(),
+                None => Vec::new(),
+            };
+            for edge in tone_edges {
+                apply_keyer_edge(&app_handle, &audio_engine, &cw_engine, edge.down, edge.at);
+            }
+
+            // Advance the software keyer by the time elapsed since the last pass
+            advance_software_keyer(&app_handle, &audio_engine, &cw_engine, Instant::now(), keyer_rate);
+
+            // Pick up output rate changes for the keyer every ~50ms (50 loop iterations at 1ms sleep)
             loop_counter = loop_counter.wrapping_add(1);
-            if loop_counter % 50 == 0 {
-                let mut cw = cw_engine.lock();
-                if let Some(decoded) = cw.check_timeout() {
-                    emit_decoded(&app_handle, decoded);
+            if loop_counter.is_multiple_of(50) {
+                keyer_rate = audio_engine.lock().as_ref().map_or(48000, |e| e.get_sample_rate()) as f32;
+            }
+
+            // Check for timeout every ~50ms
+            if loop_counter.is_multiple_of(50) {
+                let decoded = cw_engine.lock().check_timeout();
+                if let Some(decoded) = decoded {
+                    emit_decoded(&app_handle, &audio_engine, decoded);
+                }
+            }
+
+            // Forward audio stream recovery attempts to the frontend
+            if loop_counter.is_multiple_of(50) {
+                let recoveries = match *audio_engine.lock() {
+                    Some(ref engine) => engine.drain_recovery_events(),
+                    None => Vec::new(),
+                };
+                for recovery in recoveries {
+                    let _ = app_handle.emit("audio:recovery", recovery);
+                }
+            }
+
+            // Forward spectrum frames as the analyzer produces them
+            if loop_counter.is_multiple_of(5) {
+                let frame = match *audio_engine.lock() {
+                    Some(ref engine) => engine.latest_spectrum_frame(),
+                    None => None,
+                };
+                if let Some(frame) = frame {
+                    let _ = app_handle.emit("audio:spectrum", frame);
+                }
+            }
+
+            // Decode key timings heard in the conference audio
+            if loop_counter.is_multiple_of(10) {
+                let (timings, pitch_hz) = match *audio_engine.lock() {
+                    Some(ref engine) => (engine.drain_rx_timings(), engine.get_rx_pitch()),
+                    None => (Vec::new(), 0.0),
+                };
+                for timing in timings {
+                    if let Some(character) = rx_decoder.add_timing(timing) {
+                        let wpm = rx_decoder.estimate_wpm();
+                        if let Some(ref engine) = *audio_engine.lock() {
+                            engine.record_decoded(&character, wpm, true);
+                        }
+                        let _ = app_handle.emit("cw:rx_decoded", RxDecodedEvent { character, wpm, pitch_hz });
+                    }
+                }
+            }
+
+            // Tell the frontend when the operator starts or stops talking
+            if loop_counter.is_multiple_of(50) {
+                let active = match *audio_engine.lock() {
+                    Some(ref engine) => engine.is_voice_active(),
+                    None => false,
+                };
+                if active != voice_active {
+                    voice_active = active;
+                    let _ = app_handle.emit("audio:voice", VoiceEvent { active });
                 }
             }
 
@@ -385,10 +943,11 @@ struct TestRecordingState {
     playback_progress: f32,
 }
 
+/// Start a test recording of the mix, or with `mic_only` of the raw mic alone
 #[tauri::command]
-fn start_test_recording(state: tauri::State<AppState>) -> Result<(), String> {
+fn start_test_recording(state: tauri::State<AppState>, mic_only: Option<bool>) -> Result<(), String> {
     if let Some(ref engine) = *state.audio_engine.lock() {
-        engine.start_test_recording()
+        engine.start_test_recording(mic_only.unwrap_or(false))
     } else {
         Err("Audio engine not initialized".to_string())
     }
@@ -403,6 +962,16 @@ fn stop_test_recording(state: tauri::State<AppState>) -> Result<(), String> {
     }
 }
 
+/// Save the test recording as a WAV file, e.g. to send to someone helping with levels
+#[tauri::command]
+fn export_test_recording(state: tauri::State<AppState>, path: String) -> Result<(), String> {
+    if let Some(ref engine) = *state.audio_engine.lock() {
+        engine.export_test_recording(Path::new(&path))
+    } else {
+        Err("Audio engine not initialized".to_string())
+    }
+}
+
 #[tauri::command]
 fn play_test_recording(state: tauri::State<AppState>, local_device: Option<String>) -> Result<(), String> {
     if let Some(ref engine) = *state.audio_engine.lock() {
@@ -428,7 +997,7 @@ fn get_test_recording_state(state: tauri::State<AppState>) -> TestRecordingState
             is_recording: engine.is_recording(),
             is_playing: engine.is_playing(),
             samples_recorded: engine.get_recording_samples(),
-            sample_rate: engine.get_sample_rate(),
+            sample_rate: engine.get_recording_sample_rate(),
             duration_seconds: engine.get_recording_duration(),
             playback_progress: engine.get_playback_progress(),
         }
@@ -444,23 +1013,66 @@ fn get_test_recording_state(state: tauri::State<AppState>) -> TestRecordingState
     }
 }
 
+// Session Recording Commands
+
+/// Start recording the session's mix, mic and sidetone stems plus a JSON
+/// sidecar into `directory` (default: a "Vail Zoomer" folder in the user's
+/// music directory). Returns the sidecar's path.
+#[tauri::command]
+fn start_session_recording(state: tauri::State<AppState>, directory: Option<String>) -> Result<String, String> {
+    let directory = match directory {
+        Some(directory) => PathBuf::from(directory),
+        None => dirs::audio_dir()
+            .or_else(dirs::home_dir)
+            .ok_or_else(|| "No directory to record into".to_string())?
+            .join("Vail Zoomer"),
+    };
+    match *state.audio_engine.lock() {
+        Some(ref engine) => engine.start_session_recording(&directory).map(|path| path.display().to_string()),
+        None => Err("Audio engine not initialized".to_string()),
+    }
+}
+
+#[tauri::command]
+fn stop_session_recording(state: tauri::State<AppState>) -> Result<audio::SessionStatus, String> {
+    match *state.audio_engine.lock() {
+        Some(ref engine) => engine.stop_session_recording(),
+        None => Err("Audio engine not initialized".to_string()),
+    }
+}
+
+/// Duration and file size of the current (or last) session recording
+#[tauri::command]
+fn get_session_recording_state(state: tauri::State<AppState>) -> audio::SessionStatus {
+    match *state.audio_engine.lock() {
+        Some(ref engine) => engine.get_session_recording_status(),
+        None => audio::SessionStatus::default(),
+    }
+}
+
 fn main() {
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_os::init())
         .plugin(tauri_plugin_updater::Builder::new().build())
         .plugin(tauri_plugin_process::init())
+        .plugin(tauri_plugin_global_shortcut::Builder::new().build())
         .setup(|app| {
             // Load settings from disk (or use defaults if not found)
             let settings = Settings::load();
-            let cw_engine = CwEngine::new(settings.wpm);
+            let mut cw_engine = CwEngine::new(settings.wpm);
+            cw_engine.set_keyer_type(settings.keyer_type);
+            cw_engine.configure_keyer(settings.wpm, settings.dit_dah_ratio, settings.weighting, settings.swap_paddles);
+            cw_engine.set_software_keyer(settings.software_keyer);
 
+            let ptt_hotkey = settings.ptt_hotkey.clone();
             let midi_handler = Arc::new(Mutex::new(MidiHandler::new().ok()));
             let audio_engine = Arc::new(Mutex::new(None));
             let cw_engine = Arc::new(Mutex::new(cw_engine));
+            let settings = Arc::new(Mutex::new(settings));
 
             let state = AppState {
-                settings: Arc::new(Mutex::new(settings)),
+                settings: Arc::clone(&settings),
                 audio_engine: Arc::clone(&audio_engine),
                 midi_handler: Arc::clone(&midi_handler),
                 cw_engine: Arc::clone(&cw_engine),
@@ -468,9 +1080,13 @@ fn main() {
 
             app.manage(state);
 
+            // Register the push-to-talk hotkey if one is configured
+            register_ptt_hotkey(app.handle(), ptt_hotkey.as_deref());
+
             // Start the MIDI event processing loop
             start_midi_event_loop(
                 app.handle().clone(),
+                settings,
                 midi_handler,
                 audio_engine,
                 cw_engine,
@@ -495,7 +1111,20 @@ fn main() {
             connect_midi_device,
             list_audio_devices,
             get_mic_level,
+            get_mic_gate_level,
             get_output_level,
+            get_meters,
+            start_rx_decoder,
+            stop_rx_decoder,
+            start_tone_keying,
+            stop_tone_keying,
+            get_gain_reduction,
+            get_bleed_status,
+            get_audio_stats,
+            get_stream_configs,
+            set_latency_measurement,
+            get_latency_report,
+            run_loopback_latency_test,
             list_input_devices,
             start_audio,
             start_audio_with_devices,
@@ -504,15 +1133,21 @@ fn main() {
             set_mic_volume,
             key_down,
             key_up,
+            paddle,
+            set_ptt,
             check_linux_virtual_audio,
             setup_linux_virtual_audio,
             mark_linux_audio_setup_complete,
             is_linux_audio_setup_completed,
             start_test_recording,
             stop_test_recording,
+            export_test_recording,
             play_test_recording,
             stop_test_playback,
             get_test_recording_state,
+            start_session_recording,
+            stop_session_recording,
+            get_session_recording_state,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");